@@ -576,6 +576,9 @@ impl Shuttle {
             service_name: service_name.to_string(),
             resources: Default::default(),
             secrets,
+            tags: Default::default(),
+            metadata: Default::default(),
+            features: Default::default(),
         });
 
         trace!("loading service");
@@ -1,11 +1,13 @@
 use std::{
+    borrow::Cow,
     io::Write,
     slice::IterMut,
     sync::{Arc, Mutex},
 };
 
 use chrono::{DateTime, NaiveDateTime, Utc};
-use http::{HeaderMap, Method, Request, Response, StatusCode, Uri, Version};
+use http::header::AsHeaderName;
+use http::{HeaderMap, HeaderValue, Method, Request, Response, StatusCode, Uri, Version};
 use rmps::Serializer;
 use serde::{Deserialize, Serialize};
 use tracing::Subscriber;
@@ -42,6 +44,40 @@ impl From<http::request::Parts> for RequestWrapper {
 }
 
 impl RequestWrapper {
+    /// The method of the request
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    /// The path of the request, without the query
+    pub fn path(&self) -> &str {
+        self.uri.path()
+    }
+
+    /// The query of the request, if it has one
+    pub fn query(&self) -> Option<&str> {
+        self.uri.query()
+    }
+
+    /// The decoded `name=value` pairs of the query, which are only parsed as they are read
+    pub fn query_params(&self) -> QueryParams<'_> {
+        QueryParams {
+            pairs: self.query().unwrap_or_default().split('&'),
+        }
+    }
+
+    /// Get the decoded value of the first `name` query parameter
+    pub fn query_param(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.query_params()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Get the first value of the `name` header
+    pub fn header<K: AsHeaderName>(&self, name: K) -> Option<&HeaderValue> {
+        self.headers.get(name)
+    }
+
     /// Serialize a RequestWrapper to the Rust MessagePack data format
     pub fn into_rmp(self) -> Result<Vec<u8>, rmps::encode::Error> {
         let mut buf = Vec::new();
@@ -66,6 +102,55 @@ impl RequestWrapper {
     }
 }
 
+/// Iterator over the query parameters of a request, see [RequestWrapper::query_params]
+pub struct QueryParams<'a> {
+    pairs: std::str::Split<'a, char>,
+}
+
+impl<'a> Iterator for QueryParams<'a> {
+    type Item = (Cow<'a, str>, Cow<'a, str>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pair = self.pairs.find(|pair| !pair.is_empty())?;
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+
+        Some((decode_query_component(key), decode_query_component(value)))
+    }
+}
+
+/// Decode `+` and `%XX` escapes of a query component, keeping invalid escapes as they are.
+/// Only components which contain escapes are copied.
+fn decode_query_component(component: &str) -> Cow<'_, str> {
+    if !component.contains(['+', '%']) {
+        return Cow::Borrowed(component);
+    }
+
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let escaped = (bytes[index] == b'%')
+            .then(|| bytes.get(index + 1..index + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[index], escaped) {
+            (_, Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+                continue;
+            }
+            (b'+', None) => decoded.push(b' '),
+            (byte, None) => decoded.push(byte),
+        }
+        index += 1;
+    }
+
+    Cow::Owned(String::from_utf8_lossy(&decoded).into_owned())
+}
+
 // todo: add http extensions field
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ResponseWrapper {
@@ -90,6 +175,16 @@ impl From<http::response::Parts> for ResponseWrapper {
 }
 
 impl ResponseWrapper {
+    /// The status of the response
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Get the first value of the `name` header
+    pub fn header<K: AsHeaderName>(&self, name: K) -> Option<&HeaderValue> {
+        self.headers.get(name)
+    }
+
     /// Serialize a ResponseWrapper into the Rust MessagePack data format
     pub fn into_rmp(self) -> Result<Vec<u8>, rmps::encode::Error> {
         let mut buf = Vec::new();
@@ -152,6 +247,60 @@ impl Log {
 
         buf
     }
+
+    /// Like [Bytesable::from_bytes], but keeps at most `max_size` bytes of each variable length
+    /// part of the log so that a giant log cannot exhaust memory. The rest of an oversized part is
+    /// skipped, so the next log can still be read. Truncated fields are replaced by a JSON object
+    /// with the start of the fields as its message and `"truncated": true`.
+    ///
+    /// Returns the log and whether any part of it was truncated.
+    pub fn from_bytes_capped<I: Iterator<Item = u8>>(
+        iter: &mut I,
+        max_size: usize,
+    ) -> Option<(Self, bool)> {
+        let level = iter.get()?;
+        let timestamp = iter.get()?;
+        let (file, file_truncated) = read_capped(iter, max_size)?;
+        let line = iter.get()?;
+        let (target, target_truncated) = read_capped(iter, max_size)?;
+        let (mut fields, fields_truncated) = read_capped(iter, max_size)?;
+
+        if fields_truncated {
+            fields = serde_json::to_vec(&serde_json::json!({
+                "message": String::from_utf8_lossy(&fields),
+                "truncated": true,
+            }))
+            .expect("json values should serialize");
+        }
+
+        let log = Self {
+            level,
+            timestamp,
+            file: String::from_utf8_lossy(&file).into_owned(),
+            line,
+            target: String::from_utf8_lossy(&target).into_owned(),
+            fields,
+        };
+
+        Some((log, file_truncated || target_truncated || fields_truncated))
+    }
+}
+
+/// Read a length prefixed byte string, keeping at most `max_size` bytes of it and skipping the
+/// rest. Returns the kept bytes and whether any were skipped.
+fn read_capped<I: Iterator<Item = u8>>(iter: &mut I, max_size: usize) -> Option<(Vec<u8>, bool)> {
+    let length: u64 = iter.get()?;
+    let kept = length.min(max_size as u64);
+
+    let mut vec = vec![0; kept as usize];
+    vec.iter_mut().try_fill_with(iter)?;
+
+    let skipped = length - kept;
+    if skipped > 0 {
+        iter.nth((skipped - 1) as usize)?;
+    }
+
+    Some((vec, skipped > 0))
 }
 
 /// Like [slice::fill_with] but allows unwrapping of [Option]s
@@ -373,7 +522,6 @@ mod tests {
 
     use super::*;
     use chrono::SubsecRound;
-    use http::HeaderValue;
     use hyper::body::Body;
     use tracing_subscriber::prelude::*;
 
@@ -404,6 +552,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn request_roundtrip_methods() {
+        for method in [
+            Method::GET,
+            Method::HEAD,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+            Method::OPTIONS,
+            Method::TRACE,
+            Method::CONNECT,
+            Method::from_bytes(b"PURGE").unwrap(),
+        ] {
+            let (parts, _) = Request::builder()
+                .method(method.clone())
+                .uri("/hello")
+                .body(())
+                .unwrap()
+                .into_parts();
+            let rmp = RequestWrapper::from(parts).into_rmp().unwrap();
+
+            let back: RequestWrapper = rmps::from_slice(&rmp).unwrap();
+            assert_eq!(back.method, method);
+
+            let request = back.into_request_builder().body(()).unwrap();
+            assert_eq!(request.method(), method);
+        }
+    }
+
     #[test]
     fn response_roundtrip() {
         let response: Response<Body> = Response::builder()
@@ -426,6 +604,107 @@ mod tests {
         assert_eq!(back.version, Version::HTTP_11);
     }
 
+    #[test]
+    fn accessors() {
+        let request: Request<Body> = Request::builder()
+            .method(Method::POST)
+            .header("test", HeaderValue::from_static("request"))
+            .uri("https://axum-wasm.example/hello?name=world")
+            .body(Body::empty())
+            .unwrap();
+
+        let (parts, _) = request.into_parts();
+        let request = RequestWrapper::from(parts);
+
+        assert_eq!(request.method(), Method::POST);
+        assert_eq!(request.path(), "/hello");
+        assert_eq!(request.query(), Some("name=world"));
+        assert_eq!(request.header("test").unwrap(), "request");
+        assert!(request.header("missing").is_none());
+        assert_eq!(request.query_param("name").unwrap(), "world");
+        assert!(request.query_param("missing").is_none());
+
+        let response: Response<Body> = Response::builder()
+            .header("test", HeaderValue::from_static("response"))
+            .status(StatusCode::CREATED)
+            .body(Body::empty())
+            .unwrap();
+
+        let (parts, _) = response.into_parts();
+        let response = ResponseWrapper::from(parts);
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.header("test").unwrap(), "response");
+    }
+
+    #[test]
+    fn query_params() {
+        let request = RequestWrapper::from(
+            Request::get("/search?q=hello+world&tag=a%26b&&empty=&flag&tag=second&bad=%zz")
+                .body(())
+                .unwrap()
+                .into_parts()
+                .0,
+        );
+
+        let params: Vec<_> = request.query_params().collect();
+        assert_eq!(
+            params,
+            vec![
+                ("q".into(), "hello world".into()),
+                ("tag".into(), "a&b".into()),
+                ("empty".into(), "".into()),
+                ("flag".into(), "".into()),
+                ("tag".into(), "second".into()),
+                ("bad".into(), "%zz".into()),
+            ]
+        );
+
+        // Only the first value of a repeated parameter is returned
+        assert_eq!(request.query_param("tag").unwrap(), "a&b");
+
+        // Components without escapes are not copied
+        assert!(matches!(
+            request.query_param("flag"),
+            Some(Cow::Borrowed(""))
+        ));
+
+        let request = RequestWrapper::from(Request::get("/").body(()).unwrap().into_parts().0);
+        assert_eq!(request.query_params().count(), 0);
+    }
+
+    #[test]
+    fn log_capped() {
+        let log = |fields: &[u8]| Log {
+            level: Level::Info,
+            timestamp: Utc::now().trunc_subsecs(3),
+            file: "main.rs".to_string(),
+            line: 5,
+            target: "crate::main".to_string(),
+            fields: fields.to_vec(),
+        };
+
+        let small = log(br#"{"message":"hi"}"#);
+        let big = log(&[b'a'; 1024]);
+        let next = log(br#"{"message":"next"}"#);
+
+        let mut bytes = small.clone().into_bytes();
+        bytes.extend(big.into_bytes());
+        bytes.extend(next.clone().into_bytes());
+        let mut iter = bytes.into_iter();
+
+        assert_eq!(Log::from_bytes_capped(&mut iter, 64), Some((small, false)));
+
+        let (truncated, was_truncated) = Log::from_bytes_capped(&mut iter, 64).unwrap();
+        let fields: serde_json::Value = serde_json::from_slice(&truncated.fields).unwrap();
+        assert!(was_truncated);
+        assert_eq!(fields["message"], "a".repeat(64));
+        assert_eq!(fields["truncated"], true);
+
+        assert_eq!(Log::from_bytes_capped(&mut iter, 64), Some((next, false)));
+        assert_eq!(Log::from_bytes_capped(&mut iter, 64), None);
+    }
+
     #[test]
     fn log_roundtrip() {
         let log = Log {
@@ -305,6 +305,9 @@ async fn load(
         service_name: service_name.clone(),
         resources,
         secrets,
+        tags: Default::default(),
+        metadata: Default::default(),
+        features: Default::default(),
     });
 
     if let Some(claim) = claim {
@@ -16,6 +16,21 @@ pub struct LoadRequest {
         ::prost::alloc::string::String,
         ::prost::alloc::string::String,
     >,
+    /// Tags to label the logs of this deployment with, like its project or environment
+    #[prost(map = "string, string", tag = "30")]
+    pub tags: ::std::collections::HashMap<
+        ::prost::alloc::string::String,
+        ::prost::alloc::string::String,
+    >,
+    /// Free-form metadata to add to the logs of this deployment, like a ticket id or git sha
+    #[prost(map = "string, string", tag = "40")]
+    pub metadata: ::std::collections::HashMap<
+        ::prost::alloc::string::String,
+        ::prost::alloc::string::String,
+    >,
+    /// Host middleware to turn on ("cors") or off ("-cors") for this deployment
+    #[prost(string, repeated, tag = "50")]
+    pub features: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -53,6 +68,28 @@ pub struct StopResponse {
     /// Was the stop successful
     #[prost(bool, tag = "1")]
     pub success: bool,
+    /// Summary of the service's run, if it was running
+    #[prost(message, optional, tag = "2")]
+    pub report: ::core::option::Option<ShutdownReport>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ShutdownReport {
+    /// Number of requests handled while the service was running
+    #[prost(uint64, tag = "1")]
+    pub total_requests: u64,
+    /// Number of requests which were in flight when stopping and finished before the drain timeout
+    #[prost(uint64, tag = "2")]
+    pub drained_requests: u64,
+    /// Number of requests which were still in flight when the drain timeout ran out
+    #[prost(uint64, tag = "3")]
+    pub dropped_requests: u64,
+    /// Most requests which were handled at the same time
+    #[prost(uint64, tag = "4")]
+    pub peak_in_flight_requests: u64,
+    /// Seconds the service was running for
+    #[prost(uint64, tag = "5")]
+    pub uptime_secs: u64,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -86,6 +123,106 @@ pub struct LogItem {
     #[prost(bytes = "vec", tag = "8")]
     pub fields: ::prost::alloc::vec::Vec<u8>,
 }
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StatusRequest {}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StatusResponse {
+    /// Number of requests currently being handled
+    #[prost(uint64, tag = "1")]
+    pub in_flight_requests: u64,
+    /// Number of requests handled since the service was started
+    #[prost(uint64, tag = "2")]
+    pub total_requests: u64,
+    /// Seconds since the service was started
+    #[prost(uint64, tag = "3")]
+    pub uptime_secs: u64,
+    /// Name of the loaded service
+    #[prost(string, tag = "4")]
+    pub service_name: ::prost::alloc::string::String,
+    /// Number of logs which could not be delivered since the service was loaded
+    #[prost(uint64, tag = "5")]
+    pub dropped_logs: u64,
+    /// Number of threads in the guest thread pool, if the service uses one
+    #[prost(uint64, tag = "6")]
+    pub guest_threads: u64,
+    /// Number of requests rejected to relieve memory pressure since the service was started
+    #[prost(uint64, tag = "7")]
+    pub shed_requests: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct VersionRequest {}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct VersionResponse {
+    /// Version of the loaded service, or "unknown" if it did not embed one
+    #[prost(string, tag = "1")]
+    pub version: ::prost::alloc::string::String,
+    /// Commit the loaded service was built from, or "unknown" if it did not embed one
+    #[prost(string, tag = "2")]
+    pub commit: ::prost::alloc::string::String,
+    /// Lowercase hex SHA-256 of the loaded module's bytes, like `sha256sum` prints it
+    #[prost(string, tag = "3")]
+    pub module_sha256: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SetMaintenanceRequest {
+    /// Whether maintenance mode should be active
+    #[prost(bool, tag = "1")]
+    pub enabled: bool,
+    /// Status to respond with, or 503 when not set
+    #[prost(uint32, optional, tag = "2")]
+    pub status: ::core::option::Option<u32>,
+    /// Body to respond with
+    #[prost(bytes = "vec", tag = "3")]
+    pub body: ::prost::alloc::vec::Vec<u8>,
+    /// Content type of the body, or plain text when not set
+    #[prost(string, optional, tag = "4")]
+    pub content_type: ::core::option::Option<::prost::alloc::string::String>,
+    /// Seconds clients should wait before retrying, sent as the Retry-After header
+    #[prost(uint64, optional, tag = "5")]
+    pub retry_after_secs: ::core::option::Option<u64>,
+    /// Paths which are still passed to the service, such as health checks
+    #[prost(string, repeated, tag = "6")]
+    pub exempt_paths: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SetMaintenanceResponse {
+    /// Whether maintenance mode is now active
+    #[prost(bool, tag = "1")]
+    pub enabled: bool,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AbortRequestRequest {
+    /// Id of the request to abort, as in the request_id field of its logs
+    #[prost(uint64, tag = "1")]
+    pub request_id: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AbortRequestResponse {
+    /// Whether the request was still being handled and is now aborted
+    #[prost(bool, tag = "1")]
+    pub aborted: bool,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetConfigRequest {}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetConfigResponse {
+    /// The runtime config in force, as JSON. Secrets of the service are never part of it.
+    #[prost(string, tag = "1")]
+    pub config: ::prost::alloc::string::String,
+    /// Names of the host middleware which is turned on
+    #[prost(string, repeated, tag = "2")]
+    pub middleware: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
 #[repr(i32)]
 pub enum StopReason {
@@ -322,6 +459,102 @@ pub mod runtime_client {
             );
             self.inner.server_streaming(request.into_request(), path, codec).await
         }
+        /// Get a quick snapshot of the service health
+        pub async fn status(
+            &mut self,
+            request: impl tonic::IntoRequest<super::StatusRequest>,
+        ) -> Result<tonic::Response<super::StatusResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/runtime.Runtime/Status");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// Get the build metadata of the loaded service
+        pub async fn version(
+            &mut self,
+            request: impl tonic::IntoRequest<super::VersionRequest>,
+        ) -> Result<tonic::Response<super::VersionResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/runtime.Runtime/Version");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// Answer every request with a fixed response while maintenance is enabled
+        pub async fn set_maintenance(
+            &mut self,
+            request: impl tonic::IntoRequest<super::SetMaintenanceRequest>,
+        ) -> Result<tonic::Response<super::SetMaintenanceResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/runtime.Runtime/SetMaintenance",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// Abort a single request the service is handling, making it fail with an error response
+        pub async fn abort_request(
+            &mut self,
+            request: impl tonic::IntoRequest<super::AbortRequestRequest>,
+        ) -> Result<tonic::Response<super::AbortRequestResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/runtime.Runtime/AbortRequest",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// Get the configuration the loaded service runs with
+        pub async fn get_config(
+            &mut self,
+            request: impl tonic::IntoRequest<super::GetConfigRequest>,
+        ) -> Result<tonic::Response<super::GetConfigResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/runtime.Runtime/GetConfig",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
     }
 }
 /// Generated server implementations.
@@ -368,6 +601,31 @@ pub mod runtime_server {
             &self,
             request: tonic::Request<super::SubscribeLogsRequest>,
         ) -> Result<tonic::Response<Self::SubscribeLogsStream>, tonic::Status>;
+        /// Get a quick snapshot of the service health
+        async fn status(
+            &self,
+            request: tonic::Request<super::StatusRequest>,
+        ) -> Result<tonic::Response<super::StatusResponse>, tonic::Status>;
+        /// Get the build metadata of the loaded service
+        async fn version(
+            &self,
+            request: tonic::Request<super::VersionRequest>,
+        ) -> Result<tonic::Response<super::VersionResponse>, tonic::Status>;
+        /// Answer every request with a fixed response while maintenance is enabled
+        async fn set_maintenance(
+            &self,
+            request: tonic::Request<super::SetMaintenanceRequest>,
+        ) -> Result<tonic::Response<super::SetMaintenanceResponse>, tonic::Status>;
+        /// Abort a single request the service is handling, making it fail with an error response
+        async fn abort_request(
+            &self,
+            request: tonic::Request<super::AbortRequestRequest>,
+        ) -> Result<tonic::Response<super::AbortRequestResponse>, tonic::Status>;
+        /// Get the configuration the loaded service runs with
+        async fn get_config(
+            &self,
+            request: tonic::Request<super::GetConfigRequest>,
+        ) -> Result<tonic::Response<super::GetConfigResponse>, tonic::Status>;
     }
     #[derive(Debug)]
     pub struct RuntimeServer<T: Runtime> {
@@ -618,6 +876,186 @@ pub mod runtime_server {
                     };
                     Box::pin(fut)
                 }
+                "/runtime.Runtime/Status" => {
+                    #[allow(non_camel_case_types)]
+                    struct StatusSvc<T: Runtime>(pub Arc<T>);
+                    impl<T: Runtime> tonic::server::UnaryService<super::StatusRequest>
+                    for StatusSvc<T> {
+                        type Response = super::StatusResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::StatusRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).status(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = StatusSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/runtime.Runtime/Version" => {
+                    #[allow(non_camel_case_types)]
+                    struct VersionSvc<T: Runtime>(pub Arc<T>);
+                    impl<T: Runtime> tonic::server::UnaryService<super::VersionRequest>
+                    for VersionSvc<T> {
+                        type Response = super::VersionResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::VersionRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).version(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = VersionSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/runtime.Runtime/SetMaintenance" => {
+                    #[allow(non_camel_case_types)]
+                    struct SetMaintenanceSvc<T: Runtime>(pub Arc<T>);
+                    impl<T: Runtime> tonic::server::UnaryService<super::SetMaintenanceRequest>
+                    for SetMaintenanceSvc<T> {
+                        type Response = super::SetMaintenanceResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::SetMaintenanceRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).set_maintenance(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = SetMaintenanceSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/runtime.Runtime/AbortRequest" => {
+                    #[allow(non_camel_case_types)]
+                    struct AbortRequestSvc<T: Runtime>(pub Arc<T>);
+                    impl<T: Runtime> tonic::server::UnaryService<super::AbortRequestRequest>
+                    for AbortRequestSvc<T> {
+                        type Response = super::AbortRequestResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::AbortRequestRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).abort_request(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = AbortRequestSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/runtime.Runtime/GetConfig" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetConfigSvc<T: Runtime>(pub Arc<T>);
+                    impl<T: Runtime> tonic::server::UnaryService<super::GetConfigRequest>
+                    for GetConfigSvc<T> {
+                        type Response = super::GetConfigResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::GetConfigRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).get_config(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = GetConfigSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 _ => {
                     Box::pin(async move {
                         Ok(
@@ -0,0 +1,148 @@
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use hyper::{Body, Request};
+use shuttle_runtime::bench::{self, BenchRouter};
+use shuttle_runtime::RouterBuilder;
+
+const AXUM_WASM: &str =
+    "tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm";
+
+/// Compares chunk sizes for reading a large response body from the guest, which is where the
+/// default `RouterBuilder::response_chunk_size` comes from
+fn response_chunk_size(c: &mut Criterion) {
+    const BODY_SIZE: usize = 8 * 1024 * 1024;
+
+    let mut group = c.benchmark_group("response_chunk_size");
+    group.throughput(Throughput::Bytes(BODY_SIZE as u64));
+    group.sample_size(20);
+
+    for chunk_size in [1024, 4 * 1024, 16 * 1024, 64 * 1024, 256 * 1024] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(chunk_size),
+            &chunk_size,
+            |b, &chunk_size| {
+                b.iter(|| {
+                    let (mut writer, reader) = UnixStream::pair().unwrap();
+                    let guest = std::thread::spawn(move || {
+                        writer.write_all(&vec![b'a'; BODY_SIZE]).unwrap();
+                    });
+
+                    let read = bench::read_response_body(reader, chunk_size).unwrap();
+                    guest.join().unwrap();
+
+                    assert_eq!(read, BODY_SIZE);
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+/// Compares building the WASI context of every request from scratch with building it from a
+/// template made once
+fn wasi_context(c: &mut Criterion) {
+    let mut group = c.benchmark_group("wasi_context");
+
+    group.bench_function("from_scratch", |b| {
+        b.iter(|| bench::build_wasi_from_scratch().unwrap())
+    });
+
+    let contexts = bench::WasiContexts::new();
+    group.bench_function("from_template", |b| b.iter(|| contexts.build().unwrap()));
+
+    group.finish();
+}
+
+/// Compares making a fresh instance of the `axum.wasm` fixture for every request with taking
+/// instances from a warm pool, with `BENCH_CONCURRENCY` requests in flight at a time (16 by
+/// default). Criterion reports the throughput in requests per second, and the p50 and p99
+/// latency of the requests are printed after each run.
+fn instances(c: &mut Criterion) {
+    Command::new("cargo")
+        .args(["build", "--target", "wasm32-wasi"])
+        .current_dir("tests/resources/axum-wasm-expanded")
+        .status()
+        .unwrap();
+
+    let concurrency: usize = std::env::var("BENCH_CONCURRENCY")
+        .ok()
+        .and_then(|concurrency| concurrency.parse().ok())
+        .unwrap_or(16);
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("instances");
+    group.throughput(Throughput::Elements(concurrency as u64));
+
+    for (name, warm_pool_size) in [("per_request", 0), ("pooled", concurrency * 2)] {
+        let router = {
+            let _runtime = runtime.enter();
+            let builder = RouterBuilder::new()
+                .unwrap()
+                .src(AXUM_WASM)
+                .warm_pool_size(warm_pool_size);
+
+            Arc::new(BenchRouter::new(builder).unwrap())
+        };
+        let latencies = Arc::new(Mutex::new(Vec::new()));
+
+        group.bench_function(name, |b| {
+            b.iter_custom(|iters| {
+                runtime.block_on(async {
+                    let start = Instant::now();
+
+                    for _ in 0..iters {
+                        let requests: Vec<_> = (0..concurrency)
+                            .map(|_| {
+                                let router = router.clone();
+                                let latencies = latencies.clone();
+
+                                tokio::spawn(async move {
+                                    let request = Request::get("https://axum-wasm.example/hello")
+                                        .body(Body::empty())
+                                        .unwrap();
+
+                                    let request_start = Instant::now();
+                                    router.handle(request).await.unwrap();
+                                    latencies.lock().unwrap().push(request_start.elapsed());
+                                })
+                            })
+                            .collect();
+
+                        for request in requests {
+                            request.await.unwrap();
+                        }
+                    }
+
+                    start.elapsed()
+                })
+            })
+        });
+
+        let mut latencies = latencies.lock().unwrap();
+        latencies.sort();
+        println!(
+            "instances/{name}: p50 {:?}, p99 {:?} at a concurrency of {concurrency}",
+            percentile(&latencies, 50),
+            percentile(&latencies, 99),
+        );
+    }
+
+    group.finish();
+}
+
+fn percentile(latencies: &[Duration], percentile: usize) -> Duration {
+    latencies
+        .get((latencies.len() * percentile / 100).min(latencies.len().saturating_sub(1)))
+        .copied()
+        .unwrap_or_default()
+}
+
+criterion_group!(benches, response_chunk_size, wasi_context, instances);
+criterion_main!(benches);
@@ -25,8 +25,11 @@ use shuttle_proto::{
     runtime::{
         self,
         runtime_server::{Runtime, RuntimeServer},
-        LoadRequest, LoadResponse, LogItem, StartRequest, StartResponse, StopReason, StopRequest,
+        AbortRequestRequest, AbortRequestResponse, GetConfigRequest, GetConfigResponse,
+        LoadRequest, LoadResponse, LogItem, SetMaintenanceRequest, SetMaintenanceResponse,
+        StartRequest, StartResponse, StatusRequest, StatusResponse, StopReason, StopRequest,
         StopResponse, SubscribeLogsRequest, SubscribeStopRequest, SubscribeStopResponse,
+        VersionRequest, VersionResponse,
     },
 };
 use shuttle_service::{Environment, Factory, Service, ServiceName};
@@ -176,6 +179,7 @@ where
             resources,
             secrets,
             service_name,
+            ..
         } = request.into_inner();
         trace!(path, "loading alpha project");
 
@@ -381,11 +385,17 @@ where
                 return Err(Status::internal("failed to stop deployment"));
             }
 
-            Ok(Response::new(StopResponse { success: true }))
+            Ok(Response::new(StopResponse {
+                success: true,
+                report: None,
+            }))
         } else {
             warn!("failed to stop deployment");
 
-            Ok(tonic::Response::new(StopResponse { success: false }))
+            Ok(tonic::Response::new(StopResponse {
+                success: false,
+                report: None,
+            }))
         }
     }
 
@@ -436,4 +446,49 @@ where
             Err(Status::internal("logs have already been subscribed to"))
         }
     }
+
+    async fn status(
+        &self,
+        _request: Request<StatusRequest>,
+    ) -> Result<Response<StatusResponse>, Status> {
+        Err(Status::unimplemented(
+            "status is not supported by the alpha runtime",
+        ))
+    }
+
+    async fn set_maintenance(
+        &self,
+        _request: Request<SetMaintenanceRequest>,
+    ) -> Result<Response<SetMaintenanceResponse>, Status> {
+        Err(Status::unimplemented(
+            "maintenance mode is not supported by the alpha runtime",
+        ))
+    }
+
+    async fn abort_request(
+        &self,
+        _request: Request<AbortRequestRequest>,
+    ) -> Result<Response<AbortRequestResponse>, Status> {
+        Err(Status::unimplemented(
+            "aborting requests is not supported by the alpha runtime",
+        ))
+    }
+
+    async fn get_config(
+        &self,
+        _request: Request<GetConfigRequest>,
+    ) -> Result<Response<GetConfigResponse>, Status> {
+        Err(Status::unimplemented(
+            "getting the config is not supported by the alpha runtime",
+        ))
+    }
+
+    async fn version(
+        &self,
+        _request: Request<VersionRequest>,
+    ) -> Result<Response<VersionResponse>, Status> {
+        Err(Status::unimplemented(
+            "version is not supported by the alpha runtime",
+        ))
+    }
 }
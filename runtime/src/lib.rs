@@ -226,7 +226,14 @@ pub use alpha::{start, Alpha};
 pub use async_trait::async_trait;
 pub use logger::Logger;
 #[cfg(feature = "next")]
-pub use next::{AxumWasm, NextArgs};
+#[doc(hidden)]
+pub use next::bench;
+#[cfg(feature = "next")]
+pub use next::{
+    replay, AxumWasm, BodyLimitOverride, BodyTransformer, CaptureConfig, CorsConfig, ErrorPage,
+    MemoryPressureConfig, NextArgs, ReplayOutcome, RequestContext, ResponseBodyTransform,
+    RouterBuilder, RuntimeConfig,
+};
 pub use provisioner_factory::ProvisionerFactory;
 pub use resource_tracker::{get_resource, ResourceTracker};
 pub use shuttle_common::storage_manager::StorageManager;
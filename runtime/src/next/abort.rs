@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// The requests running in the guest by id, shared between the runtime and every copy of its
+/// router so operators can abort a single stuck request
+#[derive(Default)]
+pub(crate) struct AbortRegistry {
+    requests: Mutex<HashMap<u64, Arc<AtomicBool>>>,
+}
+
+impl AbortRegistry {
+    /// Make the request with `id` abortable until the returned guard is dropped
+    pub(crate) fn register(self: &Arc<Self>, id: u64) -> AbortGuard {
+        let aborted = Arc::new(AtomicBool::new(false));
+
+        self.requests
+            .lock()
+            .expect("abort registry lock should not be poisoned")
+            .insert(id, aborted.clone());
+
+        AbortGuard {
+            registry: self.clone(),
+            id,
+            aborted,
+        }
+    }
+
+    /// Abort the request with `id`. Returns whether it was running.
+    pub(crate) fn abort(&self, id: u64) -> bool {
+        let requests = self
+            .requests
+            .lock()
+            .expect("abort registry lock should not be poisoned");
+
+        match requests.get(&id) {
+            Some(aborted) => {
+                aborted.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A registered request, which cannot be aborted anymore once this is dropped
+pub(crate) struct AbortGuard {
+    registry: Arc<AbortRegistry>,
+    id: u64,
+    aborted: Arc<AtomicBool>,
+}
+
+impl AbortGuard {
+    /// The flag which is set when the request is aborted
+    pub(crate) fn flag(&self) -> Arc<AtomicBool> {
+        self.aborted.clone()
+    }
+
+    pub(crate) fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for AbortGuard {
+    fn drop(&mut self) {
+        self.registry
+            .requests
+            .lock()
+            .expect("abort registry lock should not be poisoned")
+            .remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn abort() {
+        let registry = Arc::new(AbortRegistry::default());
+
+        let first = registry.register(1);
+        let second = registry.register(2);
+
+        assert!(registry.abort(2));
+        assert!(!first.is_aborted());
+        assert!(second.is_aborted());
+        assert!(second.flag().load(Ordering::Relaxed));
+
+        // Finished requests cannot be aborted
+        drop(first);
+        assert!(!registry.abort(1));
+        assert!(!registry.abort(3));
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use hyper::header::{self, HeaderValue};
+use hyper::http::StatusCode;
+use hyper::{Body, Method, Response};
+
+use super::metadata::custom_section;
+
+/// Name of the custom section a guest can advertise the methods of its routes in, as a JSON
+/// object from route to methods like `{"/orders": ["GET", "POST"], "/files/*": ["GET"]}`. A
+/// route ending in `*` matches every path with that prefix, otherwise the path has to match
+/// exactly.
+const ROUTES_SECTION: &str = "shuttle:routes";
+
+// Allowed for paths the guest does not advertise any methods for
+const DEFAULT_ALLOW: &str = "OPTIONS, GET, HEAD, POST, PUT, PATCH, DELETE";
+
+/// The methods a guest advertises for its routes
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct RouteMethods {
+    exact: HashMap<String, Vec<Method>>,
+    /// Prefixes with their methods, longest first so the most specific one matches
+    prefixes: Vec<(String, Vec<Method>)>,
+}
+
+impl RouteMethods {
+    /// Read the route methods from the bytes of a wasm module. Modules without them, or with
+    /// methods which cannot be parsed, advertise no routes.
+    pub(crate) fn from_module(bytes: &[u8]) -> Self {
+        custom_section(bytes, ROUTES_SECTION)
+            .and_then(|section| {
+                serde_json::from_slice::<HashMap<String, Vec<String>>>(section).ok()
+            })
+            .map(Self::new)
+            .unwrap_or_default()
+    }
+
+    fn new(routes: HashMap<String, Vec<String>>) -> Self {
+        let mut exact = HashMap::new();
+        let mut prefixes = Vec::new();
+
+        for (route, methods) in routes {
+            let methods = methods
+                .iter()
+                .filter_map(|method| Method::from_bytes(method.as_bytes()).ok())
+                .collect();
+
+            match route.strip_suffix('*') {
+                Some(prefix) => prefixes.push((prefix.to_string(), methods)),
+                None => {
+                    exact.insert(route, methods);
+                }
+            }
+        }
+
+        prefixes.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()));
+
+        Self { exact, prefixes }
+    }
+
+    /// Get the methods the guest advertises for `path`, if any
+    fn methods_for(&self, path: &str) -> Option<&[Method]> {
+        if let Some(methods) = self.exact.get(path) {
+            return Some(methods);
+        }
+
+        self.prefixes
+            .iter()
+            .find(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .map(|(_, methods)| methods.as_slice())
+    }
+
+    /// Every advertised route with its methods, keeping the `*` of prefix routes
+    pub(crate) fn routes(&self) -> impl Iterator<Item = (String, &[Method])> {
+        let exact = self
+            .exact
+            .iter()
+            .map(|(route, methods)| (route.clone(), methods.as_slice()));
+        let prefixes = self
+            .prefixes
+            .iter()
+            .map(|(prefix, methods)| (format!("{prefix}*"), methods.as_slice()));
+
+        exact.chain(prefixes)
+    }
+
+    /// Answer an `OPTIONS` request for `path` with the methods it allows, or every common
+    /// method when the guest does not advertise any for it
+    pub(crate) fn options_response(&self, path: &str) -> Response<Body> {
+        let allow = match self.methods_for(path) {
+            Some(methods) => {
+                let mut allow = vec![Method::OPTIONS];
+                for method in methods {
+                    if !allow.contains(method) {
+                        allow.push(method.clone());
+                    }
+                    // Every route which answers `GET` answers `HEAD` too
+                    if method == Method::GET && !methods.contains(&Method::HEAD) {
+                        allow.push(Method::HEAD);
+                    }
+                }
+
+                let allow: Vec<_> = allow.iter().map(Method::as_str).collect();
+                HeaderValue::from_str(&allow.join(", "))
+                    .expect("methods should be valid header values")
+            }
+            None => HeaderValue::from_static(DEFAULT_ALLOW),
+        };
+
+        Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header(header::ALLOW, allow)
+            .body(Body::empty())
+            .expect("building an options response should not fail")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allow(routes: &RouteMethods, path: &str) -> String {
+        let response = routes.options_response(path);
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        response.headers()[header::ALLOW]
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn allows_advertised_methods() {
+        let routes = RouteMethods::new(HashMap::from([
+            (
+                "/orders".to_string(),
+                vec!["GET".to_string(), "POST".to_string()],
+            ),
+            ("/files/*".to_string(), vec!["PUT".to_string()]),
+        ]));
+
+        assert_eq!(allow(&routes, "/orders"), "OPTIONS, GET, HEAD, POST");
+        assert_eq!(allow(&routes, "/files/a.txt"), "OPTIONS, PUT");
+        assert_eq!(allow(&routes, "/unknown"), DEFAULT_ALLOW);
+        assert_eq!(allow(&RouteMethods::default(), "/orders"), DEFAULT_ALLOW);
+    }
+
+    #[test]
+    fn route_methods_from_module() {
+        let section = br#"{"/orders": ["DELETE"]}"#;
+
+        let mut contents = vec![ROUTES_SECTION.len() as u8];
+        contents.extend_from_slice(ROUTES_SECTION.as_bytes());
+        contents.extend_from_slice(section);
+
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        module.push(0);
+        module.push(contents.len() as u8);
+        module.extend(contents);
+
+        let routes = RouteMethods::from_module(&module);
+        assert_eq!(routes.methods_for("/orders"), Some(&[Method::DELETE][..]));
+
+        assert_eq!(
+            RouteMethods::from_module(b"\0asm\x01\0\0\0"),
+            RouteMethods::default()
+        );
+    }
+}
@@ -0,0 +1,70 @@
+use std::fmt::Write;
+
+use wasmtime::{Trap, WasmBacktrace};
+
+/// Describe where in the guest `error` happened, with the wasm frames it carries innermost
+/// first. Functions are named when the module has a name section. This is `None` for errors
+/// which did not come from running wasm.
+pub(crate) fn describe(error: &anyhow::Error) -> Option<String> {
+    let backtrace = error.downcast_ref::<WasmBacktrace>()?;
+
+    let mut description = match error.downcast_ref::<Trap>() {
+        Some(trap) => format!("wasm trap: {trap}"),
+        None => "wasm error".to_string(),
+    };
+
+    for (index, frame) in backtrace.frames().iter().enumerate() {
+        let _ = write!(description, "\n  {index:>2}: ");
+
+        if let Some(offset) = frame.module_offset() {
+            let _ = write!(description, "{offset:#x} - ");
+        }
+
+        match frame.func_name() {
+            Some(name) => description.push_str(name),
+            None => {
+                let _ = write!(description, "<wasm function {}>", frame.func_index());
+            }
+        }
+    }
+
+    Some(description)
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmtime::{Engine, Instance, Module, Store};
+
+    use super::*;
+
+    #[test]
+    fn describe_trap() {
+        let engine = Engine::default();
+        let module = Module::new(
+            &engine,
+            r#"(module
+                (func $inner unreachable)
+                (func $outer call $inner)
+                (func call $outer)
+                (export "run" (func 2)))"#,
+        )
+        .unwrap();
+
+        let mut store = Store::new(&engine, ());
+        let instance = Instance::new(&mut store, &module, &[]).unwrap();
+        let run = instance
+            .get_typed_func::<(), ()>(&mut store, "run")
+            .unwrap();
+
+        let error = run.call(&mut store, ()).unwrap_err();
+        let description = describe(&error).unwrap();
+        let lines: Vec<_> = description.lines().collect();
+
+        assert!(lines[0].starts_with("wasm trap: "), "{description}");
+        assert!(lines[1].ends_with(" - inner"), "{description}");
+        assert!(lines[2].ends_with(" - outer"), "{description}");
+        assert!(lines[3].ends_with(" - <wasm function 2>"), "{description}");
+
+        assert!(describe(&anyhow::anyhow!("not wasm")).is_none());
+    }
+}
@@ -0,0 +1,93 @@
+//! Entry points into the internals of the runtime for the benchmarks in `benches/`. These are
+//! not part of the public API and can change at any time.
+
+use std::io::Read;
+
+use hyper::body::Bytes;
+use hyper::{Body, Request};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use wasmtime_wasi::WasiCtxBuilder;
+
+use super::secrets::Secrets;
+use super::{body_chunks, Router, RouterBuilder, WasiTemplate};
+
+/// Read a whole response body in chunks of `chunk_size` bytes, returning how many bytes it had
+pub fn read_response_body<R: Read>(reader: R, chunk_size: usize) -> std::io::Result<usize> {
+    let mut read = 0;
+    for chunk in body_chunks(reader, chunk_size) {
+        read += chunk?.len();
+    }
+
+    Ok(read)
+}
+
+/// Build the WASI context of a request from scratch, like requests did before they had a template
+pub fn build_wasi_from_scratch() -> anyhow::Result<()> {
+    WasiCtxBuilder::new()
+        .inherit_stdio()
+        .inherit_args()?
+        .build();
+
+    Ok(())
+}
+
+/// The template requests build their WASI context from
+pub struct WasiContexts(WasiTemplate);
+
+impl WasiContexts {
+    pub fn new() -> Self {
+        Self(WasiTemplate::from_env(&Secrets::default()))
+    }
+
+    /// Build the WASI context of one request
+    pub fn build(&self) -> anyhow::Result<()> {
+        self.0.build()?;
+
+        Ok(())
+    }
+}
+
+impl Default for WasiContexts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A router handling requests like the server does, without a listener in front of it
+pub struct BenchRouter {
+    router: Router,
+    warm_pool_refill: Option<JoinHandle<()>>,
+}
+
+impl BenchRouter {
+    /// Build the router of `builder` and start filling its warm pool, if it has one. This has to
+    /// be called from within a tokio runtime.
+    pub fn new(builder: RouterBuilder) -> anyhow::Result<Self> {
+        let router = builder.build()?;
+        let warm_pool_refill = router.refill_warm_pool();
+
+        Ok(Self {
+            router,
+            warm_pool_refill,
+        })
+    }
+
+    /// Handle `request` and read its whole response body. The logs of the request are dropped.
+    pub async fn handle(&self, request: Request<Body>) -> anyhow::Result<Bytes> {
+        let (logs_tx, mut logs_rx) = mpsc::channel(64);
+        tokio::spawn(async move { while logs_rx.recv().await.is_some() {} });
+
+        let response = self.router.handle_request(request, logs_tx).await?;
+
+        Ok(hyper::body::to_bytes(response.into_body()).await?)
+    }
+}
+
+impl Drop for BenchRouter {
+    fn drop(&mut self) {
+        if let Some(warm_pool_refill) = &self.warm_pool_refill {
+            warm_pool_refill.abort();
+        }
+    }
+}
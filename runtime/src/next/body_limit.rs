@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hyper::http::request::Parts;
+use ring::hmac;
+
+use super::metadata::custom_section;
+
+/// Header trusted callers can use to raise the body limit for a single request
+pub const OVERRIDE_HEADER: &str = "x-max-body-override";
+
+/// Name of the custom section a guest can embed the body limits of its routes in, as a JSON
+/// object from route to limit in bytes like `{"/upload": 104857600, "/files/*": 1048576}`. A
+/// route ending in `*` matches every path with that prefix, otherwise the path has to match
+/// exactly.
+const ROUTE_LIMITS_SECTION: &str = "shuttle:body-limits";
+
+/// Lets trusted callers raise the body limit of a request with a signed header, up to a ceiling.
+///
+/// The header value has the form `<limit>.<expires>.<signature>` where `expires` is a unix
+/// timestamp in seconds and the signature is the hex encoded HMAC-SHA256 of
+/// `<limit>:<expires>:<path>` using the shared secret. Binding the signature to the path stops
+/// a leaked header from being reused for other endpoints, and the expiry stops it from being
+/// replayed after it.
+#[derive(Clone)]
+pub struct BodyLimitOverride {
+    key: hmac::Key,
+    ceiling: u64,
+}
+
+impl BodyLimitOverride {
+    pub fn new(secret: &[u8], ceiling: u64) -> Self {
+        Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret),
+            ceiling,
+        }
+    }
+
+    /// Get the body limit a request is allowed if it has a validly signed override header which
+    /// has not expired. The returned limit never exceeds the ceiling.
+    pub fn limit_for(&self, parts: &Parts) -> Option<u64> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+
+        self.limit_at(parts, now)
+    }
+
+    fn limit_at(&self, parts: &Parts, now: u64) -> Option<u64> {
+        let value = parts.headers.get(OVERRIDE_HEADER)?.to_str().ok()?;
+        let mut fields = value.splitn(3, '.');
+        let (limit, expires, signature) = (fields.next()?, fields.next()?, fields.next()?);
+        let signature = decode_hex(signature)?;
+
+        let message = format!("{limit}:{expires}:{}", parts.uri.path());
+        hmac::verify(&self.key, message.as_bytes(), &signature).ok()?;
+
+        let expires: u64 = expires.parse().ok()?;
+        if now >= expires {
+            return None;
+        }
+
+        let limit: u64 = limit.parse().ok()?;
+
+        Some(limit.min(self.ceiling))
+    }
+}
+
+/// The body limits a guest gives its routes, which replace the default limit for requests to
+/// those routes
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct RouteBodyLimits {
+    exact: HashMap<String, u64>,
+    /// Prefixes with their limits, longest first so the most specific one matches
+    prefixes: Vec<(String, u64)>,
+}
+
+impl RouteBodyLimits {
+    /// Read the route limits from the bytes of a wasm module. Modules without them, or with
+    /// limits which cannot be parsed, have no route limits.
+    pub(crate) fn from_module(bytes: &[u8]) -> Self {
+        custom_section(bytes, ROUTE_LIMITS_SECTION)
+            .and_then(|section| serde_json::from_slice::<HashMap<String, u64>>(section).ok())
+            .map(Self::new)
+            .unwrap_or_default()
+    }
+
+    fn new(limits: HashMap<String, u64>) -> Self {
+        let mut exact = HashMap::new();
+        let mut prefixes = Vec::new();
+
+        for (route, limit) in limits {
+            match route.strip_suffix('*') {
+                Some(prefix) => prefixes.push((prefix.to_string(), limit)),
+                None => {
+                    exact.insert(route, limit);
+                }
+            }
+        }
+
+        prefixes.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()));
+
+        Self { exact, prefixes }
+    }
+
+    /// Get the body limit the guest gives `path`, if any
+    pub(crate) fn limit_for(&self, path: &str) -> Option<u64> {
+        if let Some(limit) = self.exact.get(path) {
+            return Some(*limit);
+        }
+
+        self.prefixes
+            .iter()
+            .find(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .map(|(_, limit)| *limit)
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Request;
+
+    use super::*;
+
+    fn sign(secret: &[u8], message: &str) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+
+        hmac::sign(&key, message.as_bytes())
+            .as_ref()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+
+    fn parts(header: &str) -> Parts {
+        Request::post("/upload")
+            .header(OVERRIDE_HEADER, header)
+            .body(())
+            .unwrap()
+            .into_parts()
+            .0
+    }
+
+    #[test]
+    fn signed_override() {
+        let limit_override = BodyLimitOverride::new(b"secret", 1024 * 1024);
+
+        let header = format!("131072.2000.{}", sign(b"secret", "131072:2000:/upload"));
+        assert_eq!(limit_override.limit_at(&parts(&header), 1000), Some(131072));
+
+        // The ceiling is always enforced
+        let header = format!("99999999.2000.{}", sign(b"secret", "99999999:2000:/upload"));
+        assert_eq!(
+            limit_override.limit_at(&parts(&header), 1000),
+            Some(1024 * 1024)
+        );
+    }
+
+    #[test]
+    fn expired_override() {
+        let limit_override = BodyLimitOverride::new(b"secret", 1024 * 1024);
+
+        let header = format!("131072.2000.{}", sign(b"secret", "131072:2000:/upload"));
+        assert_eq!(limit_override.limit_at(&parts(&header), 2000), None);
+        assert_eq!(limit_override.limit_for(&parts(&header)), None);
+
+        // The expiry is signed, so it cannot be pushed back
+        let header = format!(
+            "131072.9999999999.{}",
+            sign(b"secret", "131072:2000:/upload")
+        );
+        assert_eq!(limit_override.limit_at(&parts(&header), 1000), None);
+    }
+
+    #[test]
+    fn invalid_override() {
+        let limit_override = BodyLimitOverride::new(b"secret", 1024 * 1024);
+
+        let header = format!("131072.2000.{}", sign(b"wrong", "131072:2000:/upload"));
+        assert_eq!(limit_override.limit_at(&parts(&header), 1000), None);
+
+        let header = format!("131072.2000.{}", sign(b"secret", "131072:2000:/other"));
+        assert_eq!(limit_override.limit_at(&parts(&header), 1000), None);
+
+        // Headers without an expiry are not accepted anymore
+        let header = format!("131072.{}", sign(b"secret", "131072:/upload"));
+        assert_eq!(limit_override.limit_at(&parts(&header), 1000), None);
+
+        assert_eq!(limit_override.limit_at(&parts("131072"), 1000), None);
+        assert_eq!(
+            limit_override.limit_at(&parts("131072.2000.zz"), 1000),
+            None
+        );
+    }
+
+    #[test]
+    fn route_limits() {
+        let limits = RouteBodyLimits::new(HashMap::from([
+            ("/login".to_string(), 4096),
+            ("/files/*".to_string(), 1024 * 1024),
+            ("/files/videos/*".to_string(), 100 * 1024 * 1024),
+        ]));
+
+        assert_eq!(limits.limit_for("/login"), Some(4096));
+        assert_eq!(limits.limit_for("/login/sso"), None);
+        assert_eq!(limits.limit_for("/files/a.txt"), Some(1024 * 1024));
+        assert_eq!(
+            limits.limit_for("/files/videos/a.mp4"),
+            Some(100 * 1024 * 1024)
+        );
+        assert_eq!(limits.limit_for("/"), None);
+    }
+
+    #[test]
+    fn route_limits_from_module() {
+        let section = br#"{"/upload": 104857600}"#;
+
+        let mut contents = vec![ROUTE_LIMITS_SECTION.len() as u8];
+        contents.extend_from_slice(ROUTE_LIMITS_SECTION.as_bytes());
+        contents.extend_from_slice(section);
+
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        module.push(0);
+        module.push(contents.len() as u8);
+        module.extend(contents);
+
+        let limits = RouteBodyLimits::from_module(&module);
+        assert_eq!(limits.limit_for("/upload"), Some(104857600));
+
+        assert_eq!(
+            RouteBodyLimits::from_module(b"\0asm\x01\0\0\0"),
+            RouteBodyLimits::default()
+        );
+    }
+}
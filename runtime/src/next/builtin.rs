@@ -0,0 +1,123 @@
+use std::time::SystemTime;
+
+use hyper::body::Bytes;
+use hyper::header::{self, HeaderValue};
+use hyper::http::StatusCode;
+use hyper::{Body, Method, Request, Response};
+
+use super::conditional::Validators;
+
+/// A fully transparent 1x1 icon
+const DEFAULT_FAVICON: &[u8] = &[
+    0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01, 0x01, 0x00, 0x00, 0x01, 0x00, 0x20, 0x00, 0x30, 0x00,
+    0x00, 0x00, 0x16, 0x00, 0x00, 0x00, 0x28, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00,
+    0x00, 0x00, 0x01, 0x00, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Allow all crawlers everywhere
+const DEFAULT_ROBOTS_TXT: &str = "User-agent: *\nAllow: /\n";
+
+/// Responses for common paths which the host answers without calling the guest
+#[derive(Clone, Debug)]
+pub struct BuiltinResponses {
+    pub favicon: Option<Bytes>,
+    pub robots_txt: Option<Bytes>,
+    pub last_modified: SystemTime,
+}
+
+impl Default for BuiltinResponses {
+    fn default() -> Self {
+        Self {
+            favicon: Some(Bytes::from_static(DEFAULT_FAVICON)),
+            robots_txt: Some(Bytes::from_static(DEFAULT_ROBOTS_TXT.as_bytes())),
+            last_modified: SystemTime::now(),
+        }
+    }
+}
+
+impl BuiltinResponses {
+    /// Get the builtin response for a request, if there is one
+    pub fn respond<B>(&self, req: &Request<B>) -> Option<Response<Body>> {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return None;
+        }
+
+        let (content, content_type) = match req.uri().path() {
+            "/favicon.ico" => (self.favicon.as_ref()?, "image/x-icon"),
+            "/robots.txt" => (self.robots_txt.as_ref()?, "text/plain; charset=utf-8"),
+            _ => return None,
+        };
+
+        let validators = Validators::for_content(content, self.last_modified);
+
+        let mut response = if validators.is_not_modified(req.headers()) {
+            Response::builder()
+                .status(StatusCode::NOT_MODIFIED)
+                .body(Body::empty())
+        } else {
+            let body = if req.method() == Method::HEAD {
+                Body::empty()
+            } else {
+                Body::from(content.clone())
+            };
+
+            Response::builder()
+                .header(header::CONTENT_TYPE, HeaderValue::from_static(content_type))
+                .header(header::CONTENT_LENGTH, content.len())
+                .body(body)
+        }
+        .expect("building a builtin response should not fail");
+
+        validators.apply(response.headers_mut());
+
+        Some(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn robots_txt() {
+        let builtin = BuiltinResponses::default();
+        let request = Request::get("/robots.txt").body(()).unwrap();
+
+        let response = builtin.respond(&request).unwrap();
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "23");
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body.as_ref(), DEFAULT_ROBOTS_TXT.as_bytes());
+    }
+
+    #[test]
+    fn not_modified() {
+        let builtin = BuiltinResponses::default();
+        let request = Request::get("/favicon.ico").body(()).unwrap();
+        let etag = builtin.respond(&request).unwrap().headers()[header::ETAG].clone();
+
+        let request = Request::get("/favicon.ico")
+            .header(header::IF_NONE_MATCH, etag)
+            .body(())
+            .unwrap();
+        let response = builtin.respond(&request).unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[test]
+    fn disabled() {
+        let builtin = BuiltinResponses {
+            favicon: None,
+            ..Default::default()
+        };
+
+        let request = Request::get("/favicon.ico").body(()).unwrap();
+        assert!(builtin.respond(&request).is_none());
+
+        let request = Request::post("/robots.txt").body(()).unwrap();
+        assert!(builtin.respond(&request).is_none());
+    }
+}
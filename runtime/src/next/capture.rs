@@ -0,0 +1,273 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Context;
+use hyper::header::{self, HeaderName, HeaderValue};
+use hyper::http::StatusCode;
+use hyper::{Body, HeaderMap, Uri};
+use serde::{Deserialize, Serialize};
+use shuttle_common::wasm::{RequestWrapper, ResponseWrapper};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{trace, warn};
+
+use super::RouterBuilder;
+
+const DEFAULT_MAX_BYTES: u64 = 64 * 1024 * 1024;
+const DEFAULT_MAX_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// Configuration for recording requests and the guest's responses to a file, so they can be
+/// replayed later with [replay]
+#[derive(Clone, Debug)]
+pub struct CaptureConfig {
+    path: PathBuf,
+    max_bytes: u64,
+    max_duration: Duration,
+    redacted_headers: Vec<HeaderName>,
+}
+
+impl CaptureConfig {
+    /// Capture to the file at `path`, replacing it if it already exists. The `Authorization`,
+    /// `Proxy-Authorization`, `Cookie` and `Set-Cookie` headers are redacted by default.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            max_bytes: DEFAULT_MAX_BYTES,
+            max_duration: DEFAULT_MAX_DURATION,
+            redacted_headers: vec![
+                header::AUTHORIZATION,
+                header::PROXY_AUTHORIZATION,
+                header::COOKIE,
+                header::SET_COOKIE,
+            ],
+        }
+    }
+
+    /// Stop capturing once the file reaches this size
+    pub fn max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Stop capturing once this long has passed since the router was built
+    pub fn max_duration(mut self, max_duration: Duration) -> Self {
+        self.max_duration = max_duration;
+        self
+    }
+
+    /// Also redact the values of `header` in the capture
+    pub fn redact_header(mut self, header: HeaderName) -> Self {
+        self.redacted_headers.push(header);
+        self
+    }
+}
+
+/// A single request and the response the guest gave for it
+#[derive(Serialize, Deserialize)]
+struct Record {
+    request: RequestWrapper,
+    request_body: Vec<u8>,
+    response: ResponseWrapper,
+    response_body: Vec<u8>,
+}
+
+/// An open capture file
+pub(crate) struct Capture {
+    writer: Mutex<CaptureWriter>,
+    deadline: Instant,
+    max_bytes: u64,
+    redacted_headers: Vec<HeaderName>,
+}
+
+struct CaptureWriter {
+    file: BufWriter<File>,
+    written: u64,
+    done: bool,
+}
+
+impl Capture {
+    pub(crate) fn open(config: CaptureConfig) -> anyhow::Result<Self> {
+        let file = File::create(&config.path).context("failed to create capture file")?;
+
+        Ok(Self {
+            writer: Mutex::new(CaptureWriter {
+                file: BufWriter::new(file),
+                written: 0,
+                done: false,
+            }),
+            deadline: Instant::now() + config.max_duration,
+            max_bytes: config.max_bytes,
+            redacted_headers: config.redacted_headers,
+        })
+    }
+
+    /// Append a request and its response to the capture, unless one of the bounds was reached
+    pub(crate) fn record(
+        &self,
+        mut request: RequestWrapper,
+        request_body: &[u8],
+        mut response: ResponseWrapper,
+        response_body: &[u8],
+    ) {
+        let mut writer = self.writer.lock().unwrap();
+
+        if writer.done {
+            return;
+        }
+
+        if Instant::now() > self.deadline {
+            trace!("capture duration reached, no longer capturing");
+            writer.done = true;
+            return;
+        }
+
+        self.redact(&mut request.headers);
+        self.redact(&mut response.headers);
+
+        let record = Record {
+            request,
+            request_body: request_body.to_vec(),
+            response,
+            response_body: response_body.to_vec(),
+        };
+
+        let bytes = match rmp_serde::to_vec(&record) {
+            Ok(bytes) => bytes,
+            Err(error) => {
+                warn!(%error, "failed to serialize capture record");
+                return;
+            }
+        };
+
+        if writer.written + bytes.len() as u64 > self.max_bytes {
+            trace!("capture size reached, no longer capturing");
+            writer.done = true;
+            return;
+        }
+
+        let result = writer
+            .file
+            .write_all(&bytes)
+            .and_then(|_| writer.file.flush());
+
+        match result {
+            Ok(()) => writer.written += bytes.len() as u64,
+            Err(error) => {
+                warn!(%error, "failed to write to capture file, no longer capturing");
+                writer.done = true;
+            }
+        }
+    }
+
+    fn redact(&self, headers: &mut HeaderMap) {
+        for name in &self.redacted_headers {
+            if let header::Entry::Occupied(mut entry) = headers.entry(name) {
+                entry.insert(HeaderValue::from_static("[redacted]"));
+            }
+        }
+    }
+}
+
+/// The outcome of replaying a single captured request
+#[derive(Debug)]
+pub struct ReplayOutcome {
+    pub uri: Uri,
+    pub captured_status: StatusCode,
+    pub status: StatusCode,
+    pub body_matches: bool,
+}
+
+/// Send every request in the capture file at `capture` to a router built from `router_builder`
+/// and compare the responses with the captured ones
+pub async fn replay<P: AsRef<Path>>(
+    router_builder: RouterBuilder,
+    capture: P,
+) -> anyhow::Result<Vec<ReplayOutcome>> {
+    let router = router_builder.build()?;
+    let mut reader = BufReader::new(File::open(capture).context("failed to open capture file")?);
+
+    let (logs_tx, mut logs_rx) = mpsc::channel(1);
+    tokio::spawn(async move { while logs_rx.recv().await.is_some() {} });
+
+    let mut outcomes = Vec::new();
+
+    loop {
+        let record: Record = match rmp_serde::from_read(&mut reader) {
+            Ok(record) => record,
+            Err(rmp_serde::decode::Error::InvalidMarkerRead(error))
+                if error.kind() == std::io::ErrorKind::UnexpectedEof =>
+            {
+                break;
+            }
+            Err(error) => return Err(error).context("failed to read capture record"),
+        };
+
+        let uri = record.request.uri.clone();
+        let request = record
+            .request
+            .into_request_builder()
+            .body(Body::from(record.request_body))
+            .context("failed to build captured request")?;
+
+        let response = router.handle_request(request, logs_tx.clone()).await?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .context("failed to read replayed response body")?;
+
+        outcomes.push(ReplayOutcome {
+            uri,
+            captured_status: record.response.status,
+            status,
+            body_matches: body.as_ref() == record.response_body.as_slice(),
+        });
+    }
+
+    Ok(outcomes)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Method, Version};
+
+    use super::*;
+
+    #[test]
+    fn redacts_sensitive_headers() {
+        let path = std::env::temp_dir().join(format!("capture-{}", std::process::id()));
+        let capture = Capture::open(CaptureConfig::new(&path)).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer secret"),
+        );
+        headers.insert(header::ACCEPT, HeaderValue::from_static("*/*"));
+
+        capture.record(
+            RequestWrapper {
+                method: Method::GET,
+                uri: Uri::from_static("/hello"),
+                version: Version::HTTP_11,
+                headers,
+            },
+            b"",
+            ResponseWrapper {
+                status: StatusCode::OK,
+                version: Version::HTTP_11,
+                headers: HeaderMap::new(),
+            },
+            b"Hello, World!",
+        );
+
+        let record: Record = rmp_serde::from_read(File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(record.request.headers[header::AUTHORIZATION], "[redacted]");
+        assert_eq!(record.request.headers[header::ACCEPT], "*/*");
+        assert_eq!(record.response_body, b"Hello, World!");
+    }
+}
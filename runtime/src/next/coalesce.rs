@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use hyper::body::Bytes;
+use hyper::header::{self, HeaderValue};
+use hyper::http::{StatusCode, Version};
+use hyper::{Body, HeaderMap, Method, Request, Response};
+use tokio::sync::broadcast;
+
+/// Identifies requests which are expected to get the same response
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct CoalesceKey {
+    method: Method,
+    uri: String,
+    headers: Vec<Option<HeaderValue>>,
+}
+
+impl CoalesceKey {
+    /// Get the key for a request, if it can be coalesced with identical requests. Only `GET`
+    /// and `HEAD` requests without credentials are coalesced.
+    pub(crate) fn for_request<B>(req: &Request<B>) -> Option<Self> {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return None;
+        }
+
+        let headers = req.headers();
+        if headers.contains_key(header::AUTHORIZATION) || headers.contains_key(header::COOKIE) {
+            return None;
+        }
+
+        // The request headers which commonly change the response, so requests which differ in
+        // them are not coalesced
+        let varying_headers = [
+            header::HOST,
+            header::ACCEPT,
+            header::ACCEPT_ENCODING,
+            header::ACCEPT_LANGUAGE,
+        ];
+
+        Some(Self {
+            method: req.method().clone(),
+            uri: req.uri().to_string(),
+            headers: varying_headers
+                .iter()
+                .map(|name| headers.get(name).cloned())
+                .collect(),
+        })
+    }
+}
+
+/// A complete response which can be given to every coalesced request
+#[derive(Debug)]
+pub(crate) struct SharedResponse {
+    status: StatusCode,
+    version: Version,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl SharedResponse {
+    /// Buffer the whole of `response` so it can be shared
+    pub(crate) async fn buffer(response: Response<Body>) -> hyper::Result<Self> {
+        let (parts, body) = response.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+
+        Ok(Self {
+            status: parts.status,
+            version: parts.version,
+            headers: parts.headers,
+            body,
+        })
+    }
+
+    pub(crate) fn to_response(&self) -> Response<Body> {
+        let mut response = Response::new(Body::from(self.body.clone()));
+        *response.status_mut() = self.status;
+        *response.version_mut() = self.version;
+        *response.headers_mut() = self.headers.clone();
+
+        response
+    }
+}
+
+/// Check if a response with these headers may be given to clients other than the one which
+/// caused it, which is not the case when it sets cookies or is marked as private
+pub(crate) fn is_shareable(headers: &HeaderMap) -> bool {
+    if headers.contains_key(header::SET_COOKIE) {
+        return false;
+    }
+
+    !headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| {
+            let directive = directive.trim();
+            directive.eq_ignore_ascii_case("private") || directive.eq_ignore_ascii_case("no-store")
+        })
+}
+
+type FlightSender = broadcast::Sender<Arc<SharedResponse>>;
+
+/// The requests currently being handled on behalf of identical requests waiting on them
+#[derive(Default)]
+pub(crate) struct Coalescer {
+    in_flight: Mutex<HashMap<CoalesceKey, (u64, FlightSender)>>,
+    next_id: AtomicU64,
+}
+
+/// How a request takes part in coalescing
+pub(crate) enum Flight {
+    /// The request should be handled and its response shared with the followers
+    Leader(Leader),
+    /// The request should wait for the response of an identical request. The sender is dropped
+    /// without sending when the response cannot be shared.
+    Follower(broadcast::Receiver<Arc<SharedResponse>>),
+}
+
+impl Coalescer {
+    /// Join the in-flight request for `key`, or lead a new one if there is none
+    pub(crate) fn join(self: &Arc<Self>, key: CoalesceKey) -> Flight {
+        let mut in_flight = self
+            .in_flight
+            .lock()
+            .expect("coalescer lock should not be poisoned");
+
+        if let Some((_, tx)) = in_flight.get(&key) {
+            return Flight::Follower(tx.subscribe());
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, _rx) = broadcast::channel(1);
+        in_flight.insert(key.clone(), (id, tx.clone()));
+
+        Flight::Leader(Leader {
+            coalescer: self.clone(),
+            key,
+            id,
+            tx,
+        })
+    }
+
+    /// Stop new requests for `key` from joining flight `id`, unless a newer flight replaced it
+    fn remove(&self, key: &CoalesceKey, id: u64) {
+        let mut in_flight = self
+            .in_flight
+            .lock()
+            .expect("coalescer lock should not be poisoned");
+
+        if in_flight
+            .get(key)
+            .is_some_and(|(current, _)| *current == id)
+        {
+            in_flight.remove(key);
+        }
+    }
+}
+
+/// The request being handled for a key. Followers stop waiting on it when it is dropped, even
+/// if it did not finish.
+pub(crate) struct Leader {
+    coalescer: Arc<Coalescer>,
+    key: CoalesceKey,
+    id: u64,
+    tx: FlightSender,
+}
+
+impl Leader {
+    /// Give `response` to every follower
+    pub(crate) fn finish(self, response: Arc<SharedResponse>) {
+        // Later requests should start a new flight instead of joining this finished one
+        self.coalescer.remove(&self.key, self.id);
+
+        // There might not be any followers
+        let _ = self.tx.send(response);
+    }
+}
+
+impl Drop for Leader {
+    fn drop(&mut self) {
+        self.coalescer.remove(&self.key, self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(req: Request<()>) -> Option<CoalesceKey> {
+        CoalesceKey::for_request(&req)
+    }
+
+    #[test]
+    fn keys() {
+        let get = |path: &str| Request::get(path);
+
+        assert_eq!(
+            key(get("/a").body(()).unwrap()),
+            key(get("/a").header("user-agent", "curl").body(()).unwrap())
+        );
+        assert_ne!(
+            key(get("/a").body(()).unwrap()),
+            key(get("/a?page=2").body(()).unwrap())
+        );
+        assert_ne!(
+            key(get("/a").header("accept", "text/html").body(()).unwrap()),
+            key(get("/a")
+                .header("accept", "application/json")
+                .body(())
+                .unwrap())
+        );
+        assert_ne!(
+            key(get("/a").body(()).unwrap()),
+            key(Request::head("/a").body(()).unwrap())
+        );
+
+        assert!(key(Request::post("/a").body(()).unwrap()).is_none());
+        assert!(key(get("/a").header("cookie", "session=1").body(()).unwrap()).is_none());
+        assert!(key(get("/a")
+            .header("authorization", "Bearer 1")
+            .body(())
+            .unwrap())
+        .is_none());
+    }
+
+    #[tokio::test]
+    async fn followers_share_the_response() {
+        let coalescer = Arc::new(Coalescer::default());
+        let key = key(Request::get("/a").body(()).unwrap()).unwrap();
+
+        let Flight::Leader(leader) = coalescer.join(key.clone()) else {
+            panic!("first request should lead");
+        };
+        let Flight::Follower(mut follower) = coalescer.join(key.clone()) else {
+            panic!("second request should follow");
+        };
+
+        let response = Response::builder()
+            .header("content-type", "text/plain")
+            .body(Body::from("hello"))
+            .unwrap();
+        let shared = SharedResponse::buffer(response).await.unwrap();
+        leader.finish(Arc::new(shared));
+
+        let response = follower.recv().await.unwrap().to_response();
+        assert_eq!(response.headers()["content-type"], "text/plain");
+        assert_eq!(
+            hyper::body::to_bytes(response.into_body()).await.unwrap(),
+            "hello"
+        );
+
+        // The finished flight is not joined anymore
+        assert!(matches!(coalescer.join(key), Flight::Leader(_)));
+    }
+
+    #[tokio::test]
+    async fn followers_stop_waiting_on_a_dropped_leader() {
+        let coalescer = Arc::new(Coalescer::default());
+        let key = key(Request::get("/a").body(()).unwrap()).unwrap();
+
+        let leader = coalescer.join(key.clone());
+        let Flight::Follower(mut follower) = coalescer.join(key.clone()) else {
+            panic!("second request should follow");
+        };
+
+        drop(leader);
+
+        assert!(follower.recv().await.is_err());
+        assert!(matches!(coalescer.join(key), Flight::Leader(_)));
+    }
+
+    #[test]
+    fn private_responses_are_not_shared() {
+        let headers = |name: &'static str, value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(name, HeaderValue::from_static(value));
+            headers
+        };
+
+        assert!(is_shareable(&HeaderMap::new()));
+        assert!(!is_shareable(&headers("set-cookie", "session=1")));
+        assert!(!is_shareable(&headers(
+            "cache-control",
+            "max-age=60, private"
+        )));
+        assert!(!is_shareable(&headers("cache-control", "No-Store")));
+        assert!(is_shareable(&headers(
+            "cache-control",
+            "public, max-age=60"
+        )));
+    }
+}
@@ -0,0 +1,145 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use headers::{ETag, HeaderMapExt, IfModifiedSince, IfNoneMatch, IfRange, LastModified};
+use hyper::HeaderMap;
+use ring::digest;
+
+/// Validators for content served by the host, used to answer conditional requests
+pub struct Validators {
+    etag: ETag,
+    last_modified: SystemTime,
+}
+
+impl Validators {
+    /// Derive a weak entity tag from the length and modification time of a file, so the file
+    /// does not have to be read to answer a conditional request for it
+    pub fn for_file(length: u64, last_modified: SystemTime) -> Self {
+        let modified = last_modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        Self {
+            etag: format!(
+                "W/\"{length:x}-{:x}.{:x}\"",
+                modified.as_secs(),
+                modified.subsec_nanos()
+            )
+            .parse()
+            .expect("hex numbers should make a valid etag"),
+            last_modified,
+        }
+    }
+
+    /// Derive the entity tag from a hash of `content`
+    pub fn for_content(content: &[u8], last_modified: SystemTime) -> Self {
+        let digest = digest::digest(&digest::SHA256, content);
+        let hex: String = digest.as_ref()[..16]
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+
+        Self {
+            etag: format!("\"{hex}\"")
+                .parse()
+                .expect("a hex digest should be a valid etag"),
+            last_modified,
+        }
+    }
+
+    /// Check if the client already has the current content cached, in which case it should get
+    /// a `304 Not Modified`. `If-Modified-Since` is ignored when `If-None-Match` is present.
+    pub fn is_not_modified(&self, headers: &HeaderMap) -> bool {
+        if let Some(if_none_match) = headers.typed_get::<IfNoneMatch>() {
+            return !if_none_match.precondition_passes(&self.etag);
+        }
+
+        if let Some(if_modified_since) = headers.typed_get::<IfModifiedSince>() {
+            return !if_modified_since.is_modified(self.last_modified);
+        }
+
+        false
+    }
+
+    /// Check if a range request can get part of the current content, which it cannot when its
+    /// `If-Range` is for a different version of the content
+    pub fn is_range_current(&self, headers: &HeaderMap) -> bool {
+        headers.typed_get::<IfRange>().map_or(true, |if_range| {
+            !if_range.is_modified(
+                Some(&self.etag),
+                Some(&LastModified::from(self.last_modified)),
+            )
+        })
+    }
+
+    /// Add the `ETag` and `Last-Modified` headers to a response
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        headers.typed_insert(self.etag.clone());
+        headers.typed_insert(LastModified::from(self.last_modified));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn if_none_match() {
+        let validators = Validators::for_content(b"hello", SystemTime::now());
+
+        let mut response_headers = HeaderMap::new();
+        validators.apply(&mut response_headers);
+        let etag = response_headers[hyper::header::ETAG].clone();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(hyper::header::IF_NONE_MATCH, etag);
+        assert!(validators.is_not_modified(&headers));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            hyper::header::IF_NONE_MATCH,
+            "\"something-else\"".parse().unwrap(),
+        );
+        assert!(!validators.is_not_modified(&headers));
+    }
+
+    #[test]
+    fn file_etags() {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let validators = Validators::for_file(5, modified);
+
+        let mut response_headers = HeaderMap::new();
+        validators.apply(&mut response_headers);
+        let etag = response_headers[hyper::header::ETAG].clone();
+        assert!(etag.to_str().unwrap().starts_with("W/\"5-"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(hyper::header::IF_NONE_MATCH, etag.clone());
+        assert!(validators.is_not_modified(&headers));
+
+        // Changing the length or the modification time changes the tag
+        for other in [
+            Validators::for_file(6, modified),
+            Validators::for_file(5, modified + Duration::from_millis(1)),
+        ] {
+            assert!(!other.is_not_modified(&headers));
+        }
+    }
+
+    #[test]
+    fn if_modified_since() {
+        let last_modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let validators = Validators::for_content(b"hello", last_modified);
+
+        let mut headers = HeaderMap::new();
+        headers.typed_insert(IfModifiedSince::from(last_modified));
+        assert!(validators.is_not_modified(&headers));
+
+        let mut headers = HeaderMap::new();
+        headers.typed_insert(IfModifiedSince::from(
+            last_modified - Duration::from_secs(60),
+        ));
+        assert!(!validators.is_not_modified(&headers));
+
+        assert!(!validators.is_not_modified(&HeaderMap::new()));
+    }
+}
@@ -0,0 +1,224 @@
+use serde::{Deserialize, Serialize};
+
+use super::instance_rate::InstanceRate;
+use super::keep_alive::KeepAlive;
+use super::listener::ListenerOptions;
+use super::watch::LoadRetries;
+use super::{
+    DEFAULT_BODY_WRITE_TIMEOUT, DEFAULT_DRAIN_TIMEOUT, DEFAULT_MAX_BODY_SIZE, DEFAULT_MAX_LOG_SIZE,
+    DEFAULT_MAX_REQUEST_HEAD_SIZE, DEFAULT_MAX_RESPONSE_HEADERS, DEFAULT_MAX_RESPONSE_HEADERS_SIZE,
+    DEFAULT_MAX_RESPONSE_HEADER_BYTES, DEFAULT_MAX_ROUTE_BODY_SIZE, DEFAULT_RESPONSE_CHUNK_SIZE,
+    DEFAULT_STARTUP_RETRY_AFTER,
+};
+
+/// The tunables of a router in one place, so they can be serialized and come from a config
+/// file or a load request. Missing fields take their default when deserializing.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RuntimeConfig {
+    /// Largest request body accepted, in bytes
+    pub max_body_size: u64,
+
+    /// Largest request body accepted for routes the guest gives their own limit, in bytes
+    pub max_route_body_size: u64,
+
+    /// How long the guest has to read the request parts and body, in milliseconds
+    pub body_write_timeout_ms: u64,
+
+    /// How long instantiating the module for a request can take, in milliseconds
+    pub instantiate_timeout_ms: Option<u64>,
+
+    /// How long the guest has to produce a response, in milliseconds
+    pub handler_timeout_ms: Option<u64>,
+
+    /// How long all phases of a request can take together, in milliseconds
+    pub request_timeout_ms: Option<u64>,
+
+    /// Size of the chunks response bodies are streamed in, in bytes
+    pub response_chunk_size: usize,
+
+    /// Send response bodies of at most this many bytes whole, with an exact `Content-Length`
+    pub response_buffer_threshold: Option<usize>,
+
+    /// Most headers the guest can respond with
+    pub max_response_headers: usize,
+
+    /// Largest total size of the headers the guest can respond with, in bytes
+    pub max_response_headers_size: usize,
+
+    /// Largest total size of the response headers sent to the client, in bytes
+    pub max_response_header_bytes: usize,
+
+    /// Largest request line and headers buffered while parsing a request, in bytes
+    pub max_request_head_size: usize,
+
+    /// Largest size of each part of a guest log, in bytes
+    pub max_log_size: usize,
+
+    /// Log requests which take longer than this many milliseconds
+    pub slow_request_threshold_ms: Option<u64>,
+
+    /// Run guest calls on a dedicated pool of up to this many threads
+    pub guest_threads: Option<usize>,
+
+    /// Stop guest pool threads which have been idle for this many milliseconds
+    pub guest_thread_idle_timeout_ms: Option<u64>,
+
+    /// Reject guest responses which claim to be JSON but are not
+    pub strict_content_type: bool,
+
+    /// Describe host errors with `application/problem+json` bodies
+    pub problem_json: bool,
+
+    /// Write response header names in Title-Case
+    pub title_case_headers: bool,
+
+    /// Pipe the request body to the guest's stdin
+    pub body_as_stdin: bool,
+
+    /// Stream request bodies into the guest, buffering at most this many bytes it has not read
+    pub request_body_high_water_mark: Option<usize>,
+
+    /// Stop the server after it has been running for this many milliseconds
+    pub max_lifetime_ms: Option<u64>,
+
+    /// Most connections waiting to be accepted
+    pub listen_backlog: u32,
+
+    /// Let other processes share the address the server listens on
+    pub reuse_port: bool,
+
+    /// Keep connections open for more requests after a response
+    pub keep_alive: bool,
+
+    /// Close connections after they served this many requests
+    pub max_requests_per_connection: Option<u64>,
+
+    /// Close connections which have been idle for this many milliseconds
+    pub keep_alive_timeout_ms: Option<u64>,
+
+    /// Close connections whose current request has taken this many milliseconds, from reading
+    /// its body until its response is sent
+    pub max_request_duration_ms: Option<u64>,
+
+    /// Share the response of a request with identical requests which arrive while it is handled
+    pub coalesce_requests: bool,
+
+    /// Log the wasm backtraces of requests which trap in the guest, at debug level
+    pub wasm_backtraces: bool,
+
+    /// Also write forwarded logs to stdout as JSON lines
+    pub json_logs: bool,
+
+    /// Honor `X-HTTP-Method-Override` on `POST` requests
+    pub method_override: bool,
+
+    /// Answer `OPTIONS` requests on the host with the methods the guest advertises
+    pub auto_options: bool,
+
+    /// Trap guests which make more than this many host calls for one request
+    pub max_host_calls: Option<u64>,
+
+    /// Instances of the module to keep ready for requests
+    pub warm_pool_size: usize,
+
+    /// Create at most this many instances for requests per second on average
+    pub max_instances_per_second: Option<u32>,
+
+    /// Instances which can be created at once despite the rate
+    pub instance_burst: u32,
+
+    /// Longest a request waits for its turn to create an instance, in milliseconds
+    pub max_instance_wait_ms: u64,
+
+    /// Times loading the module is retried when it fails in a way which can pass
+    pub module_load_retries: u32,
+
+    /// How long to wait between retries of loading the module, in milliseconds
+    pub module_load_retry_delay_ms: u64,
+
+    /// How long to wait for in-flight requests when stopping, in milliseconds
+    pub drain_timeout_ms: u64,
+
+    /// Detail of the response to requests which arrive before the server is ready
+    pub startup_detail: Option<String>,
+
+    /// Seconds clients are told to wait in the `Retry-After` of the startup response
+    pub startup_retry_after_secs: u64,
+
+    /// Share of the open files limit of the process that unix streams for requests can use
+    pub max_request_fds_percent: Option<u8>,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self {
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            max_route_body_size: DEFAULT_MAX_ROUTE_BODY_SIZE,
+            body_write_timeout_ms: DEFAULT_BODY_WRITE_TIMEOUT.as_millis() as u64,
+            instantiate_timeout_ms: None,
+            handler_timeout_ms: None,
+            request_timeout_ms: None,
+            response_chunk_size: DEFAULT_RESPONSE_CHUNK_SIZE,
+            response_buffer_threshold: None,
+            max_response_headers: DEFAULT_MAX_RESPONSE_HEADERS,
+            max_response_headers_size: DEFAULT_MAX_RESPONSE_HEADERS_SIZE,
+            max_response_header_bytes: DEFAULT_MAX_RESPONSE_HEADER_BYTES,
+            max_request_head_size: DEFAULT_MAX_REQUEST_HEAD_SIZE,
+            max_log_size: DEFAULT_MAX_LOG_SIZE,
+            slow_request_threshold_ms: None,
+            guest_threads: None,
+            guest_thread_idle_timeout_ms: None,
+            strict_content_type: false,
+            problem_json: false,
+            title_case_headers: false,
+            body_as_stdin: false,
+            request_body_high_water_mark: None,
+            max_lifetime_ms: None,
+            listen_backlog: ListenerOptions::default().backlog,
+            reuse_port: false,
+            keep_alive: KeepAlive::default().enabled,
+            max_requests_per_connection: None,
+            keep_alive_timeout_ms: KeepAlive::default()
+                .idle_timeout
+                .map(|timeout| timeout.as_millis() as u64),
+            max_request_duration_ms: None,
+            coalesce_requests: false,
+            wasm_backtraces: false,
+            json_logs: false,
+            method_override: false,
+            auto_options: false,
+            max_host_calls: None,
+            warm_pool_size: 0,
+            max_instances_per_second: InstanceRate::default().per_second,
+            instance_burst: InstanceRate::default().burst,
+            max_instance_wait_ms: InstanceRate::default().max_wait.as_millis() as u64,
+            module_load_retries: LoadRetries::default().attempts,
+            module_load_retry_delay_ms: LoadRetries::default().delay.as_millis() as u64,
+            drain_timeout_ms: DEFAULT_DRAIN_TIMEOUT.as_millis() as u64,
+            startup_detail: None,
+            startup_retry_after_secs: DEFAULT_STARTUP_RETRY_AFTER.as_secs(),
+            max_request_fds_percent: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_fields_are_defaults() {
+        let config: RuntimeConfig =
+            serde_json::from_str(r#"{"max_body_size": 1024, "guest_threads": 4}"#).unwrap();
+
+        assert_eq!(
+            config,
+            RuntimeConfig {
+                max_body_size: 1024,
+                guest_threads: Some(4),
+                ..Default::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use std::time::Duration;
+
+use hyper::header::{self, HeaderName, HeaderValue};
+use hyper::http::StatusCode;
+use hyper::{Body, HeaderMap, Method, Request, Response};
+
+/// Configuration for handling CORS on the host instead of in the guest
+#[derive(Clone, Debug, Default)]
+pub struct CorsConfig {
+    allowed_origins: Vec<String>,
+    allowed_methods: Vec<Method>,
+    allowed_headers: Vec<HeaderName>,
+    allow_credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl CorsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow requests from `origin`, or from any origin when `origin` is `*`
+    pub fn allow_origin(mut self, origin: impl Into<String>) -> Self {
+        self.allowed_origins.push(origin.into());
+        self
+    }
+
+    /// Methods to allow in preflight requests
+    pub fn allow_methods(mut self, methods: impl IntoIterator<Item = Method>) -> Self {
+        self.allowed_methods.extend(methods);
+        self
+    }
+
+    /// Request headers to allow in preflight requests
+    pub fn allow_headers(mut self, headers: impl IntoIterator<Item = HeaderName>) -> Self {
+        self.allowed_headers.extend(headers);
+        self
+    }
+
+    /// Allow requests to include credentials like cookies
+    pub fn allow_credentials(mut self, allow: bool) -> Self {
+        self.allow_credentials = allow;
+        self
+    }
+
+    /// How long browsers may cache the result of a preflight request
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Check if this is a preflight request which should be answered without calling the guest
+    pub(crate) fn is_preflight<B>(req: &Request<B>) -> bool {
+        req.method() == Method::OPTIONS
+            && req.headers().contains_key(header::ORIGIN)
+            && req
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+    }
+
+    /// Answer a preflight request. The browser will block the actual request if the origin
+    /// is not allowed, since the response will not have any CORS headers then.
+    pub(crate) fn preflight_response(&self, origin: Option<&HeaderValue>) -> Response<Body> {
+        let mut response = Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(Body::empty())
+            .expect("building request with empty body should not fail");
+
+        if self.apply(origin, response.headers_mut()) {
+            let headers = response.headers_mut();
+
+            if !self.allowed_methods.is_empty() {
+                headers.insert(
+                    header::ACCESS_CONTROL_ALLOW_METHODS,
+                    join(self.allowed_methods.iter().map(Method::as_str)),
+                );
+            }
+
+            if !self.allowed_headers.is_empty() {
+                headers.insert(
+                    header::ACCESS_CONTROL_ALLOW_HEADERS,
+                    join(self.allowed_headers.iter().map(HeaderName::as_str)),
+                );
+            }
+
+            if let Some(max_age) = self.max_age {
+                headers.insert(header::ACCESS_CONTROL_MAX_AGE, max_age.as_secs().into());
+            }
+        }
+
+        response
+    }
+
+    /// Add the CORS headers for a request from `origin` to the response `headers`.
+    /// Returns whether the origin is allowed.
+    pub(crate) fn apply(&self, origin: Option<&HeaderValue>, headers: &mut HeaderMap) -> bool {
+        let Some(origin) = origin else {
+            return false;
+        };
+
+        let allows_any = self.allowed_origins.iter().any(|allowed| allowed == "*");
+        let is_allowed = allows_any
+            || self
+                .allowed_origins
+                .iter()
+                .any(|allowed| allowed.as_bytes() == origin.as_bytes());
+
+        if !is_allowed {
+            return false;
+        }
+
+        // Credentials cannot be used with a wildcard origin
+        if allows_any && !self.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                HeaderValue::from_static("*"),
+            );
+        } else {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+            headers.append(header::VARY, HeaderValue::from_static("Origin"));
+        }
+
+        if self.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+
+        true
+    }
+}
+
+fn join<'a>(values: impl Iterator<Item = &'a str>) -> HeaderValue {
+    HeaderValue::from_str(&values.collect::<Vec<_>>().join(", "))
+        .expect("methods and header names should be valid header values")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preflight() {
+        let cors = CorsConfig::new()
+            .allow_origin("https://shuttle.rs")
+            .allow_methods([Method::GET, Method::POST])
+            .allow_headers([header::CONTENT_TYPE])
+            .max_age(Duration::from_secs(600));
+
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, "https://shuttle.rs")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(())
+            .unwrap();
+        assert!(CorsConfig::is_preflight(&request));
+
+        let response = cors.preflight_response(request.headers().get(header::ORIGIN));
+        let headers = response.headers();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://shuttle.rs"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_METHODS], "GET, POST");
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "content-type"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+
+        let response = cors.preflight_response(Some(&HeaderValue::from_static("https://evil.rs")));
+        assert!(!response
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[test]
+    fn wildcard_origin() {
+        let origin = HeaderValue::from_static("https://shuttle.rs");
+
+        let mut headers = HeaderMap::new();
+        assert!(CorsConfig::new()
+            .allow_origin("*")
+            .apply(Some(&origin), &mut headers));
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+
+        let mut headers = HeaderMap::new();
+        assert!(CorsConfig::new()
+            .allow_origin("*")
+            .allow_credentials(true)
+            .apply(Some(&origin), &mut headers));
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://shuttle.rs"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::{BTreeMap, HashMap};
+
+use shuttle_proto::runtime::LogItem;
+use thiserror::Error;
+
+// The metadata is copied into every log, so keep it small
+const MAX_SIZE: usize = 4 * 1024;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum DeploymentMetadataError {
+    #[error("deployment metadata should be at most {} bytes", MAX_SIZE)]
+    TooLarge,
+}
+
+/// Free-form metadata, like a ticket id or git sha, to correlate everything a deployment logs
+/// with external systems. Unlike [Tags](super::tags::Tags) the keys and values are not restricted.
+#[derive(Clone, Debug, Default)]
+pub struct DeploymentMetadata(BTreeMap<String, String>);
+
+impl DeploymentMetadata {
+    pub fn new(metadata: HashMap<String, String>) -> Result<Self, DeploymentMetadataError> {
+        let size: usize = metadata
+            .iter()
+            .map(|(key, value)| key.len() + value.len())
+            .sum();
+
+        if size > MAX_SIZE {
+            return Err(DeploymentMetadataError::TooLarge);
+        }
+
+        Ok(Self(metadata.into_iter().collect()))
+    }
+
+    /// Add the metadata to the fields of a log item
+    pub fn apply(&self, log: &mut LogItem) {
+        if self.0.is_empty() {
+            return;
+        }
+
+        let Ok(mut fields) =
+            serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(&log.fields)
+        else {
+            return;
+        };
+
+        fields.insert(
+            "metadata".to_string(),
+            serde_json::to_value(&self.0).expect("metadata should serialize"),
+        );
+
+        log.fields = serde_json::to_vec(&fields).expect("json values should serialize");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_is_limited() {
+        let metadata =
+            |value: String| DeploymentMetadata::new(HashMap::from([("ticket".to_string(), value)]));
+
+        assert!(metadata("a".repeat(MAX_SIZE - 6)).is_ok());
+        assert_eq!(
+            metadata("a".repeat(MAX_SIZE - 5)).unwrap_err(),
+            DeploymentMetadataError::TooLarge
+        );
+    }
+
+    #[test]
+    fn apply() {
+        let metadata = DeploymentMetadata::new(HashMap::from([
+            ("Git SHA".to_string(), "0a1b2c3".to_string()),
+            ("deployer".to_string(), "ci@example.com".to_string()),
+        ]))
+        .unwrap();
+        let mut log = LogItem {
+            fields: br#"{"message":"hello"}"#.to_vec(),
+            ..Default::default()
+        };
+
+        metadata.apply(&mut log);
+
+        let fields: serde_json::Value = serde_json::from_slice(&log.fields).unwrap();
+        assert_eq!(fields["message"], "hello");
+        assert_eq!(fields["metadata"]["Git SHA"], "0a1b2c3");
+        assert_eq!(fields["metadata"]["deployer"], "ci@example.com");
+    }
+}
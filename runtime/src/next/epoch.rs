@@ -0,0 +1,30 @@
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+use wasmtime::Engine;
+
+/// Advances the epoch of an engine every tick from a thread of its own until it is dropped.
+/// Guest calls only notice cancellations and timeouts on epoch changes, and a call can block
+/// the only thread of a tokio runtime, like in deterministic mode, so the ticks cannot come
+/// from a task.
+pub(crate) struct EpochTicker {
+    _stop: mpsc::Sender<()>,
+}
+
+impl EpochTicker {
+    pub(crate) fn start(engine: Engine, tick: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel();
+
+        std::thread::Builder::new()
+            .name("wasm-epoch".to_string())
+            .spawn(move || {
+                // The sender is only ever dropped, which ends the ticks
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(tick) {
+                    engine.increment_epoch();
+                }
+            })
+            .expect("epoch thread should be spawned");
+
+        Self { _stop: stop }
+    }
+}
@@ -0,0 +1,180 @@
+use hyper::http::StatusCode;
+use hyper::{Body, Response};
+
+/// Errors the host responds with itself, without the guest producing the response
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HostError {
+    AmbiguousFraming,
+    MalformedUri,
+    MisdirectedRequest,
+    NotFound,
+    PayloadTooLarge,
+    InvalidResponse,
+    NoResponse,
+    GuestNotReading,
+    InstantiateTimeout,
+    HandlerTimeout,
+    Overloaded,
+    Starting,
+    IdempotencyConflict,
+    IdempotencyKeyReused,
+    Internal,
+}
+
+impl HostError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::AmbiguousFraming => StatusCode::BAD_REQUEST,
+            Self::MalformedUri => StatusCode::BAD_REQUEST,
+            Self::MisdirectedRequest => StatusCode::MISDIRECTED_REQUEST,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::InvalidResponse => StatusCode::BAD_GATEWAY,
+            Self::NoResponse => StatusCode::BAD_GATEWAY,
+            Self::GuestNotReading => StatusCode::BAD_GATEWAY,
+            Self::InstantiateTimeout => StatusCode::SERVICE_UNAVAILABLE,
+            Self::HandlerTimeout => StatusCode::GATEWAY_TIMEOUT,
+            Self::Overloaded => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Starting => StatusCode::SERVICE_UNAVAILABLE,
+            Self::IdempotencyConflict => StatusCode::CONFLICT,
+            Self::IdempotencyKeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Stable URI identifying this type of error. These should never change once published.
+    fn type_uri(&self) -> &'static str {
+        match self {
+            Self::AmbiguousFraming => "urn:shuttle:next:ambiguous-framing",
+            Self::MalformedUri => "urn:shuttle:next:malformed-uri",
+            Self::MisdirectedRequest => "urn:shuttle:next:misdirected-request",
+            Self::NotFound => "urn:shuttle:next:not-found",
+            Self::PayloadTooLarge => "urn:shuttle:next:payload-too-large",
+            Self::InvalidResponse => "urn:shuttle:next:invalid-response",
+            Self::NoResponse => "urn:shuttle:next:no-response",
+            Self::GuestNotReading => "urn:shuttle:next:guest-not-reading",
+            Self::InstantiateTimeout => "urn:shuttle:next:instantiate-timeout",
+            Self::HandlerTimeout => "urn:shuttle:next:handler-timeout",
+            Self::Overloaded => "urn:shuttle:next:overloaded",
+            Self::Starting => "urn:shuttle:next:starting",
+            Self::IdempotencyConflict => "urn:shuttle:next:idempotency-conflict",
+            Self::IdempotencyKeyReused => "urn:shuttle:next:idempotency-key-reused",
+            Self::Internal => "urn:shuttle:next:internal",
+        }
+    }
+
+    /// Explanation safe to show to clients, so it should not contain any internals
+    fn detail(&self) -> &'static str {
+        match self {
+            Self::AmbiguousFraming => "the request sets conflicting body length headers",
+            Self::MalformedUri => "the request uri is malformed",
+            Self::MisdirectedRequest => "this service does not serve the requested host",
+            Self::NotFound => "this service does not serve the requested path",
+            Self::PayloadTooLarge => "the request body is larger than this service accepts",
+            Self::InvalidResponse => "the service produced an invalid response",
+            Self::NoResponse => "the service did not produce a response",
+            Self::GuestNotReading => "the service did not read the request",
+            Self::InstantiateTimeout => "the service took too long to start for the request",
+            Self::HandlerTimeout => "the service took too long to respond",
+            Self::Overloaded => "the service is overloaded, try again later",
+            Self::Starting => "the service is starting, try again shortly",
+            Self::IdempotencyConflict => {
+                "a request with this idempotency key is still being handled"
+            }
+            Self::IdempotencyKeyReused => {
+                "this idempotency key was already used for a different request"
+            }
+            Self::Internal => "the service failed to handle the request",
+        }
+    }
+
+    /// Build the response for this error. With `problem_json` the body is an RFC 7807
+    /// `application/problem+json` document, otherwise the body is empty.
+    pub fn into_response(self, problem_json: bool) -> Response<Body> {
+        self.build_response(problem_json, None)
+    }
+
+    /// Like [HostError::into_response], but with a custom detail which is also used as a plain
+    /// text body when `problem_json` is not set
+    pub fn into_response_with_detail(self, problem_json: bool, detail: &str) -> Response<Body> {
+        self.build_response(problem_json, Some(detail))
+    }
+
+    fn build_response(self, problem_json: bool, detail: Option<&str>) -> Response<Body> {
+        let status = self.status();
+        let builder = Response::builder().status(status);
+
+        let response = if problem_json {
+            let problem = serde_json::json!({
+                "type": self.type_uri(),
+                "title": status.canonical_reason().unwrap_or_default(),
+                "status": status.as_u16(),
+                "detail": detail.unwrap_or(self.detail()),
+            });
+
+            builder
+                .header(hyper::header::CONTENT_TYPE, "application/problem+json")
+                .body(problem.to_string().into())
+        } else if let Some(detail) = detail {
+            builder
+                .header(hyper::header::CONTENT_TYPE, "text/plain; charset=utf-8")
+                .body(detail.to_string().into())
+        } else {
+            builder.body(Body::empty())
+        };
+
+        response.expect("building a host error response should not fail")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn problem_json() {
+        let response = HostError::PayloadTooLarge.into_response(true);
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            response.headers()[hyper::header::CONTENT_TYPE],
+            "application/problem+json"
+        );
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(problem["type"], "urn:shuttle:next:payload-too-large");
+        assert_eq!(problem["title"], "Payload Too Large");
+        assert_eq!(problem["status"], 413);
+    }
+
+    #[tokio::test]
+    async fn empty_body_by_default() {
+        let response = HostError::Internal.into_response(false);
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn custom_detail() {
+        let response =
+            HostError::PayloadTooLarge.into_response_with_detail(false, "body exceeds 65536 bytes");
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "body exceeds 65536 bytes");
+
+        let response =
+            HostError::PayloadTooLarge.into_response_with_detail(true, "body exceeds 65536 bytes");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(problem["detail"], "body exceeds 65536 bytes");
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use hyper::body::Bytes;
+use hyper::header::{self, HeaderValue};
+use hyper::http::StatusCode;
+use hyper::{Body, Response};
+
+/// A pre-rendered page the host serves instead of its default body for one of its own errors
+#[derive(Clone, Debug)]
+pub struct ErrorPage {
+    content_type: HeaderValue,
+    body: Bytes,
+}
+
+impl ErrorPage {
+    pub fn new(content_type: HeaderValue, body: impl Into<Bytes>) -> Self {
+        Self {
+            content_type,
+            body: body.into(),
+        }
+    }
+
+    /// An HTML page
+    pub fn html(body: impl Into<Bytes>) -> Self {
+        Self::new(HeaderValue::from_static("text/html; charset=utf-8"), body)
+    }
+}
+
+/// The pages to serve for errors generated by the host, by their status. These never apply to
+/// responses from the guest, which renders its own errors.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ErrorPages(HashMap<StatusCode, ErrorPage>);
+
+impl ErrorPages {
+    pub(crate) fn new(pages: HashMap<StatusCode, ErrorPage>) -> Self {
+        Self(pages)
+    }
+
+    pub(crate) fn contains(&self, status: StatusCode) -> bool {
+        self.0.contains_key(&status)
+    }
+
+    /// Respond with the page for `status`, if one is configured
+    pub(crate) fn response(&self, status: StatusCode) -> Option<Response<Body>> {
+        let page = self.0.get(&status)?;
+
+        let response = Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, page.content_type.clone())
+            .header(header::CONTENT_LENGTH, page.body.len())
+            .body(page.body.clone().into())
+            .expect("building an error page response should not fail");
+
+        Some(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn only_configured_statuses() {
+        let pages = ErrorPages::new(HashMap::from([(
+            StatusCode::SERVICE_UNAVAILABLE,
+            ErrorPage::html("<h1>Back soon</h1>"),
+        )]));
+
+        let response = pages.response(StatusCode::SERVICE_UNAVAILABLE).unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "18");
+        assert_eq!(
+            hyper::body::to_bytes(response.into_body()).await.unwrap(),
+            "<h1>Back soon</h1>"
+        );
+
+        assert!(pages.response(StatusCode::BAD_GATEWAY).is_none());
+    }
+}
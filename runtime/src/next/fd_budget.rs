@@ -0,0 +1,148 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::{bail, Context};
+
+/// File descriptors a request holds while it is handled: both ends of the logs, parts and body
+/// unix streams
+pub(crate) const FDS_PER_REQUEST: u64 = 6;
+
+/// How many file descriptors requests can use at the same time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum FdLimit {
+    /// At most this many file descriptors
+    Fixed(u64),
+    /// At most this percentage of the open files limit of the process
+    PercentOfRlimit(u8),
+}
+
+impl FdLimit {
+    /// Get the number of file descriptors this limit allows, or `None` if the process can open
+    /// an unlimited number of files
+    pub(crate) fn resolve(self) -> anyhow::Result<Option<u64>> {
+        match self {
+            Self::Fixed(max) => Ok(Some(max)),
+            Self::PercentOfRlimit(percent) => {
+                let Some(limit) = open_files_limit()? else {
+                    return Ok(None);
+                };
+
+                Ok(Some(limit * percent.min(100) as u64 / 100))
+            }
+        }
+    }
+}
+
+/// The file descriptors in use by requests, shared by every copy of a router
+pub(crate) struct FdBudget {
+    max: u64,
+    in_use: AtomicU64,
+}
+
+impl FdBudget {
+    pub(crate) fn new(max: u64) -> Self {
+        Self {
+            max,
+            in_use: AtomicU64::new(0),
+        }
+    }
+
+    /// Reserve the file descriptors for a request, unless that would go over the budget. They
+    /// are released when the returned permit is dropped.
+    pub(crate) fn try_acquire(self: &Arc<Self>) -> Option<FdPermit> {
+        self.in_use
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |in_use| {
+                let in_use = in_use + FDS_PER_REQUEST;
+                (in_use <= self.max).then_some(in_use)
+            })
+            .ok()?;
+
+        Some(FdPermit {
+            budget: self.clone(),
+        })
+    }
+}
+
+/// The file descriptors reserved for one request
+pub(crate) struct FdPermit {
+    budget: Arc<FdBudget>,
+}
+
+impl Drop for FdPermit {
+    fn drop(&mut self) {
+        self.budget
+            .in_use
+            .fetch_sub(FDS_PER_REQUEST, Ordering::AcqRel);
+    }
+}
+
+/// Read the soft limit on open files of this process, which is `None` when it is unlimited
+fn open_files_limit() -> anyhow::Result<Option<u64>> {
+    let limits =
+        std::fs::read_to_string("/proc/self/limits").context("failed to read process limits")?;
+
+    match parse_open_files_limit(&limits) {
+        Some(limit) => Ok(limit),
+        None => bail!("process limits should contain the open files limit"),
+    }
+}
+
+/// Get the soft limit on open files from the contents of `/proc/self/limits`
+fn parse_open_files_limit(limits: &str) -> Option<Option<u64>> {
+    let soft_limit = limits
+        .lines()
+        .find_map(|line| line.strip_prefix("Max open files"))?
+        .split_whitespace()
+        .next()?;
+
+    if soft_limit == "unlimited" {
+        return Some(None);
+    }
+
+    soft_limit.parse().ok().map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget() {
+        let budget = Arc::new(FdBudget::new(2 * FDS_PER_REQUEST + 1));
+
+        let first = budget.try_acquire().unwrap();
+        let _second = budget.try_acquire().unwrap();
+        assert!(budget.try_acquire().is_none());
+
+        drop(first);
+        assert!(budget.try_acquire().is_some());
+    }
+
+    #[test]
+    fn parse() {
+        let limits = "\
+Limit                     Soft Limit           Hard Limit           Units
+Max cpu time              unlimited            unlimited            seconds
+Max open files            1024                 524288               files
+Max locked memory         8388608              8388608              bytes
+";
+
+        assert_eq!(parse_open_files_limit(limits), Some(Some(1024)));
+        assert_eq!(
+            parse_open_files_limit(
+                "Max open files            unlimited            unlimited            files\n"
+            ),
+            Some(None)
+        );
+        assert_eq!(parse_open_files_limit("Max cpu time  unlimited\n"), None);
+    }
+
+    #[test]
+    fn percent_of_rlimit() {
+        let limit = open_files_limit().unwrap();
+        let resolved = FdLimit::PercentOfRlimit(50).resolve().unwrap();
+
+        assert_eq!(resolved, limit.map(|limit| limit / 2));
+        assert_eq!(FdLimit::Fixed(60).resolve().unwrap(), Some(60));
+    }
+}
@@ -0,0 +1,188 @@
+use std::str::FromStr;
+
+use tracing::warn;
+
+use super::RouterBuilder;
+
+/// Host middleware which a deployment can turn on or off with its feature flags
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Middleware {
+    CoalesceRequests,
+    Cors,
+    BodyTransform,
+    MemoryPressure,
+    IdempotencyKeys,
+    ProblemJson,
+    StrictContentType,
+    TitleCaseHeaders,
+}
+
+impl Middleware {
+    const ALL: [Self; 8] = [
+        Self::CoalesceRequests,
+        Self::Cors,
+        Self::BodyTransform,
+        Self::MemoryPressure,
+        Self::IdempotencyKeys,
+        Self::ProblemJson,
+        Self::StrictContentType,
+        Self::TitleCaseHeaders,
+    ];
+
+    /// The name of the feature flag for this middleware
+    fn name(self) -> &'static str {
+        match self {
+            Self::CoalesceRequests => "coalesce-requests",
+            Self::Cors => "cors",
+            Self::BodyTransform => "body-transform",
+            Self::MemoryPressure => "memory-pressure",
+            Self::IdempotencyKeys => "idempotency-keys",
+            Self::ProblemJson => "problem-json",
+            Self::StrictContentType => "strict-content-type",
+            Self::TitleCaseHeaders => "title-case-headers",
+        }
+    }
+
+    fn is_enabled(self, builder: &RouterBuilder) -> bool {
+        match self {
+            Self::CoalesceRequests => builder.coalesce_requests,
+            Self::Cors => builder.cors.is_some(),
+            Self::BodyTransform => builder.body_transform.is_some(),
+            Self::MemoryPressure => builder.memory_pressure.is_some(),
+            Self::IdempotencyKeys => builder.idempotency_keys.is_some(),
+            Self::ProblemJson => builder.problem_json,
+            Self::StrictContentType => builder.strict_content_type,
+            Self::TitleCaseHeaders => builder.title_case_headers,
+        }
+    }
+}
+
+impl FromStr for Middleware {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|middleware| middleware.name() == name)
+            .ok_or(())
+    }
+}
+
+/// The names of the middleware which `builder` turns on
+pub(crate) fn enabled_middleware(builder: &RouterBuilder) -> Vec<String> {
+    Middleware::ALL
+        .into_iter()
+        .filter(|middleware| middleware.is_enabled(builder))
+        .map(|middleware| middleware.name().to_string())
+        .collect()
+}
+
+/// The middleware a load request turns on or off, in the order of its flags. A flag is the
+/// name of the middleware to turn it on, or the name prefixed with `-` to turn it off.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct FeatureFlags(Vec<(Middleware, bool)>);
+
+impl FeatureFlags {
+    /// Parse `flags`, skipping the ones which are not known so a runtime can serve deployments
+    /// meant for newer runtimes
+    pub(crate) fn new(flags: Vec<String>) -> Self {
+        let flags = flags
+            .iter()
+            .filter_map(|flag| {
+                let (name, enabled) = match flag.strip_prefix('-') {
+                    Some(name) => (name, false),
+                    None => (flag.as_str(), true),
+                };
+
+                match name.parse() {
+                    Ok(middleware) => Some((middleware, enabled)),
+                    Err(()) => {
+                        warn!(flag, "ignoring unknown feature flag");
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        Self(flags)
+    }
+
+    /// Turn the middleware of `builder` on or off. Middleware which needs configuration, like
+    /// CORS, can only be turned on when `builder` has its configuration.
+    pub(crate) fn apply(&self, mut builder: RouterBuilder) -> RouterBuilder {
+        for (middleware, enabled) in self.0.iter().copied() {
+            match middleware {
+                Middleware::CoalesceRequests => builder.coalesce_requests = enabled,
+                Middleware::ProblemJson => builder.problem_json = enabled,
+                Middleware::StrictContentType => builder.strict_content_type = enabled,
+                Middleware::TitleCaseHeaders => builder.title_case_headers = enabled,
+                Middleware::Cors if !enabled => builder.cors = None,
+                Middleware::BodyTransform if !enabled => builder.body_transform = None,
+                Middleware::MemoryPressure if !enabled => builder.memory_pressure = None,
+                Middleware::IdempotencyKeys if !enabled => builder.idempotency_keys = None,
+                Middleware::Cors => warn_unconfigured(builder.cors.is_none(), "cors"),
+                Middleware::BodyTransform => {
+                    warn_unconfigured(builder.body_transform.is_none(), "body-transform")
+                }
+                Middleware::MemoryPressure => {
+                    warn_unconfigured(builder.memory_pressure.is_none(), "memory-pressure")
+                }
+                Middleware::IdempotencyKeys => {
+                    warn_unconfigured(builder.idempotency_keys.is_none(), "idempotency-keys")
+                }
+            }
+        }
+
+        builder
+    }
+}
+
+fn warn_unconfigured(unconfigured: bool, flag: &str) {
+    if unconfigured {
+        warn!(
+            flag,
+            "ignoring feature flag for middleware which the runtime has not configured"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::next::CorsConfig;
+
+    fn flags(flags: &[&str]) -> FeatureFlags {
+        FeatureFlags::new(flags.iter().map(ToString::to_string).collect())
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            flags(&["cors", "-problem-json", "compression", "--cors"]),
+            FeatureFlags(vec![
+                (Middleware::Cors, true),
+                (Middleware::ProblemJson, false)
+            ])
+        );
+    }
+
+    #[test]
+    fn apply() {
+        let builder = RouterBuilder::new()
+            .unwrap()
+            .problem_json(true)
+            .cors(CorsConfig::default());
+
+        let builder = flags(&["-problem-json", "coalesce-requests", "-cors"]).apply(builder);
+        assert!(!builder.problem_json);
+        assert!(builder.coalesce_requests);
+        assert!(builder.cors.is_none());
+
+        // Later flags win, and middleware without configuration stays off
+        let builder = flags(&["-coalesce-requests", "coalesce-requests", "cors"]).apply(builder);
+        assert!(builder.coalesce_requests);
+        assert!(builder.cors.is_none());
+
+        assert_eq!(enabled_middleware(&builder), ["coalesce-requests"]);
+    }
+}
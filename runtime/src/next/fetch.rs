@@ -0,0 +1,141 @@
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+
+const DEFAULT_MAX_SIZE: u64 = 256 * 1024 * 1024;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Limits for downloading a module from a URL
+#[derive(Clone, Debug)]
+pub(crate) struct ModuleFetch {
+    pub max_size: u64,
+    pub timeout: Duration,
+}
+
+impl Default for ModuleFetch {
+    fn default() -> Self {
+        Self {
+            max_size: DEFAULT_MAX_SIZE,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl ModuleFetch {
+    /// Download the bytes of the module at `url`. TLS certificates are always verified.
+    pub(crate) async fn fetch(&self, url: &str) -> anyhow::Result<Vec<u8>> {
+        let client = reqwest::Client::builder()
+            .timeout(self.timeout)
+            .build()
+            .context("failed to build the http client")?;
+
+        let mut response = client
+            .get(url)
+            .send()
+            .await
+            .context("failed to request the module")?
+            .error_for_status()
+            .context("the module could not be downloaded")?;
+
+        if response
+            .content_length()
+            .is_some_and(|length| length > self.max_size)
+        {
+            bail!("the module is larger than {} bytes", self.max_size);
+        }
+
+        // The length is only a hint, so keep counting while downloading
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .context("failed to download the module")?
+        {
+            if (bytes.len() + chunk.len()) as u64 > self.max_size {
+                bail!("the module is larger than {} bytes", self.max_size);
+            }
+
+            bytes.extend_from_slice(&chunk);
+        }
+
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Response, StatusCode};
+
+    use super::*;
+
+    /// Serve `body` for `/module.wasm` and a not found for everything else, after `delay`
+    async fn serve(body: &'static [u8], delay: Duration) -> SocketAddr {
+        let make_service = make_service_fn(move |_conn| async move {
+            Ok::<_, Infallible>(service_fn(move |req| async move {
+                tokio::time::sleep(delay).await;
+
+                let response = if req.uri().path() == "/module.wasm" {
+                    Response::new(Body::from(body))
+                } else {
+                    Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .body(Body::empty())
+                        .unwrap()
+                };
+
+                Ok::<_, Infallible>(response)
+            }))
+        });
+
+        let server = hyper::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service);
+        let address = server.local_addr();
+        tokio::spawn(server);
+
+        address
+    }
+
+    #[tokio::test]
+    async fn fetch() {
+        let address = serve(b"\0asm\x01\0\0\0", Duration::ZERO).await;
+
+        let bytes = ModuleFetch::default()
+            .fetch(&format!("http://{address}/module.wasm"))
+            .await
+            .unwrap();
+        assert_eq!(bytes, b"\0asm\x01\0\0\0");
+    }
+
+    #[tokio::test]
+    async fn fetch_errors() {
+        let address = serve(b"\0asm\x01\0\0\0", Duration::ZERO).await;
+
+        let error = ModuleFetch::default()
+            .fetch(&format!("http://{address}/missing.wasm"))
+            .await
+            .unwrap_err();
+        assert!(format!("{error:#}").contains("404"), "{error:#}");
+
+        let error = ModuleFetch {
+            max_size: 4,
+            ..Default::default()
+        }
+        .fetch(&format!("http://{address}/module.wasm"))
+        .await
+        .unwrap_err();
+        assert_eq!(error.to_string(), "the module is larger than 4 bytes");
+
+        let address = serve(b"\0asm\x01\0\0\0", Duration::from_secs(5)).await;
+        let error = ModuleFetch {
+            timeout: Duration::from_millis(100),
+            ..Default::default()
+        }
+        .fetch(&format!("http://{address}/module.wasm"))
+        .await
+        .unwrap_err();
+        assert!(format!("{error:#}").contains("timed out"), "{error:#}");
+    }
+}
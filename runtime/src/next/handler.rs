@@ -0,0 +1,460 @@
+use std::os::unix::prelude::RawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use cap_std::os::unix::net::UnixStream;
+use hyper::{Body, Request, Response};
+use shuttle_proto::runtime::LogItem;
+use tokio::sync::mpsc::Sender;
+use tonic::Status;
+use wasi_common::file::FileCaps;
+use wasmtime::{Store, TypedFunc};
+use wasmtime_wasi::sync::net::UnixStream as WasiUnixStream;
+use wasmtime_wasi::WasiCtx;
+
+use super::Router;
+
+const LOGS_FD: u32 = 20;
+const PARTS_FD: u32 = 3;
+const BODY_FD: u32 = 4;
+
+/// Something which handles requests from start to end, like the wasm-backed [Router]
+#[async_trait]
+pub(crate) trait RequestHandler: Send + Sync {
+    async fn handle(
+        &self,
+        req: Request<Body>,
+        logs_tx: Sender<Result<LogItem, Status>>,
+    ) -> anyhow::Result<Response<Body>>;
+}
+
+#[async_trait]
+impl RequestHandler for Router {
+    async fn handle(
+        &self,
+        req: Request<Body>,
+        logs_tx: Sender<Result<LogItem, Status>>,
+    ) -> anyhow::Result<Response<Body>> {
+        self.handle_request(req, logs_tx).await
+    }
+}
+
+/// The guest's ends of the unix streams it reads its request from, and writes its logs and
+/// response to
+pub(crate) struct GuestStreams {
+    pub(crate) logs: UnixStream,
+    pub(crate) parts: UnixStream,
+    pub(crate) body: UnixStream,
+}
+
+/// Decides when a guest call has to stop before it returns: once an operator aborted the
+/// request, once it ran out of time to respond, or once the client has gone away or the request
+/// body could not be written to it
+#[derive(Clone)]
+pub(crate) struct CallInterrupt {
+    pub(crate) cancelled: Arc<AtomicBool>,
+    pub(crate) aborted: Arc<AtomicBool>,
+    pub(crate) timed_out: Arc<AtomicBool>,
+    pub(crate) handler_deadline: Option<Instant>,
+}
+
+impl CallInterrupt {
+    /// Fail with the reason the call has to stop, if it has to
+    pub(crate) fn check(&self) -> anyhow::Result<()> {
+        if self.aborted.load(Ordering::Relaxed) {
+            Err(anyhow!("request was aborted"))
+        } else if self
+            .handler_deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            self.timed_out.store(true, Ordering::Relaxed);
+            Err(anyhow!("request took too long to handle"))
+        } else if self.cancelled.load(Ordering::Relaxed) {
+            Err(anyhow!("request was cancelled by the client disconnecting"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Runs the router function of a guest on one request, after the host wrote the request to its
+/// streams and before the host reads the response from them. This is the wasm module, and tests
+/// use a mock to stand in for it.
+pub(crate) trait Guest: Send + Sync {
+    /// Handle the request in `streams`, blocking until the guest returns. `call` is the router
+    /// function of the instance in `store`, and the call fails once `interrupt` says so.
+    fn call(
+        &self,
+        store: Store<WasiCtx>,
+        call: TypedFunc<(RawFd, RawFd, RawFd), ()>,
+        streams: GuestStreams,
+        interrupt: CallInterrupt,
+    ) -> anyhow::Result<()>;
+}
+
+/// Calls the router function of the wasm module with the streams as its files
+pub(crate) struct WasmGuest;
+
+impl Guest for WasmGuest {
+    fn call(
+        &self,
+        mut store: Store<WasiCtx>,
+        call: TypedFunc<(RawFd, RawFd, RawFd), ()>,
+        streams: GuestStreams,
+        interrupt: CallInterrupt,
+    ) -> anyhow::Result<()> {
+        for (fd, stream) in [
+            (LOGS_FD, streams.logs),
+            (PARTS_FD, streams.parts),
+            (BODY_FD, streams.body),
+        ] {
+            store.data_mut().insert_file(
+                fd,
+                Box::new(WasiUnixStream::from_cap_std(stream)),
+                FileCaps::all(),
+            );
+        }
+
+        // Trap the guest on its next epoch check once it has to stop
+        store.set_epoch_deadline(1);
+        store.epoch_deadline_callback(move |_| interrupt.check().map(|()| 1));
+
+        call.call(
+            &mut store,
+            (LOGS_FD as i32, PARTS_FD as i32, BODY_FD as i32),
+        )
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::io::Write;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    use hyper::body::Bytes;
+    use hyper::header::{HeaderName, HeaderValue};
+    use hyper::{HeaderMap, StatusCode, Version};
+    use shuttle_common::wasm::{RequestWrapper, ResponseWrapper};
+    use wasmtime::Trap;
+
+    use super::*;
+
+    // How often a delayed mock checks whether it has to stop, like a guest on an epoch tick
+    const INTERRUPT_CHECK: Duration = Duration::from_millis(10);
+
+    /// A guest which gives every request the same response, or traps on every request, so
+    /// everything the host does around a guest call can be tested without a wasm module
+    pub(crate) struct MockGuest {
+        status: StatusCode,
+        headers: HeaderMap,
+        body: Bytes,
+        trap: bool,
+        delay: Option<Duration>,
+        calls: AtomicUsize,
+    }
+
+    impl MockGuest {
+        /// A guest which responds to every request with `status` and `body`
+        pub(crate) fn respond(status: StatusCode, body: &'static str) -> Self {
+            Self {
+                status,
+                headers: HeaderMap::new(),
+                body: Bytes::from_static(body.as_bytes()),
+                trap: false,
+                delay: None,
+                calls: AtomicUsize::new(0),
+            }
+        }
+
+        /// A guest which traps on every request
+        pub(crate) fn trap() -> Self {
+            Self {
+                trap: true,
+                ..Self::respond(StatusCode::OK, "")
+            }
+        }
+
+        /// Add a header to the response
+        pub(crate) fn header(mut self, name: &'static str, value: &'static str) -> Self {
+            self.headers.append(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+            self
+        }
+
+        /// Take `delay` to handle every request
+        pub(crate) fn delay(mut self, delay: Duration) -> Self {
+            self.delay = Some(delay);
+            self
+        }
+
+        /// How many requests the guest got
+        pub(crate) fn calls(&self) -> usize {
+            self.calls.load(Ordering::Relaxed)
+        }
+    }
+
+    impl Guest for MockGuest {
+        fn call(
+            &self,
+            _store: Store<WasiCtx>,
+            _call: TypedFunc<(RawFd, RawFd, RawFd), ()>,
+            streams: GuestStreams,
+            interrupt: CallInterrupt,
+        ) -> anyhow::Result<()> {
+            let GuestStreams {
+                mut parts,
+                mut body,
+                ..
+            } = streams;
+
+            self.calls.fetch_add(1, Ordering::Relaxed);
+
+            // Take the whole request like a guest would
+            let _request: RequestWrapper = rmp_serde::from_read(&mut parts)?;
+            std::io::copy(&mut body, &mut std::io::sink())?;
+
+            if let Some(delay) = self.delay {
+                let end = Instant::now() + delay;
+
+                while Instant::now() < end {
+                    interrupt.check()?;
+                    std::thread::sleep(
+                        INTERRUPT_CHECK.min(end.saturating_duration_since(Instant::now())),
+                    );
+                }
+            }
+
+            if self.trap {
+                return Err(Trap::UnreachableCodeReached.into());
+            }
+
+            let response = ResponseWrapper {
+                status: self.status,
+                version: Version::HTTP_11,
+                headers: self.headers.clone(),
+            };
+            parts.write_all(&response.into_rmp()?)?;
+            body.write_all(&self.body)?;
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use hyper::header::{HeaderName, HeaderValue};
+    use hyper::{HeaderMap, StatusCode};
+    use tokio::sync::mpsc;
+
+    use super::mock::MockGuest;
+    use super::*;
+    use crate::next::{RouterBuilder, TrailingSlash};
+
+    fn router(builder: RouterBuilder, guest: &Arc<MockGuest>) -> Router {
+        builder
+            .module_bytes(
+                br#"(module (func (export "__SHUTTLE_Axum_call") (param i32 i32 i32)))"#.to_vec(),
+            )
+            .build()
+            .unwrap()
+            .with_guest(guest.clone())
+    }
+
+    async fn send(handler: &dyn RequestHandler, req: Request<Body>) -> Response<Body> {
+        let (tx, _rx) = mpsc::channel(64);
+
+        handler.handle(req, tx).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn coalesces_with_a_mock_guest() {
+        let guest = Arc::new(
+            MockGuest::respond(StatusCode::OK, "hello")
+                .header("cache-control", "public, max-age=60")
+                .delay(Duration::from_millis(100)),
+        );
+        let router = router(
+            RouterBuilder::new().unwrap().coalesce_requests(true),
+            &guest,
+        );
+
+        let get = || {
+            Request::get("https://mock.example/hello")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let (first, second) = tokio::join!(send(&router, get()), send(&router, get()));
+
+        for res in [first, second] {
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(
+                hyper::body::to_bytes(res.into_body()).await.unwrap(),
+                "hello"
+            );
+        }
+        assert_eq!(guest.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn failures_are_not_replayed() {
+        let guest = Arc::new(MockGuest::respond(StatusCode::SERVICE_UNAVAILABLE, "busy"));
+        let router = router(
+            RouterBuilder::new()
+                .unwrap()
+                .idempotency_keys(Duration::from_secs(60), 16),
+            &guest,
+        );
+
+        let post = || {
+            Request::post("https://mock.example/orders")
+                .header("idempotency-key", "order-1")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        for _ in 0..2 {
+            let res = send(&router, post()).await;
+            assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+            assert!(res.headers().get("idempotent-replayed").is_none());
+        }
+        assert_eq!(guest.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn traps_are_errors() {
+        let guest = Arc::new(MockGuest::trap());
+        let router = router(RouterBuilder::new().unwrap(), &guest);
+        let (tx, _rx) = mpsc::channel(64);
+
+        let error = router
+            .handle(
+                Request::get("https://mock.example/")
+                    .body(Body::empty())
+                    .unwrap(),
+                tx,
+            )
+            .await
+            .unwrap_err();
+
+        assert!(error.downcast_ref::<wasmtime::Trap>().is_some());
+        assert_eq!(guest.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn host_processes_the_mock_response() {
+        let guest = Arc::new(MockGuest::respond(StatusCode::NOT_FOUND, "missing"));
+        let router = router(
+            RouterBuilder::new()
+                .unwrap()
+                .status_remap(HashMap::from([(StatusCode::NOT_FOUND, StatusCode::GONE)]))
+                .default_response_headers(HeaderMap::from_iter([(
+                    HeaderName::from_static("x-frame-options"),
+                    HeaderValue::from_static("DENY"),
+                )])),
+            &guest,
+        );
+
+        let res = send(
+            &router,
+            Request::get("https://mock.example/orders/1")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+
+        assert_eq!(res.status(), StatusCode::GONE);
+        assert_eq!(res.headers()["x-frame-options"], "DENY");
+        assert_eq!(
+            hyper::body::to_bytes(res.into_body()).await.unwrap(),
+            "missing"
+        );
+    }
+
+    #[tokio::test]
+    async fn mock_guests_time_out() {
+        let guest =
+            Arc::new(MockGuest::respond(StatusCode::OK, "slow").delay(Duration::from_secs(5)));
+        let router = router(
+            RouterBuilder::new()
+                .unwrap()
+                .handler_timeout(Duration::from_millis(100)),
+            &guest,
+        );
+
+        let res = send(
+            &router,
+            Request::get("https://mock.example/")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+
+        assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(guest.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn host_features_run_before_the_guest() {
+        let guest = Arc::new(MockGuest::respond(StatusCode::OK, "hello"));
+        let router = router(
+            RouterBuilder::new()
+                .unwrap()
+                .allowed_hosts(vec!["mock.example".to_string()]),
+            &guest,
+        );
+
+        let res = send(
+            &router,
+            Request::get("https://other.example/")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+
+        assert_eq!(res.status(), StatusCode::MISDIRECTED_REQUEST);
+        assert_eq!(guest.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn trailing_slash_redirects_skip_the_guest() {
+        let guest = Arc::new(MockGuest::respond(StatusCode::OK, "hello"));
+        let router = router(
+            RouterBuilder::new()
+                .unwrap()
+                .trailing_slash(TrailingSlash::RedirectToNoSlash),
+            &guest,
+        );
+
+        let res = send(
+            &router,
+            Request::get("https://mock.example/hello/?name=wasm")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(res.headers()["location"], "/hello?name=wasm");
+        assert_eq!(guest.calls(), 0);
+
+        let res = send(
+            &router,
+            Request::get("https://mock.example/hello")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(guest.calls(), 1);
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::os::unix::prelude::RawFd;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+use cap_std::os::unix::net::UnixStream;
+use hyper::header::{self, HeaderValue};
+use hyper::http::StatusCode;
+use hyper::{Body, Response};
+use serde::{Deserialize, Serialize};
+use wasi_common::file::FileCaps;
+use wasmtime::{Linker, Module, Store};
+use wasmtime_wasi::sync::net::UnixStream as WasiUnixStream;
+use wasmtime_wasi::WasiCtx;
+
+/// Export a guest can have to report the health of its dependencies. It is called with a file
+/// descriptor to write a JSON report to, like
+/// `{"status": "healthy", "checks": {"db": {"status": "unhealthy", "detail": "timed out"}}}`.
+const HEALTH_EXPORT: &str = "__SHUTTLE_health";
+
+const HEALTH_FD: u32 = 3;
+
+// A health check should be quick, and a stuck one should not hold up the next
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Reports are small, so anything larger is cut off and fails to parse
+const MAX_REPORT_SIZE: u64 = 64 * 1024;
+
+/// How healthy a guest or one of its dependencies is, from best to worst
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum HealthStatus {
+    Healthy,
+    Degraded,
+    Unhealthy,
+}
+
+/// The health of one dependency of the guest
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct DependencyHealth {
+    status: HealthStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+/// A report as the guest writes it, where every part is optional
+#[derive(Deserialize)]
+struct GuestReport {
+    status: Option<HealthStatus>,
+    #[serde(default)]
+    checks: BTreeMap<String, DependencyHealth>,
+}
+
+/// The health of the guest, as the host responds with it
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub(crate) struct HealthReport {
+    status: HealthStatus,
+    checks: BTreeMap<String, DependencyHealth>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+impl HealthReport {
+    /// The guest could be instantiated, which is all there is to know without a health export
+    pub(crate) fn alive() -> Self {
+        Self {
+            status: HealthStatus::Healthy,
+            checks: BTreeMap::new(),
+            detail: None,
+        }
+    }
+
+    /// The health could not be checked
+    pub(crate) fn failed(detail: &str) -> Self {
+        Self {
+            status: HealthStatus::Unhealthy,
+            checks: BTreeMap::new(),
+            detail: Some(detail.to_string()),
+        }
+    }
+
+    /// Summarize the report the guest wrote. The overall status is the worst of the status
+    /// the guest gave and those of its dependencies.
+    pub(crate) fn from_guest(report: &[u8]) -> Self {
+        let Ok(report) = serde_json::from_slice::<GuestReport>(report) else {
+            return Self::failed("the service wrote an invalid health report");
+        };
+
+        let status = report
+            .checks
+            .values()
+            .map(|check| check.status)
+            .chain(report.status)
+            .max()
+            .unwrap_or(HealthStatus::Healthy);
+
+        Self {
+            status,
+            checks: report.checks,
+            detail: None,
+        }
+    }
+
+    /// Respond with the report as JSON, with a `503 Service Unavailable` when unhealthy
+    pub(crate) fn into_response(self, head: bool) -> Response<Body> {
+        let status = match self.status {
+            HealthStatus::Healthy | HealthStatus::Degraded => StatusCode::OK,
+            HealthStatus::Unhealthy => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        let report = serde_json::to_vec(&self).expect("health reports should serialize");
+
+        Response::builder()
+            .status(status)
+            .header(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            )
+            .header(header::CONTENT_LENGTH, report.len())
+            .header(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))
+            .body(if head { Body::empty() } else { report.into() })
+            .expect("building a health response should not fail")
+    }
+}
+
+/// Check the health of `module` in `store` with its health export, or only that it could be
+/// instantiated when it has none. This blocks until the guest is done.
+pub(crate) fn check(
+    linker: &Linker<WasiCtx>,
+    module: &Module,
+    mut store: Store<WasiCtx>,
+) -> anyhow::Result<HealthReport> {
+    if module.get_export(HEALTH_EXPORT).is_none() {
+        return Ok(HealthReport::alive());
+    }
+
+    let (mut report_stream, report_client) =
+        UnixStream::pair().context("failed to open health report unixstream")?;
+    store.data_mut().insert_file(
+        HEALTH_FD,
+        Box::new(WasiUnixStream::from_cap_std(report_client)),
+        FileCaps::all(),
+    );
+
+    let deadline = Instant::now() + HEALTH_CHECK_TIMEOUT;
+    store.set_epoch_deadline(1);
+    store.epoch_deadline_callback(move |_| {
+        if Instant::now() >= deadline {
+            Err(anyhow!("health check took too long"))
+        } else {
+            Ok(1)
+        }
+    });
+
+    let health = linker
+        .get(&mut store, "axum", HEALTH_EXPORT)
+        .context("health export should be available")?
+        .into_func()
+        .context("health export should be a function")?
+        .typed::<RawFd, ()>(&store)?;
+    health.call(&mut store, HEALTH_FD as RawFd)?;
+
+    // Dropping the store closes the guest's end, so the report ends where the guest stopped
+    drop(store);
+
+    let mut report = Vec::new();
+    (&mut report_stream)
+        .take(MAX_REPORT_SIZE)
+        .read_to_end(&mut report)
+        .context("failed to read the health report")?;
+
+    Ok(HealthReport::from_guest(&report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn worst_status_wins() {
+        let report = HealthReport::from_guest(
+            br#"{"checks": {"db": {"status": "healthy"}, "cache": {"status": "degraded", "detail": "slow"}}}"#,
+        );
+        assert_eq!(report.status, HealthStatus::Degraded);
+        assert_eq!(report.checks["cache"].detail.as_deref(), Some("slow"));
+
+        let report = HealthReport::from_guest(
+            br#"{"status": "unhealthy", "checks": {"db": {"status": "healthy"}}}"#,
+        );
+        assert_eq!(report.status, HealthStatus::Unhealthy);
+
+        assert_eq!(HealthReport::from_guest(b"{}"), HealthReport::alive());
+        assert_eq!(
+            HealthReport::from_guest(b"not json").status,
+            HealthStatus::Unhealthy
+        );
+    }
+
+    #[tokio::test]
+    async fn response() {
+        let response = HealthReport::from_guest(
+            br#"{"checks": {"db": {"status": "unhealthy", "detail": "connection refused"}}}"#,
+        )
+        .into_response(false);
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "status": "unhealthy",
+                "checks": {"db": {"status": "unhealthy", "detail": "connection refused"}},
+            })
+        );
+
+        let response = HealthReport::alive().into_response(true);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
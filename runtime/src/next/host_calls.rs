@@ -0,0 +1,110 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::bail;
+use wasmtime::{CallHook, Store};
+
+/// Counts the host calls, like WASI functions, a guest makes while it handles one request. With
+/// a limit the guest is trapped once it makes more calls than that.
+pub(crate) struct HostCalls {
+    count: Arc<AtomicU64>,
+    limit: Option<u64>,
+}
+
+impl HostCalls {
+    pub(crate) fn new(limit: Option<u64>) -> Self {
+        Self {
+            count: Default::default(),
+            limit,
+        }
+    }
+
+    /// Count the host calls the guest in `store` makes from now on
+    pub(crate) fn watch<T>(&self, store: &mut Store<T>) {
+        let count = self.count.clone();
+        let limit = self.limit;
+
+        store.call_hook(move |_, hook| {
+            if matches!(hook, CallHook::CallingHost) {
+                let count = count.fetch_add(1, Ordering::Relaxed) + 1;
+
+                if let Some(limit) = limit.filter(|limit| count > *limit) {
+                    bail!("guest made more than {limit} host calls");
+                }
+            }
+
+            Ok(())
+        });
+    }
+
+    pub(crate) fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Whether the guest was trapped for making too many host calls
+    pub(crate) fn exceeded(&self) -> bool {
+        self.limit.is_some_and(|limit| self.count() > limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmtime::{Engine, Linker, Module};
+
+    use super::*;
+
+    fn run(host_calls: &HostCalls, calls: i32) -> anyhow::Result<()> {
+        let engine = Engine::default();
+        let module = Module::new(
+            &engine,
+            r#"(module
+                (import "host" "ping" (func $ping))
+                (func (export "run") (param $calls i32)
+                    (loop $again
+                        (if (local.get $calls)
+                            (then
+                                (call $ping)
+                                (local.set $calls (i32.sub (local.get $calls) (i32.const 1)))
+                                (br $again))))))"#,
+        )
+        .unwrap();
+
+        let mut linker = Linker::new(&engine);
+        linker.func_wrap("host", "ping", || {}).unwrap();
+
+        let mut store = Store::new(&engine, ());
+        host_calls.watch(&mut store);
+
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+        let run = instance
+            .get_typed_func::<i32, ()>(&mut store, "run")
+            .unwrap();
+
+        run.call(&mut store, calls)
+    }
+
+    #[test]
+    fn counts_calls() {
+        let host_calls = HostCalls::new(None);
+
+        run(&host_calls, 5).unwrap();
+        assert_eq!(host_calls.count(), 5);
+        assert!(!host_calls.exceeded());
+    }
+
+    #[test]
+    fn limit() {
+        let host_calls = HostCalls::new(Some(3));
+        run(&host_calls, 3).unwrap();
+        assert!(!host_calls.exceeded());
+
+        let host_calls = HostCalls::new(Some(3));
+        let error = run(&host_calls, 10).unwrap_err();
+        assert!(host_calls.exceeded());
+        assert_eq!(host_calls.count(), 4);
+        assert!(
+            format!("{error:?}").contains("more than 3 host calls"),
+            "{error:?}"
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use std::convert::Infallible;
+use std::net::TcpListener;
+use std::sync::Arc;
+
+use hyper::header::{self, HeaderValue};
+use hyper::http::uri::{Authority, Scheme};
+use hyper::http::StatusCode;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Uri};
+use tracing::error;
+
+const DEFAULT_HTTPS_PORT: u16 = 443;
+
+/// A plain HTTP listener which redirects every request to the same URL over HTTPS, without
+/// calling the guest
+#[derive(Clone, Debug)]
+pub(crate) struct HttpsRedirect {
+    /// Port to listen for plain HTTP on
+    pub port: u16,
+    /// Port HTTPS is served on, which is left out of the redirect when it is the default
+    pub https_port: u16,
+    pub status: StatusCode,
+}
+
+impl HttpsRedirect {
+    /// Get the redirect to HTTPS for `req`. Requests without a valid host cannot be redirected.
+    pub(crate) fn respond<B>(&self, req: &Request<B>) -> Response<Body> {
+        let Some(location) = self.location(req) else {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::empty())
+                .expect("building a bad request response should not fail");
+        };
+
+        Response::builder()
+            .status(self.status)
+            .header(header::LOCATION, location)
+            .body(Body::empty())
+            .expect("building a redirect response should not fail")
+    }
+
+    fn location<B>(&self, req: &Request<B>) -> Option<HeaderValue> {
+        let authority = match req.headers().get(header::HOST) {
+            Some(host) => host.to_str().ok()?.parse::<Authority>().ok()?,
+            None => req.uri().authority()?.clone(),
+        };
+
+        let authority = if self.https_port == DEFAULT_HTTPS_PORT {
+            authority.host().parse().ok()?
+        } else {
+            format!("{}:{}", authority.host(), self.https_port)
+                .parse()
+                .ok()?
+        };
+
+        let mut location = Uri::builder().scheme(Scheme::HTTPS).authority(authority);
+        if let Some(path_and_query) = req.uri().path_and_query() {
+            location = location.path_and_query(path_and_query.clone());
+        }
+
+        let location = location.build().ok()?;
+
+        HeaderValue::from_str(&location.to_string()).ok()
+    }
+
+    /// Serve the redirects on `listener` until the returned future is dropped
+    pub(crate) async fn serve(self, listener: TcpListener) {
+        let server = match hyper::Server::from_tcp(listener) {
+            Ok(server) => server,
+            Err(error) => {
+                error!(%error, "failed to serve https redirects on the bound listener");
+                return;
+            }
+        };
+
+        let redirect = Arc::new(self);
+        let make_service = make_service_fn(move |_conn| {
+            let redirect = redirect.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let response = redirect.respond(&req);
+                    async move { Ok::<_, Infallible>(response) }
+                }))
+            }
+        });
+
+        if let Err(error) = server.serve(make_service).await {
+            error!(%error, "https redirect server failed");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    fn redirect(https_port: u16) -> HttpsRedirect {
+        HttpsRedirect {
+            port: 0,
+            https_port,
+            status: StatusCode::PERMANENT_REDIRECT,
+        }
+    }
+
+    fn location(redirect: &HttpsRedirect, req: Request<()>) -> Option<String> {
+        let res = redirect.respond(&req);
+        res.headers()
+            .get(header::LOCATION)
+            .map(|location| location.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn respond() {
+        let req = Request::get("/search?q=wasm")
+            .header(header::HOST, "example.com:8080")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            location(&redirect(443), req).as_deref(),
+            Some("https://example.com/search?q=wasm")
+        );
+
+        let req = Request::get("http://example.com/").body(()).unwrap();
+        assert_eq!(
+            location(&redirect(8443), req).as_deref(),
+            Some("https://example.com:8443/")
+        );
+
+        // Without a host there is nowhere to redirect to
+        let req = Request::get("/").body(()).unwrap();
+        assert_eq!(
+            redirect(443).respond(&req).status(),
+            StatusCode::BAD_REQUEST
+        );
+
+        let req = Request::get("/")
+            .header(header::HOST, "exa mple.com")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            redirect(443).respond(&req).status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(
+            HttpsRedirect {
+                port: address.port(),
+                https_port: 443,
+                status: StatusCode::MOVED_PERMANENTLY,
+            }
+            .serve(listener),
+        );
+
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(b"GET /login HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(
+            response.starts_with("HTTP/1.1 301 Moved Permanently\r\n"),
+            "{response}"
+        );
+        assert!(
+            response.contains("location: https://example.com/login\r\n"),
+            "{response}"
+        );
+
+        server.abort();
+    }
+}
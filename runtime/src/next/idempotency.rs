@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper::{Method, Request};
+use tokio::time::Instant;
+
+use super::coalesce::SharedResponse;
+
+// Longer keys are not tracked, so clients cannot make the store hold arbitrarily large keys
+const MAX_KEY_LEN: usize = 255;
+
+/// The `Idempotency-Key` of a request, with what the request was for
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct IdempotencyKey {
+    key: String,
+    fingerprint: (Method, String),
+}
+
+impl IdempotencyKey {
+    /// Get the idempotency key of a request, if it has one and uses a method which is not safe
+    /// to repeat
+    pub(crate) fn for_request<B>(req: &Request<B>) -> Option<Self> {
+        if req.method().is_safe() {
+            return None;
+        }
+
+        let key = req.headers().get("idempotency-key")?.to_str().ok()?.trim();
+        if key.is_empty() || key.len() > MAX_KEY_LEN {
+            return None;
+        }
+
+        Some(Self {
+            key: key.to_string(),
+            fingerprint: (req.method().clone(), req.uri().to_string()),
+        })
+    }
+}
+
+/// The responses to requests with an idempotency key, so a retried request gets the response of
+/// the first one instead of the guest handling it again. A store belongs to the router of one
+/// deployment, so keys never carry over to another deployment.
+pub(crate) struct IdempotencyStore {
+    ttl: Duration,
+    max_keys: usize,
+    entries: Mutex<HashMap<String, Entry>>,
+    next_id: AtomicU64,
+}
+
+struct Entry {
+    fingerprint: (Method, String),
+    state: State,
+}
+
+enum State {
+    InFlight(u64),
+    Done {
+        response: Arc<SharedResponse>,
+        expires_at: Instant,
+    },
+}
+
+impl Entry {
+    fn is_expired(&self, now: Instant) -> bool {
+        matches!(self.state, State::Done { expires_at, .. } if expires_at <= now)
+    }
+}
+
+/// What to do with a request which has an idempotency key
+pub(crate) enum Idempotency {
+    /// The key is new, so the request should be handled and its response recorded
+    New(IdempotencyGuard),
+    /// The request is a retry which should get this recorded response
+    Replay(Arc<SharedResponse>),
+    /// A request with the same key is still being handled
+    InFlight,
+    /// The key was used for a request with another method or uri
+    Reused,
+    /// The store is full of requests being handled, so this one is handled without its key
+    Untracked,
+}
+
+impl IdempotencyStore {
+    /// Keep responses for `ttl`, for at most `max_keys` keys at a time
+    pub(crate) fn new(ttl: Duration, max_keys: usize) -> Self {
+        Self {
+            ttl,
+            max_keys: max_keys.max(1),
+            entries: Default::default(),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Look up `key` for a request, and claim it when it is new
+    pub(crate) fn begin(self: &Arc<Self>, key: IdempotencyKey) -> Idempotency {
+        let now = Instant::now();
+        let mut entries = self
+            .entries
+            .lock()
+            .expect("idempotency store lock should not be poisoned");
+
+        if let Some(entry) = entries.get(&key.key).filter(|entry| !entry.is_expired(now)) {
+            if entry.fingerprint != key.fingerprint {
+                return Idempotency::Reused;
+            }
+
+            return match &entry.state {
+                State::InFlight(_) => Idempotency::InFlight,
+                State::Done { response, .. } => Idempotency::Replay(response.clone()),
+            };
+        }
+
+        if entries.len() >= self.max_keys && !entries.contains_key(&key.key) {
+            entries.retain(|_, entry| !entry.is_expired(now));
+        }
+
+        if entries.len() >= self.max_keys && !entries.contains_key(&key.key) {
+            // Forget the response which would expire first
+            let oldest = entries
+                .iter()
+                .filter_map(|(key, entry)| match entry.state {
+                    State::Done { expires_at, .. } => Some((expires_at, key)),
+                    State::InFlight(_) => None,
+                })
+                .min()
+                .map(|(_, key)| key.clone());
+
+            match oldest {
+                Some(oldest) => entries.remove(&oldest),
+                None => return Idempotency::Untracked,
+            };
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        entries.insert(
+            key.key.clone(),
+            Entry {
+                fingerprint: key.fingerprint,
+                state: State::InFlight(id),
+            },
+        );
+
+        Idempotency::New(IdempotencyGuard {
+            store: self.clone(),
+            key: key.key,
+            id,
+        })
+    }
+}
+
+/// A claimed key. The key is released again when this is dropped without a response, so a
+/// retry is handled anew.
+pub(crate) struct IdempotencyGuard {
+    store: Arc<IdempotencyStore>,
+    key: String,
+    id: u64,
+}
+
+impl IdempotencyGuard {
+    /// Record `response` for retries of the request
+    pub(crate) fn finish(self, response: Arc<SharedResponse>) {
+        let mut entries = self
+            .store
+            .entries
+            .lock()
+            .expect("idempotency store lock should not be poisoned");
+
+        if let Some(entry) = entries.get_mut(&self.key) {
+            if matches!(entry.state, State::InFlight(id) if id == self.id) {
+                entry.state = State::Done {
+                    response,
+                    expires_at: Instant::now() + self.store.ttl,
+                };
+            }
+        }
+    }
+}
+
+impl Drop for IdempotencyGuard {
+    fn drop(&mut self) {
+        let mut entries = self
+            .store
+            .entries
+            .lock()
+            .expect("idempotency store lock should not be poisoned");
+
+        if entries
+            .get(&self.key)
+            .is_some_and(|entry| matches!(entry.state, State::InFlight(id) if id == self.id))
+        {
+            entries.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Body, Response};
+
+    use super::*;
+
+    fn key(method: Method, uri: &str, key: &str) -> IdempotencyKey {
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("idempotency-key", key)
+            .body(())
+            .unwrap();
+
+        IdempotencyKey::for_request(&req).unwrap()
+    }
+
+    async fn response(body: &'static str) -> Arc<SharedResponse> {
+        Arc::new(
+            SharedResponse::buffer(Response::new(Body::from(body)))
+                .await
+                .unwrap(),
+        )
+    }
+
+    async fn body(response: &SharedResponse) -> hyper::body::Bytes {
+        hyper::body::to_bytes(response.to_response().into_body())
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn keys() {
+        let req = |method: Method, key: &str| {
+            Request::builder()
+                .method(method)
+                .uri("/orders")
+                .header("idempotency-key", key)
+                .body(())
+                .unwrap()
+        };
+
+        assert!(IdempotencyKey::for_request(&req(Method::POST, "order-1")).is_some());
+        assert!(IdempotencyKey::for_request(&req(Method::DELETE, "order-1")).is_some());
+        assert!(IdempotencyKey::for_request(&req(Method::GET, "order-1")).is_none());
+        assert!(IdempotencyKey::for_request(&req(Method::POST, " ")).is_none());
+        assert!(IdempotencyKey::for_request(&req(Method::POST, &"k".repeat(256))).is_none());
+        assert!(IdempotencyKey::for_request(&Request::post("/orders").body(()).unwrap()).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replay() {
+        let store = Arc::new(IdempotencyStore::new(Duration::from_secs(60), 16));
+
+        let Idempotency::New(guard) = store.begin(key(Method::POST, "/orders", "order-1")) else {
+            panic!("first request should claim the key");
+        };
+        assert!(matches!(
+            store.begin(key(Method::POST, "/orders", "order-1")),
+            Idempotency::InFlight
+        ));
+
+        guard.finish(response("created").await);
+
+        let Idempotency::Replay(replayed) = store.begin(key(Method::POST, "/orders", "order-1"))
+        else {
+            panic!("retry should be replayed");
+        };
+        assert_eq!(body(&replayed).await, "created");
+
+        assert!(matches!(
+            store.begin(key(Method::POST, "/payments", "order-1")),
+            Idempotency::Reused
+        ));
+
+        // Expired responses are not replayed
+        tokio::time::advance(Duration::from_secs(61)).await;
+        assert!(matches!(
+            store.begin(key(Method::POST, "/orders", "order-1")),
+            Idempotency::New(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn dropped_requests_release_their_key() {
+        let store = Arc::new(IdempotencyStore::new(Duration::from_secs(60), 16));
+
+        let first = store.begin(key(Method::POST, "/orders", "order-1"));
+        assert!(matches!(first, Idempotency::New(_)));
+        drop(first);
+
+        assert!(matches!(
+            store.begin(key(Method::POST, "/orders", "order-1")),
+            Idempotency::New(_)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bounded() {
+        let store = Arc::new(IdempotencyStore::new(Duration::from_secs(60), 2));
+
+        for name in ["order-1", "order-2"] {
+            let Idempotency::New(guard) = store.begin(key(Method::POST, "/orders", name)) else {
+                panic!("{name} should be new");
+            };
+            guard.finish(response(name).await);
+            tokio::time::advance(Duration::from_secs(1)).await;
+        }
+
+        // The oldest response makes room for a new key
+        let third = store.begin(key(Method::POST, "/orders", "order-3"));
+        assert!(matches!(third, Idempotency::New(_)));
+        assert!(matches!(
+            store.begin(key(Method::POST, "/orders", "order-2")),
+            Idempotency::Replay(_)
+        ));
+
+        // Requests being handled are never forgotten
+        let _fourth = store.begin(key(Method::POST, "/orders", "order-4"));
+        assert!(matches!(
+            store.begin(key(Method::POST, "/orders", "order-5")),
+            Idempotency::Untracked
+        ));
+        assert!(matches!(
+            store.begin(key(Method::POST, "/orders", "order-1")),
+            Idempotency::Untracked
+        ));
+    }
+}
@@ -0,0 +1,124 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+// Requests wait this long for an instance slot by default before they are shed
+const DEFAULT_MAX_WAIT: Duration = Duration::from_secs(1);
+
+/// How fast new instances of the module can be created for requests
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct InstanceRate {
+    /// Instances created per second on average, or no limit with `None`
+    pub per_second: Option<u32>,
+    /// Instances which can be created at once after a quiet period
+    pub burst: u32,
+    /// Longest a request waits for its turn to create an instance
+    pub max_wait: Duration,
+}
+
+impl Default for InstanceRate {
+    fn default() -> Self {
+        Self {
+            per_second: None,
+            burst: 1,
+            max_wait: DEFAULT_MAX_WAIT,
+        }
+    }
+}
+
+/// Spaces out the creation of instances to their rate, letting a burst through at once. This
+/// is a generic cell rate algorithm, which tracks when the next instance would be due if they
+/// were all created evenly spaced.
+pub(crate) struct InstanceRateLimiter {
+    interval: Duration,
+    tolerance: Duration,
+    max_wait: Duration,
+    due: Mutex<Instant>,
+}
+
+impl InstanceRateLimiter {
+    pub(crate) fn new(rate: &InstanceRate) -> Option<Self> {
+        let per_second = rate.per_second?.max(1);
+        let interval = Duration::from_secs(1) / per_second;
+
+        Some(Self {
+            interval,
+            tolerance: interval * rate.burst.max(1).saturating_sub(1),
+            max_wait: rate.max_wait,
+            due: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// Wait for the turn to create an instance. Returns `false` without taking a turn when it
+    /// would be more than the max wait away.
+    pub(crate) async fn acquire(&self) -> bool {
+        let ready_at = {
+            let mut due = self
+                .due
+                .lock()
+                .expect("instance rate lock should not be poisoned");
+            let now = Instant::now();
+            let next = (*due).max(now);
+            let ready_at = next
+                .checked_sub(self.tolerance)
+                .map_or(now, |ready_at| ready_at.max(now));
+
+            if ready_at - now > self.max_wait {
+                return false;
+            }
+
+            *due = next + self.interval;
+            ready_at
+        };
+
+        tokio::time::sleep_until(ready_at).await;
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn spaces_out_instances_after_a_burst() {
+        let limiter = InstanceRateLimiter::new(&InstanceRate {
+            per_second: Some(10),
+            burst: 3,
+            max_wait: Duration::from_millis(250),
+        })
+        .unwrap();
+        let start = Instant::now();
+
+        // The burst is let through at once
+        for _ in 0..3 {
+            assert!(limiter.acquire().await);
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // After that, instances come every 100ms
+        assert!(limiter.acquire().await);
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+        assert!(limiter.acquire().await);
+        assert_eq!(start.elapsed(), Duration::from_millis(200));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_after_the_max_wait() {
+        let limiter = InstanceRateLimiter::new(&InstanceRate {
+            per_second: Some(10),
+            burst: 1,
+            max_wait: Duration::from_millis(150),
+        })
+        .unwrap();
+
+        // Concurrent requests take turns 100ms apart, so the third one would wait 200ms
+        let turns = tokio::join!(limiter.acquire(), limiter.acquire(), limiter.acquire());
+        let taken = [turns.0, turns.1, turns.2];
+        assert_eq!(taken.iter().filter(|taken| **taken).count(), 2);
+
+        assert!(InstanceRateLimiter::new(&InstanceRate::default()).is_none());
+    }
+}
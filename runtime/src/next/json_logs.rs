@@ -0,0 +1,136 @@
+use std::io::Write;
+
+use chrono::{SecondsFormat, TimeZone, Utc};
+use serde::Serialize;
+use shuttle_proto::runtime::{LogItem, LogLevel};
+
+/// A forwarded log in the schema of the JSON lines, which collectors can rely on. Every field
+/// is always present, with `null` when a log does not have it.
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    timestamp: Option<String>,
+    level: &'static str,
+    deployment_id: &'a str,
+    request_id: Option<u64>,
+    target: &'a str,
+    message: Option<String>,
+    fields: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Serialize `log` of the deployment with `deployment_id` as a single JSON line
+pub(crate) fn json_line(deployment_id: &str, log: &LogItem) -> String {
+    let mut fields =
+        serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(&log.fields)
+            .unwrap_or_default();
+
+    let message = match fields.remove("message") {
+        Some(serde_json::Value::String(message)) => Some(message),
+        Some(message) => Some(message.to_string()),
+        // Fields which are not a JSON object are kept whole as the message
+        None if fields.is_empty() && !log.fields.is_empty() => {
+            Some(String::from_utf8_lossy(&log.fields).into_owned())
+        }
+        None => None,
+    };
+
+    let request_id = fields
+        .remove("request_id")
+        .and_then(|request_id| request_id.as_u64());
+
+    let timestamp = log.timestamp.as_ref().and_then(|timestamp| {
+        let timestamp = Utc
+            .timestamp_opt(timestamp.seconds, timestamp.nanos.try_into().ok()?)
+            .single()?;
+
+        Some(timestamp.to_rfc3339_opts(SecondsFormat::Micros, true))
+    });
+
+    let level = match LogLevel::from_i32(log.level) {
+        Some(LogLevel::Trace) => "trace",
+        Some(LogLevel::Debug) => "debug",
+        Some(LogLevel::Info) | None => "info",
+        Some(LogLevel::Warn) => "warn",
+        Some(LogLevel::Error) => "error",
+    };
+
+    let line = JsonLine {
+        timestamp,
+        level,
+        deployment_id,
+        request_id,
+        target: &log.target,
+        message,
+        fields,
+    };
+
+    serde_json::to_string(&line).expect("json lines should serialize")
+}
+
+/// Write `log` to stdout as a JSON line
+pub(crate) fn write_json_line(deployment_id: &str, log: &LogItem) {
+    let line = json_line(deployment_id, log);
+
+    // Logs are best effort, and a closed stdout should not fail requests
+    let _ = writeln!(std::io::stdout().lock(), "{line}");
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_types::Timestamp;
+
+    use super::*;
+
+    #[test]
+    fn schema() {
+        let log = LogItem {
+            timestamp: Some(Timestamp {
+                seconds: 1_700_000_000,
+                nanos: 5_000,
+            }),
+            level: LogLevel::Warn as i32,
+            file: Some("src/lib.rs".to_string()),
+            line: Some(7),
+            target: "shuttle_next".to_string(),
+            fields: br#"{"message":"slow request","request_id":3,"seq":0,"path":"/"}"#.to_vec(),
+        };
+
+        let line: serde_json::Value = serde_json::from_str(&json_line("my-service", &log)).unwrap();
+        assert_eq!(
+            line,
+            serde_json::json!({
+                "timestamp": "2023-11-14T22:13:20.000005Z",
+                "level": "warn",
+                "deployment_id": "my-service",
+                "request_id": 3,
+                "target": "shuttle_next",
+                "message": "slow request",
+                "fields": {"seq": 0, "path": "/"},
+            })
+        );
+    }
+
+    #[test]
+    fn missing_parts_are_null() {
+        let line: serde_json::Value =
+            serde_json::from_str(&json_line("my-service", &LogItem::default())).unwrap();
+        assert_eq!(
+            line,
+            serde_json::json!({
+                "timestamp": null,
+                "level": "trace",
+                "deployment_id": "my-service",
+                "request_id": null,
+                "target": "",
+                "message": null,
+                "fields": {},
+            })
+        );
+
+        let log = LogItem {
+            fields: b"not json".to_vec(),
+            ..Default::default()
+        };
+        let line: serde_json::Value = serde_json::from_str(&json_line("", &log)).unwrap();
+        assert_eq!(line["message"], "not json");
+    }
+}
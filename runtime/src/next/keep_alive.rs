@@ -0,0 +1,503 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use futures::StreamExt;
+use hyper::body::HttpBody;
+use hyper::header::{self, HeaderValue};
+use hyper::server::accept::Accept;
+use hyper::{Body, Response};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{Instant, Sleep};
+use tracing::{error, warn};
+
+// Idle connections are closed after this long by default, like most HTTP servers do
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(75);
+
+// How long to wait before accepting again after accepting failed, like when the process is out
+// of file descriptors
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How connections are kept open for more requests
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct KeepAlive {
+    pub enabled: bool,
+    /// Close connections after they served this many requests
+    pub max_requests: Option<u64>,
+    /// Close connections which have been idle between requests for this long
+    pub idle_timeout: Option<Duration>,
+    /// Close connections whose current request has taken this long, from reading its body
+    /// until its response is sent
+    pub max_request_duration: Option<Duration>,
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_requests: None,
+            idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+            max_request_duration: None,
+        }
+    }
+}
+
+/// The connections of a listener, which are closed once they have been idle for the idle
+/// timeout, or once their current request has taken longer than the max request duration
+pub(crate) struct Incoming {
+    listener: TcpListener,
+    idle_timeout: Option<Duration>,
+    max_request_duration: Option<Duration>,
+    backoff: Option<Pin<Box<Sleep>>>,
+}
+
+impl Incoming {
+    pub(crate) fn new(
+        listener: std::net::TcpListener,
+        idle_timeout: Option<Duration>,
+        max_request_duration: Option<Duration>,
+    ) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+
+        Ok(Self {
+            listener: TcpListener::from_std(listener)?,
+            idle_timeout,
+            max_request_duration,
+            backoff: None,
+        })
+    }
+}
+
+impl Accept for Incoming {
+    type Conn = Connection;
+    type Error = io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let this = self.get_mut();
+
+        loop {
+            if let Some(backoff) = &mut this.backoff {
+                ready!(backoff.as_mut().poll(cx));
+                this.backoff = None;
+            }
+
+            match ready!(this.listener.poll_accept(cx)) {
+                Ok((stream, _)) => {
+                    return Poll::Ready(Some(Ok(Connection::new(
+                        stream,
+                        this.idle_timeout,
+                        this.max_request_duration,
+                    ))));
+                }
+                // Failing to accept one connection should not stop the server
+                Err(error) => {
+                    error!(%error, "failed to accept a connection");
+                    this.backoff = Some(Box::pin(tokio::time::sleep(ACCEPT_ERROR_BACKOFF)));
+                }
+            }
+        }
+    }
+}
+
+/// What the service of a connection shares with the connection
+pub(crate) struct ConnectionState {
+    requests: AtomicU64,
+    in_flight: AtomicUsize,
+    last_active: Mutex<Instant>,
+    max_request_duration: Option<Duration>,
+    request_deadline: Mutex<Option<Instant>>,
+}
+
+impl ConnectionState {
+    /// Start handling a request on this connection, which keeps it from being idle until the
+    /// returned request is dropped
+    pub(crate) fn begin_request(self: &Arc<Self>) -> ActiveRequest {
+        if let Some(max_request_duration) = self.max_request_duration {
+            *self
+                .request_deadline
+                .lock()
+                .expect("connection state lock should not be poisoned") =
+                Some(Instant::now() + max_request_duration);
+        }
+
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        let number = self.requests.fetch_add(1, Ordering::Relaxed) + 1;
+
+        ActiveRequest {
+            connection: self.clone(),
+            number,
+        }
+    }
+
+    fn touch(&self) {
+        *self
+            .last_active
+            .lock()
+            .expect("connection state lock should not be poisoned") = Instant::now();
+    }
+
+    fn last_active(&self) -> Instant {
+        *self
+            .last_active
+            .lock()
+            .expect("connection state lock should not be poisoned")
+    }
+
+    fn request_deadline(&self) -> Option<Instant> {
+        *self
+            .request_deadline
+            .lock()
+            .expect("connection state lock should not be poisoned")
+    }
+}
+
+/// A request being handled on a connection
+pub(crate) struct ActiveRequest {
+    connection: Arc<ConnectionState>,
+    number: u64,
+}
+
+impl ActiveRequest {
+    /// How many requests the connection got up to and including this one
+    pub(crate) fn number(&self) -> u64 {
+        self.number
+    }
+
+    /// Keep the request active until the body of its `response` is sent, so the max request
+    /// duration also covers sending it
+    pub(crate) fn hold_until_sent(self, response: Response<Body>) -> Response<Body> {
+        if self.connection.max_request_duration.is_none() {
+            return response;
+        }
+
+        let (mut parts, body) = response.into_parts();
+        match body.size_hint().exact() {
+            Some(0) => return Response::from_parts(parts, body),
+            // The wrapped body has no size, so keep the length hyper would have sent
+            Some(length) => {
+                parts
+                    .headers
+                    .entry(header::CONTENT_LENGTH)
+                    .or_insert_with(|| HeaderValue::from(length));
+            }
+            None => {}
+        }
+
+        let body = Body::wrap_stream(body.map(move |chunk| {
+            let _request = &self;
+            chunk
+        }));
+
+        Response::from_parts(parts, body)
+    }
+}
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        self.connection.touch();
+        self.connection.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// An accepted connection, which reads as closed once it has been idle for the idle timeout,
+/// and fails once its current request has taken longer than the max request duration
+pub(crate) struct Connection {
+    stream: TcpStream,
+    state: Arc<ConnectionState>,
+    idle_timeout: Option<Duration>,
+    idle: Option<Pin<Box<Sleep>>>,
+    request_timer: Option<Pin<Box<Sleep>>>,
+    request_expired: bool,
+}
+
+impl Connection {
+    fn new(
+        stream: TcpStream,
+        idle_timeout: Option<Duration>,
+        max_request_duration: Option<Duration>,
+    ) -> Self {
+        Self {
+            stream,
+            state: Arc::new(ConnectionState {
+                requests: AtomicU64::new(0),
+                in_flight: AtomicUsize::new(0),
+                last_active: Mutex::new(Instant::now()),
+                max_request_duration,
+                request_deadline: Mutex::new(None),
+            }),
+            idle_timeout,
+            idle: None,
+            request_timer: None,
+            request_expired: false,
+        }
+    }
+
+    pub(crate) fn state(&self) -> Arc<ConnectionState> {
+        self.state.clone()
+    }
+
+    /// Whether the connection has been idle for too long. Nothing is idle while a request is
+    /// being handled, and the idle time restarts with every read or write.
+    fn poll_idle_expired(&mut self, cx: &mut Context<'_>) -> bool {
+        let Some(idle_timeout) = self.idle_timeout else {
+            return false;
+        };
+
+        if self.state.in_flight.load(Ordering::Relaxed) > 0 {
+            return false;
+        }
+
+        let deadline = self.state.last_active() + idle_timeout;
+        let idle = self
+            .idle
+            .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(deadline)));
+        if idle.deadline() != deadline {
+            idle.as_mut().reset(deadline);
+        }
+
+        idle.as_mut().poll(cx).is_ready()
+    }
+
+    /// Fail once the current request has taken longer than the max request duration, which
+    /// makes hyper close the connection. This bounds clients which send their body or read the
+    /// response slowly on purpose to hold on to the connection.
+    fn poll_request_expired(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        if !self.request_expired && self.state.in_flight.load(Ordering::Relaxed) == 0 {
+            return Ok(());
+        }
+
+        let Some(deadline) = self.state.request_deadline() else {
+            return Ok(());
+        };
+
+        let timer = self
+            .request_timer
+            .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(deadline)));
+        if timer.deadline() != deadline {
+            timer.as_mut().reset(deadline);
+        }
+
+        if !self.request_expired && timer.as_mut().poll(cx).is_pending() {
+            return Ok(());
+        }
+
+        // Only warn once, since hyper may poll the connection again before closing it
+        if !self.request_expired {
+            self.request_expired = true;
+            warn!(
+                request = self.state.requests.load(Ordering::Relaxed),
+                max_duration = ?self.state.max_request_duration,
+                "closing a connection whose request took too long"
+            );
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "request took longer than the max request duration",
+        ))
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if let Err(error) = this.poll_request_expired(cx) {
+            return Poll::Ready(Err(error));
+        }
+
+        // Reading nothing is the end of the stream, after which hyper closes the connection
+        if this.poll_idle_expired(cx) {
+            return Poll::Ready(Ok(()));
+        }
+
+        let filled = buf.filled().len();
+        let read = Pin::new(&mut this.stream).poll_read(cx, buf);
+        if buf.filled().len() > filled {
+            this.state.touch();
+        }
+
+        read
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        if let Err(error) = this.poll_request_expired(cx) {
+            return Poll::Ready(Err(error));
+        }
+
+        let written = Pin::new(&mut this.stream).poll_write(cx, buf);
+        if matches!(written, Poll::Ready(Ok(written)) if written > 0) {
+            this.state.touch();
+        }
+
+        written
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        if let Err(error) = this.poll_request_expired(cx) {
+            return Poll::Ready(Err(error));
+        }
+
+        let written = Pin::new(&mut this.stream).poll_write_vectored(cx, bufs);
+        if matches!(written, Poll::Ready(Ok(written)) if written > 0) {
+            this.state.touch();
+        }
+
+        written
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if let Err(error) = this.poll_request_expired(cx) {
+            return Poll::Ready(Err(error));
+        }
+
+        Pin::new(&mut this.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn idle_connections_are_closed() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let incoming = Incoming::new(listener, Some(Duration::from_millis(200)), None).unwrap();
+
+        let make_service = make_service_fn(|conn: &Connection| {
+            let state = conn.state();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |_: Request<Body>| {
+                    let request = state.begin_request();
+                    async move {
+                        // A slow request does not count as idle time
+                        tokio::time::sleep(Duration::from_millis(400)).await;
+
+                        Ok::<_, Infallible>(Response::new(Body::from(request.number().to_string())))
+                    }
+                }))
+            }
+        });
+        let server = tokio::spawn(hyper::Server::builder(incoming).serve(make_service));
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        for number in 1..=2 {
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+                .await
+                .unwrap();
+
+            let mut response = vec![0; 1024];
+            let read = stream.read(&mut response).await.unwrap();
+            let response = String::from_utf8_lossy(&response[..read]);
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+            assert!(response.ends_with(&number.to_string()), "{response}");
+        }
+
+        // Once idle for the timeout, the server closes the connection
+        let closed = tokio::time::timeout(Duration::from_secs(5), async {
+            let mut rest = Vec::new();
+            stream.read_to_end(&mut rest).await.unwrap();
+            rest
+        })
+        .await
+        .expect("idle connection should be closed");
+        assert!(closed.is_empty());
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn slow_requests_are_closed() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let incoming = Incoming::new(listener, None, Some(Duration::from_millis(200))).unwrap();
+
+        let make_service = make_service_fn(|conn: &Connection| {
+            let state = conn.state();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let request = state.begin_request();
+                    async move {
+                        let body = hyper::body::to_bytes(req.into_body()).await?;
+
+                        Ok::<_, hyper::Error>(request.hold_until_sent(Response::new(body.into())))
+                    }
+                }))
+            }
+        });
+        let server = tokio::spawn(hyper::Server::builder(incoming).serve(make_service));
+
+        // A quick request is answered
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(b"POST / HTTP/1.1\r\nHost: example.com\r\nContent-Length: 2\r\n\r\nhi")
+            .await
+            .unwrap();
+        let mut response = vec![0; 1024];
+        let read = stream.read(&mut response).await.unwrap();
+        let response = String::from_utf8_lossy(&response[..read]);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.ends_with("hi"), "{response}");
+
+        // A body which never finishes arriving is cut off at the max request duration
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(b"POST / HTTP/1.1\r\nHost: example.com\r\nContent-Length: 10\r\n\r\nhi")
+            .await
+            .unwrap();
+        let closed = tokio::time::timeout(Duration::from_secs(5), async {
+            let mut rest = Vec::new();
+            stream.read_to_end(&mut rest).await.unwrap();
+            rest
+        })
+        .await
+        .expect("slow request should be closed");
+        assert!(closed.is_empty());
+
+        server.abort();
+    }
+}
@@ -0,0 +1,74 @@
+use std::net::{SocketAddr, TcpListener};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+// Enough queued connections to ride out bursts of new clients
+const DEFAULT_BACKLOG: u32 = 1024;
+
+/// How the socket a service is served on gets bound
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ListenerOptions {
+    pub backlog: u32,
+    pub reuse_address: bool,
+    pub reuse_port: bool,
+}
+
+impl Default for ListenerOptions {
+    fn default() -> Self {
+        Self {
+            backlog: DEFAULT_BACKLOG,
+            // Lets a restarted service rebind while connections of the old one are in TIME_WAIT
+            reuse_address: true,
+            reuse_port: false,
+        }
+    }
+}
+
+impl ListenerOptions {
+    /// Bind a non-blocking listener to `address` with these options
+    pub(crate) fn bind(&self, address: SocketAddr) -> std::io::Result<TcpListener> {
+        let socket = Socket::new(
+            Domain::for_address(address),
+            Type::STREAM,
+            Some(Protocol::TCP),
+        )?;
+
+        socket.set_reuse_address(self.reuse_address)?;
+        socket.set_reuse_port(self.reuse_port)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&address.into())?;
+        socket.listen(self.backlog.min(i32::MAX as u32) as i32)?;
+
+        Ok(socket.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn port_in_use() {
+        let options = ListenerOptions::default();
+        let listener = options.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let error = options.bind(address).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::AddrInUse);
+
+        // A released port can be bound again straight away
+        drop(listener);
+        options.bind(address).unwrap();
+    }
+
+    #[test]
+    fn reuse_port_shares_the_address() {
+        let options = ListenerOptions {
+            reuse_port: true,
+            ..Default::default()
+        };
+        let listener = options.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+
+        options.bind(listener.local_addr().unwrap()).unwrap();
+    }
+}
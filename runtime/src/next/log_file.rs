@@ -0,0 +1,173 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use shuttle_proto::runtime::LogItem;
+
+use super::json_logs::json_line;
+
+// How many rotated files are kept next to the one being written, as `<path>.1` to `<path>.5`
+const KEPT_FILES: usize = 5;
+
+/// When the log file is rotated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogRotation {
+    /// Never rotate, so the file keeps growing
+    Never,
+    /// Rotate once the file is larger than this many bytes
+    Size(u64),
+    /// Rotate once the file has been written to for this long
+    Interval(Duration),
+}
+
+/// A file every forwarded log is written to as a JSON line, whether or not anything subscribed
+/// to the logs
+pub(crate) struct LogFile {
+    path: PathBuf,
+    rotation: LogRotation,
+    state: Mutex<State>,
+}
+
+struct State {
+    file: File,
+    size: u64,
+    opened: Instant,
+}
+
+impl LogFile {
+    /// Append to the file at `path`, creating it if it does not exist yet
+    pub(crate) fn open<P: AsRef<Path>>(path: P, rotation: LogRotation) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let state = State::open(&path)
+            .with_context(|| format!("failed to open the log file {}", path.display()))?;
+
+        Ok(Self {
+            path,
+            rotation,
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Write `log` of the deployment with `deployment_id` to the file
+    pub(crate) fn write(&self, deployment_id: &str, log: &LogItem) {
+        let mut line = json_line(deployment_id, log);
+        line.push('\n');
+
+        let mut state = self
+            .state
+            .lock()
+            .expect("log file lock should not be poisoned");
+
+        // Logs are best effort, and a full disk should not fail requests
+        if self.should_rotate(&state) {
+            let _ = self.rotate(&mut state);
+        }
+
+        if state.file.write_all(line.as_bytes()).is_ok() {
+            state.size += line.len() as u64;
+        }
+    }
+
+    fn should_rotate(&self, state: &State) -> bool {
+        match self.rotation {
+            LogRotation::Never => false,
+            LogRotation::Size(size) => state.size >= size,
+            LogRotation::Interval(interval) => state.opened.elapsed() >= interval,
+        }
+    }
+
+    /// Move the file to `<path>.1`, shifting the older files along and dropping the oldest, and
+    /// start a new one
+    fn rotate(&self, state: &mut State) -> io::Result<()> {
+        for index in (1..KEPT_FILES).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                fs::rename(from, self.rotated_path(index + 1))?;
+            }
+        }
+
+        fs::rename(&self.path, self.rotated_path(1))?;
+        *state = State::open(&self.path)?;
+
+        Ok(())
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+
+        path.into()
+    }
+}
+
+impl State {
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self {
+            size: file.metadata()?.len(),
+            file,
+            opened: Instant::now(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(message: &str) -> LogItem {
+        LogItem {
+            fields: serde_json::to_vec(&serde_json::json!({ "message": message })).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    fn messages(path: &Path) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let line: serde_json::Value = serde_json::from_str(line).unwrap();
+                line["message"].as_str().unwrap().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let dir = std::env::temp_dir().join(format!("log-file-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("guest.log");
+
+        let log_file = LogFile::open(&path, LogRotation::Size(1)).unwrap();
+        for message in ["first", "second", "third"] {
+            log_file.write("my-service", &log(message));
+        }
+
+        assert_eq!(messages(&path), ["third"]);
+        assert_eq!(messages(&log_file.rotated_path(1)), ["second"]);
+        assert_eq!(messages(&log_file.rotated_path(2)), ["first"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn appends_without_rotation() {
+        let path = std::env::temp_dir().join(format!("log-file-{}.log", std::process::id()));
+
+        LogFile::open(&path, LogRotation::Never)
+            .unwrap()
+            .write("my-service", &log("before"));
+        LogFile::open(&path, LogRotation::Never)
+            .unwrap()
+            .write("my-service", &log("after"));
+
+        assert_eq!(messages(&path), ["before", "after"]);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
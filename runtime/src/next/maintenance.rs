@@ -0,0 +1,159 @@
+use std::sync::{Arc, RwLock};
+
+use hyper::body::Bytes;
+use hyper::header::{self, HeaderValue};
+use hyper::http::StatusCode;
+use hyper::{Body, Request, Response};
+use shuttle_proto::runtime::SetMaintenanceRequest;
+
+/// Maintenance mode shared between the runtime, which toggles it, and every copy of its router
+#[derive(Default)]
+pub(crate) struct Maintenance {
+    page: RwLock<Option<Arc<MaintenancePage>>>,
+}
+
+impl Maintenance {
+    /// Enable or disable maintenance mode based on `request`. Returns an error message if the
+    /// request is invalid, in which case maintenance mode is left as is.
+    pub(crate) fn set(&self, request: SetMaintenanceRequest) -> Result<bool, String> {
+        let page = if request.enabled {
+            Some(Arc::new(MaintenancePage::new(request)?))
+        } else {
+            None
+        };
+        let enabled = page.is_some();
+
+        *self
+            .page
+            .write()
+            .expect("maintenance lock should not be poisoned") = page;
+
+        Ok(enabled)
+    }
+
+    /// Get the maintenance response for a request, if maintenance is enabled and the request
+    /// is not exempt
+    pub(crate) fn respond<B>(&self, req: &Request<B>) -> Option<Response<Body>> {
+        let page = self
+            .page
+            .read()
+            .expect("maintenance lock should not be poisoned")
+            .clone()?;
+
+        page.respond(req)
+    }
+}
+
+/// The response served to every request during maintenance
+struct MaintenancePage {
+    status: StatusCode,
+    body: Bytes,
+    content_type: HeaderValue,
+    retry_after: Option<u64>,
+    exempt_paths: Vec<String>,
+}
+
+impl MaintenancePage {
+    fn new(request: SetMaintenanceRequest) -> Result<Self, String> {
+        let status = match request.status {
+            Some(status) => u16::try_from(status)
+                .ok()
+                .and_then(|status| StatusCode::from_u16(status).ok())
+                .ok_or_else(|| format!("{status} is not a valid status"))?,
+            None => StatusCode::SERVICE_UNAVAILABLE,
+        };
+
+        let content_type = match request.content_type {
+            Some(content_type) => HeaderValue::try_from(content_type)
+                .map_err(|_| "content type should be a valid header value".to_string())?,
+            None => HeaderValue::from_static("text/plain; charset=utf-8"),
+        };
+
+        Ok(Self {
+            status,
+            body: request.body.into(),
+            content_type,
+            retry_after: request.retry_after_secs,
+            exempt_paths: request.exempt_paths,
+        })
+    }
+
+    fn respond<B>(&self, req: &Request<B>) -> Option<Response<Body>> {
+        let path = req.uri().path();
+
+        if self.exempt_paths.iter().any(|exempt| exempt == path) {
+            return None;
+        }
+
+        let mut response = Response::builder()
+            .status(self.status)
+            .header(header::CONTENT_TYPE, self.content_type.clone());
+
+        if let Some(retry_after) = self.retry_after {
+            response = response.header(header::RETRY_AFTER, retry_after);
+        }
+
+        let response = response
+            .body(Body::from(self.body.clone()))
+            .expect("building a maintenance response should not fail");
+
+        Some(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(path: &str) -> Request<()> {
+        Request::get(path).body(()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn maintenance() {
+        let maintenance = Maintenance::default();
+        assert!(maintenance.respond(&request("/")).is_none());
+
+        let enabled = maintenance
+            .set(SetMaintenanceRequest {
+                enabled: true,
+                body: b"back soon".to_vec(),
+                retry_after_secs: Some(120),
+                exempt_paths: vec!["/health".to_string()],
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(enabled);
+
+        let response = maintenance.respond(&request("/")).unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "120");
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "back soon");
+
+        assert!(maintenance.respond(&request("/health")).is_none());
+
+        let enabled = maintenance
+            .set(SetMaintenanceRequest {
+                enabled: false,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(!enabled);
+        assert!(maintenance.respond(&request("/")).is_none());
+    }
+
+    #[test]
+    fn invalid_status_is_rejected() {
+        let maintenance = Maintenance::default();
+
+        assert!(maintenance
+            .set(SetMaintenanceRequest {
+                enabled: true,
+                status: Some(1000),
+                ..Default::default()
+            })
+            .is_err());
+    }
+}
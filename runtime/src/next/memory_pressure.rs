@@ -0,0 +1,172 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::Context;
+
+const DEFAULT_SHED_PERCENT: u8 = 50;
+const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Configuration for shedding new requests while the process uses too much memory, so that
+/// in-flight requests can finish instead of everything being lost to an OOM kill
+#[derive(Clone, Debug)]
+pub struct MemoryPressureConfig {
+    high_rss: u64,
+    low_rss: u64,
+    shed_percent: u8,
+    check_interval: Duration,
+}
+
+impl MemoryPressureConfig {
+    /// Start shedding once the resident set size of the process is over `high_rss` bytes, and
+    /// stop again once it is under 90% of that
+    pub fn new(high_rss: u64) -> Self {
+        Self {
+            high_rss,
+            low_rss: high_rss / 10 * 9,
+            shed_percent: DEFAULT_SHED_PERCENT,
+            check_interval: DEFAULT_CHECK_INTERVAL,
+        }
+    }
+
+    /// Stop shedding once the resident set size is under `low_rss` bytes
+    pub fn low_rss(mut self, low_rss: u64) -> Self {
+        self.low_rss = low_rss.min(self.high_rss);
+        self
+    }
+
+    /// Percentage of new requests to reject with a `503 Service Unavailable` while shedding.
+    /// Defaults to half of them.
+    pub fn shed_percent(mut self, percent: u8) -> Self {
+        self.shed_percent = percent.min(100);
+        self
+    }
+
+    /// How often to check the memory usage of the process
+    pub fn check_interval(mut self, interval: Duration) -> Self {
+        self.check_interval = interval;
+        self
+    }
+}
+
+/// Whether requests are being shed, shared by every copy of a router
+pub(crate) struct MemoryPressure {
+    config: MemoryPressureConfig,
+    shedding: AtomicBool,
+    requests: AtomicU64,
+}
+
+impl MemoryPressure {
+    pub(crate) fn new(config: MemoryPressureConfig) -> Self {
+        Self {
+            config,
+            shedding: AtomicBool::new(false),
+            requests: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn check_interval(&self) -> Duration {
+        self.config.check_interval
+    }
+
+    /// Check if a new request should be shed. The shed requests are spread evenly between the
+    /// requests which are let through.
+    pub(crate) fn should_shed(&self) -> bool {
+        if !self.shedding.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        let percent = self.config.shed_percent as u64;
+        let count = self.requests.fetch_add(1, Ordering::Relaxed);
+
+        count * percent / 100 != (count + 1) * percent / 100
+    }
+
+    /// Start or stop shedding based on the resident set size `rss`. Returns whether shedding
+    /// was started or stopped, if it changed.
+    pub(crate) fn update(&self, rss: u64) -> Option<bool> {
+        let shedding = self.shedding.load(Ordering::Relaxed);
+
+        let changed = if !shedding && rss > self.config.high_rss {
+            true
+        } else if shedding && rss < self.config.low_rss {
+            false
+        } else {
+            return None;
+        };
+
+        self.shedding.store(changed, Ordering::Relaxed);
+
+        Some(changed)
+    }
+}
+
+/// Read the resident set size of this process in bytes
+pub(crate) fn current_rss() -> anyhow::Result<u64> {
+    let status =
+        std::fs::read_to_string("/proc/self/status").context("failed to read process status")?;
+
+    parse_rss(&status).context("process status should contain the resident set size")
+}
+
+/// Get the resident set size in bytes from the contents of `/proc/self/status`
+fn parse_rss(status: &str) -> Option<u64> {
+    let kilobytes = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+
+    Some(kilobytes * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shedding_has_hysteresis() {
+        let pressure = MemoryPressure::new(MemoryPressureConfig::new(1000).low_rss(800));
+
+        assert_eq!(pressure.update(900), None);
+        assert!(!pressure.should_shed());
+
+        assert_eq!(pressure.update(1001), Some(true));
+        assert_eq!(pressure.update(900), None);
+        assert_eq!(pressure.update(799), Some(false));
+        assert_eq!(pressure.update(900), None);
+    }
+
+    #[test]
+    fn sheds_a_fraction_of_requests() {
+        let pressure = MemoryPressure::new(MemoryPressureConfig::new(1000).shed_percent(25));
+        pressure.update(2000);
+
+        let shed = (0..100).filter(|_| pressure.should_shed()).count();
+        assert_eq!(shed, 25);
+
+        // Shed requests are spread out rather than rejected in a burst
+        let pattern: Vec<_> = (0..8).map(|_| pressure.should_shed()).collect();
+        assert_eq!(pattern.iter().filter(|shed| **shed).count(), 2);
+        assert_ne!(pattern[..2], [true, true]);
+
+        let pressure = MemoryPressure::new(MemoryPressureConfig::new(1000).shed_percent(100));
+        pressure.update(2000);
+        assert!((0..10).all(|_| pressure.should_shed()));
+    }
+
+    #[test]
+    fn parse() {
+        let status = "Name:\tshuttle-next\nVmPeak:\t  20000 kB\nVmRSS:\t   1234 kB\nThreads:\t4\n";
+
+        assert_eq!(parse_rss(status), Some(1234 * 1024));
+        assert_eq!(parse_rss("Name:\tshuttle-next\n"), None);
+    }
+
+    #[test]
+    fn current() {
+        assert!(current_rss().unwrap() > 0);
+    }
+}
@@ -0,0 +1,144 @@
+use serde::Deserialize;
+
+/// Name of the custom section a guest can embed its build metadata in, as a JSON object
+/// like `{"version": "1.2.0", "commit": "0a1b2c3"}`
+const METADATA_SECTION: &str = "shuttle:metadata";
+
+const UNKNOWN: &str = "unknown";
+
+/// Build metadata embedded in a module, where any missing parts are "unknown"
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModuleMetadata {
+    pub version: String,
+    pub commit: String,
+}
+
+#[derive(Deserialize)]
+struct RawMetadata {
+    version: Option<String>,
+    commit: Option<String>,
+}
+
+impl Default for ModuleMetadata {
+    fn default() -> Self {
+        Self {
+            version: UNKNOWN.to_string(),
+            commit: UNKNOWN.to_string(),
+        }
+    }
+}
+
+impl ModuleMetadata {
+    /// Read the metadata from the bytes of a wasm module. Modules without metadata, or with
+    /// metadata which cannot be parsed, get the default "unknown" metadata.
+    pub fn from_module(bytes: &[u8]) -> Self {
+        let Some(raw) = custom_section(bytes, METADATA_SECTION)
+            .and_then(|section| serde_json::from_slice::<RawMetadata>(section).ok())
+        else {
+            return Self::default();
+        };
+
+        let default = Self::default();
+
+        Self {
+            version: raw.version.unwrap_or(default.version),
+            commit: raw.commit.unwrap_or(default.commit),
+        }
+    }
+}
+
+/// Find the contents of the first custom section called `name` in a wasm binary
+pub(crate) fn custom_section<'a>(bytes: &'a [u8], name: &str) -> Option<&'a [u8]> {
+    // Skip the magic number and version
+    let mut rest = bytes.strip_prefix(b"\0asm")?.get(4..)?;
+
+    while !rest.is_empty() {
+        let id = rest[0];
+        let (size, after_size) = leb128(&rest[1..])?;
+        let contents = after_size.get(..size)?;
+        rest = &after_size[size..];
+
+        if id == 0 {
+            let (name_len, after_name_len) = leb128(contents)?;
+            let section_name = after_name_len.get(..name_len)?;
+
+            if section_name == name.as_bytes() {
+                return Some(&after_name_len[name_len..]);
+            }
+        }
+    }
+
+    None
+}
+
+/// Decode an unsigned LEB128 number, returning it with the bytes after it
+fn leb128(bytes: &[u8]) -> Option<(usize, &[u8])> {
+    let mut value: usize = 0;
+
+    for (index, byte) in bytes.iter().enumerate().take(5) {
+        value |= ((byte & 0x7f) as usize) << (7 * index);
+
+        if byte & 0x80 == 0 {
+            return Some((value, &bytes[index + 1..]));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module_with_section(name: &str, contents: &[u8]) -> Vec<u8> {
+        let mut section = vec![name.len() as u8];
+        section.extend_from_slice(name.as_bytes());
+        section.extend_from_slice(contents);
+
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        module.push(0);
+        module.push(section.len() as u8);
+        module.extend(section);
+
+        module
+    }
+
+    #[test]
+    fn reads_metadata() {
+        let module = module_with_section(
+            METADATA_SECTION,
+            br#"{"version": "1.2.0", "commit": "0a1b2c3"}"#,
+        );
+
+        assert_eq!(
+            ModuleMetadata::from_module(&module),
+            ModuleMetadata {
+                version: "1.2.0".to_string(),
+                commit: "0a1b2c3".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn tolerates_missing_metadata() {
+        let unknown = ModuleMetadata::default();
+
+        assert_eq!(ModuleMetadata::from_module(b"(module)"), unknown);
+        assert_eq!(ModuleMetadata::from_module(b"\0asm\x01\0\0\0"), unknown);
+        assert_eq!(
+            ModuleMetadata::from_module(&module_with_section("other", b"{}")),
+            unknown
+        );
+        assert_eq!(
+            ModuleMetadata::from_module(&module_with_section(METADATA_SECTION, b"not json")),
+            unknown
+        );
+
+        let partial = ModuleMetadata::from_module(&module_with_section(
+            METADATA_SECTION,
+            br#"{"version": "1.2.0"}"#,
+        ));
+        assert_eq!(partial.version, "1.2.0");
+        assert_eq!(partial.commit, "unknown");
+    }
+}
@@ -0,0 +1,71 @@
+use hyper::header::HeaderName;
+use hyper::{Method, Request};
+
+const METHOD_OVERRIDE: HeaderName = HeaderName::from_static("x-http-method-override");
+
+/// Methods a `POST` can be turned into. Only methods which a plain `POST` could already have
+/// the same effects as are allowed, so the header cannot be used to reach `CONNECT` or `TRACE`
+/// handling, or to make a request look safe to caches.
+const OVERRIDE_TARGETS: [Method; 3] = [Method::PUT, Method::PATCH, Method::DELETE];
+
+/// Turn a `POST` with an `X-HTTP-Method-Override` header into a request with the method in the
+/// header, for clients which can only send `GET` and `POST`. The header is removed either way,
+/// so the guest never acts on it itself. Returns the method the request was turned into.
+pub(crate) fn apply<B>(req: &mut Request<B>) -> Option<Method> {
+    let value = req.headers_mut().remove(METHOD_OVERRIDE)?;
+
+    if req.method() != Method::POST {
+        return None;
+    }
+
+    let method = Method::from_bytes(value.as_bytes()).ok()?;
+    if !OVERRIDE_TARGETS.contains(&method) {
+        return None;
+    }
+
+    *req.method_mut() = method.clone();
+
+    Some(method)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overridden(method: Method, value: &str) -> (Option<Method>, Request<()>) {
+        let mut req = Request::builder()
+            .method(method)
+            .uri("/orders/1")
+            .header(METHOD_OVERRIDE, value)
+            .body(())
+            .unwrap();
+
+        (apply(&mut req), req)
+    }
+
+    #[test]
+    fn overrides_post() {
+        let (method, req) = overridden(Method::POST, "DELETE");
+
+        assert_eq!(method, Some(Method::DELETE));
+        assert_eq!(req.method(), Method::DELETE);
+        assert!(req.headers().get(METHOD_OVERRIDE).is_none());
+    }
+
+    #[test]
+    fn only_safe_targets() {
+        for (method, value) in [
+            (Method::GET, "DELETE"),
+            (Method::POST, "GET"),
+            (Method::POST, "CONNECT"),
+            (Method::POST, "TRACE"),
+            (Method::POST, "delete"),
+        ] {
+            let (overridden, req) = overridden(method.clone(), value);
+
+            assert_eq!(overridden, None, "{method} {value}");
+            assert_eq!(req.method(), method);
+            assert!(req.headers().get(METHOD_OVERRIDE).is_none());
+        }
+    }
+}
@@ -1,55 +1,227 @@
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::io::{BufReader, Read, Write};
-use std::net::{Shutdown, SocketAddr};
+use std::net::{SocketAddr, TcpListener};
 use std::ops::DerefMut;
 use std::os::unix::prelude::RawFd;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use async_trait::async_trait;
 use cap_std::os::unix::net::UnixStream;
-use futures::TryStreamExt;
-use hyper::body::HttpBody;
+use chrono::Utc;
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::http::request::Parts;
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Request, Response};
-use shuttle_common::wasm::{Bytesable, Log, RequestWrapper, ResponseWrapper};
+use hyper::{Body, HeaderMap, Request, Response};
+use shuttle_common::wasm::{Level, Log, RequestWrapper, ResponseWrapper};
 use shuttle_proto::runtime::runtime_server::Runtime;
 use shuttle_proto::runtime::{
-    self, LoadRequest, LoadResponse, StartRequest, StartResponse, StopReason, StopRequest,
-    StopResponse, SubscribeLogsRequest, SubscribeStopRequest, SubscribeStopResponse,
+    self, AbortRequestRequest, AbortRequestResponse, GetConfigRequest, GetConfigResponse,
+    LoadRequest, LoadResponse, SetMaintenanceRequest, SetMaintenanceResponse, StartRequest,
+    StartResponse, StatusRequest, StatusResponse, StopReason, StopRequest, StopResponse,
+    SubscribeLogsRequest, SubscribeStopRequest, SubscribeStopResponse, VersionRequest,
+    VersionResponse,
 };
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::sync::{broadcast, mpsc, oneshot};
+// Follows the paused clock of tests so timing can be advanced without sleeping
+use tokio::time::Instant;
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::Status;
-use tracing::{error, trace, warn};
-use wasi_common::file::FileCaps;
-use wasmtime::{Engine, Linker, Module, Store};
-use wasmtime_wasi::sync::net::UnixStream as WasiUnixStream;
+use tracing::{debug, error, info, trace, warn, Instrument, Span};
+use wasi_common::pipe::ReadPipe;
+use wasmtime::{Config, Engine, Linker, Module, Store};
 use wasmtime_wasi::{WasiCtx, WasiCtxBuilder};
 
+mod abort;
 mod args;
+mod auto_options;
+mod backtrace;
+#[doc(hidden)]
+pub mod bench;
+mod body_limit;
+mod builtin;
+mod capture;
+mod coalesce;
+mod conditional;
+mod config;
+mod cors;
+mod deploy_metadata;
+mod epoch;
+mod error;
+mod error_pages;
+mod fd_budget;
+mod features;
+#[cfg(feature = "module-url")]
+mod fetch;
+mod handler;
+mod health;
+mod host_calls;
+mod https_redirect;
+mod idempotency;
+mod instance_rate;
+mod json_logs;
+mod keep_alive;
+mod listener;
+mod log_file;
+mod maintenance;
+mod memory_pressure;
+mod metadata;
+mod method_override;
+mod mounts;
+mod pool;
+mod redirect;
+mod request_body;
+mod request_context;
+mod route_listing;
+mod secrets;
+mod sequence;
+mod span;
+mod static_files;
+mod tags;
+mod timeouts;
+mod trailing_slash;
+mod transform;
+mod warm;
+mod watch;
+mod wire;
 
+use self::abort::AbortRegistry;
 pub use self::args::NextArgs;
+pub use self::body_limit::BodyLimitOverride;
+use self::body_limit::RouteBodyLimits;
+use self::builtin::BuiltinResponses;
+use self::capture::Capture;
+pub use self::capture::{replay, CaptureConfig, ReplayOutcome};
+use self::coalesce::{CoalesceKey, Coalescer, Flight, SharedResponse};
+pub use self::config::RuntimeConfig;
+pub use self::cors::CorsConfig;
+use self::deploy_metadata::DeploymentMetadata;
+use self::epoch::EpochTicker;
+use self::error::HostError;
+pub use self::error_pages::ErrorPage;
+use self::error_pages::ErrorPages;
+use self::fd_budget::{FdBudget, FdLimit};
+use self::features::FeatureFlags;
+use self::handler::{CallInterrupt, Guest, GuestStreams, RequestHandler, WasmGuest};
+use self::health::HealthReport;
+use self::host_calls::HostCalls;
+use self::https_redirect::HttpsRedirect;
+use self::idempotency::{Idempotency, IdempotencyKey, IdempotencyStore};
+use self::instance_rate::{InstanceRate, InstanceRateLimiter};
+use self::keep_alive::{Connection, Incoming, KeepAlive};
+use self::listener::ListenerOptions;
+use self::log_file::LogFile;
+pub use self::log_file::LogRotation;
+use self::maintenance::Maintenance;
+use self::memory_pressure::MemoryPressure;
+pub use self::memory_pressure::MemoryPressureConfig;
+use self::mounts::Mounts;
+use self::pool::GuestPool;
+use self::redirect::Redirects;
+pub use self::request_context::RequestContext;
+use self::secrets::Secrets;
+use self::sequence::LogSequence;
+use self::static_files::StaticFiles;
+use self::tags::Tags;
+use self::timeouts::RequestTimeouts;
+pub use self::trailing_slash::TrailingSlash;
+pub use self::transform::{BodyTransformer, ResponseBodyTransform};
+use self::warm::WarmPool;
+use self::watch::{LoadRetries, LoadedModule, SwappableModule};
+use self::wire::{Recorder, WireTap};
 
 extern crate rmp_serde as rmps;
 
-const LOGS_FD: u32 = 20;
-const PARTS_FD: u32 = 3;
-const BODY_FD: u32 = 4;
+// Only a single deployment is served by a runtime for now
+const DEFAULT_MAX_DEPLOYMENTS: usize = 1;
+
+const DEFAULT_MAX_BODY_SIZE: u64 = 64 * 1024;
+
+// Routes the guest gives a larger limit are still capped at this
+const DEFAULT_MAX_ROUTE_BODY_SIZE: u64 = 16 * 1024 * 1024;
+
+const DEFAULT_BODY_WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+const DEFAULT_STARTUP_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+// How often in-flight requests are checked while draining
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// How often requests in wasm check if they have been cancelled
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+const DEFAULT_MAX_LOG_SIZE: usize = 64 * 1024;
+
+/// Status a guest responds with to have the host handle the request instead. The host then
+/// tries its redirects and static files, and responds with `404 Not Found` if none match.
+pub const DEFER_TO_HOST_STATUS: u16 = 599;
+
+// More path segments than any sensible route has
+const MAX_PATH_SEGMENTS: usize = 128;
+
+// Leaves it to clients to sniff the type of bodies the guest did not label
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+// Generous limits on the headers a guest can respond with
+const DEFAULT_MAX_RESPONSE_HEADERS: usize = 100;
+const DEFAULT_MAX_RESPONSE_HEADERS_SIZE: usize = 64 * 1024;
+
+// Matches the header buffers of common proxies
+const DEFAULT_MAX_RESPONSE_HEADER_BYTES: usize = 32 * 1024;
+
+// Much less than what hyper buffers by default, but still more than any real request head needs
+const DEFAULT_MAX_REQUEST_HEAD_SIZE: usize = 64 * 1024;
+
+// Hyper panics with buffers smaller than this
+const MIN_REQUEST_HEAD_SIZE: usize = 8 * 1024;
+
+// See the `response_chunk_size` benchmark in `benches/next.rs` for how this default was picked
+const DEFAULT_RESPONSE_CHUNK_SIZE: usize = 16 * 1024;
+
+// Logs are buffered in the logs channel, so the subscriber only needs a little on top of it
+const LOGS_FORWARD_BUFFER: usize = 1024;
 
 pub struct AxumWasm {
     router: Mutex<Option<Router>>,
     logs_rx: Mutex<Option<Receiver<Result<runtime::LogItem, Status>>>>,
     logs_tx: Sender<Result<runtime::LogItem, Status>>,
+    logs_stopped: Arc<tokio::sync::watch::Sender<bool>>,
     kill_tx: Mutex<Option<oneshot::Sender<String>>>,
     stopped_tx: broadcast::Sender<(StopReason, String)>,
+    router_builder: RouterBuilder,
+    stats: Arc<RequestStats>,
+    maintenance: Arc<Maintenance>,
+    aborts: Arc<AbortRegistry>,
+    service_name: Mutex<String>,
+    started_at: Mutex<Option<Instant>>,
+    deployments: Arc<DeploymentSlots>,
+    module: Mutex<Option<Arc<SwappableModule>>>,
+    config: Mutex<Option<GetConfigResponse>>,
+    guest_pool: Mutex<Option<Arc<GuestPool>>>,
+    server: Mutex<Option<tokio::task::JoinHandle<ShutdownReport>>>,
+    #[cfg(feature = "module-url")]
+    module_fetch: fetch::ModuleFetch,
 }
 
 impl AxumWasm {
     pub fn new() -> Self {
+        let router_builder =
+            RouterBuilder::new().expect("wasi functions should be added to the linker");
+
+        Self::with_router_builder(router_builder)
+    }
+
+    /// Create a runtime which will use `router_builder` as a template for every router it loads
+    pub fn with_router_builder(router_builder: RouterBuilder) -> Self {
         // Allow about 2^15 = 32k logs of backpressure
         // We know the wasm currently handles about 16k requests per second (req / sec) so 16k seems to be a safe number
         // As we make performance gains elsewhere this might eventually become the new bottleneck to increase :D
@@ -59,15 +231,94 @@ impl AxumWasm {
         let (tx, rx) = mpsc::channel(1 << 15);
 
         let (stopped_tx, _stopped_rx) = broadcast::channel(10);
+        let (logs_stopped, _) = tokio::sync::watch::channel(false);
 
         Self {
             router: Mutex::new(None),
             logs_rx: Mutex::new(Some(rx)),
             logs_tx: tx,
+            logs_stopped: Arc::new(logs_stopped),
             kill_tx: Mutex::new(None),
             stopped_tx,
+            router_builder,
+            stats: Default::default(),
+            maintenance: Default::default(),
+            aborts: Default::default(),
+            service_name: Mutex::new(String::new()),
+            started_at: Mutex::new(None),
+            deployments: Arc::new(DeploymentSlots::new(DEFAULT_MAX_DEPLOYMENTS)),
+            module: Mutex::new(None),
+            config: Mutex::new(None),
+            guest_pool: Mutex::new(None),
+            server: Mutex::new(None),
+            #[cfg(feature = "module-url")]
+            module_fetch: Default::default(),
         }
     }
+
+    /// Limit how many deployments can be loaded at the same time. Further loads are rejected
+    /// with `RESOURCE_EXHAUSTED` until an active deployment is stopped.
+    pub fn max_deployments(mut self, max: usize) -> Self {
+        self.deployments = Arc::new(DeploymentSlots::new(max));
+        self
+    }
+
+    /// Limit the size of modules loaded from a URL to `max_size` bytes, and the time to
+    /// download them to `timeout`. Defaults to 256 MiB and a minute.
+    #[cfg(feature = "module-url")]
+    pub fn module_fetch_limits(mut self, max_size: u64, timeout: Duration) -> Self {
+        self.module_fetch = fetch::ModuleFetch { max_size, timeout };
+        self
+    }
+
+    /// Get a router builder for the module downloaded from `url`
+    #[cfg(feature = "module-url")]
+    async fn fetch_module(&self, url: &str) -> Result<RouterBuilder, Status> {
+        trace!(url, "fetching module");
+
+        let bytes = self.module_fetch.fetch(url).await.map_err(|err| {
+            error!(error = format!("{err:#}"), url, "failed to fetch module");
+
+            Status::failed_precondition(format!("failed to fetch module from {url}: {err:#}"))
+        })?;
+
+        Ok(self.router_builder.clone().module_bytes(bytes))
+    }
+
+    #[cfg(not(feature = "module-url"))]
+    async fn fetch_module(&self, _url: &str) -> Result<RouterBuilder, Status> {
+        Err(Status::invalid_argument(
+            "loading a module from a url needs the runtime to be built with the `module-url` feature",
+        ))
+    }
+
+    /// Bind a listener for the service to `address`
+    fn bind(&self, address: SocketAddr) -> Result<TcpListener, Status> {
+        self.router_builder.listener.bind(address).map_err(|err| {
+            error!(error = %err, %address, "failed to bind to address");
+
+            if err.kind() == std::io::ErrorKind::AddrInUse {
+                Status::already_exists(format!("address {address} is already in use"))
+            } else {
+                Status::failed_precondition(format!("failed to bind to address {address}: {err}"))
+            }
+        })
+    }
+}
+
+/// Describe the configuration `router_builder` builds routers with. Secrets of a deployment are
+/// only ever passed to its guest, so they cannot be part of it.
+fn config_response(router_builder: &RouterBuilder) -> GetConfigResponse {
+    GetConfigResponse {
+        config: serde_json::to_string(&router_builder.runtime_config())
+            .expect("runtime config should serialize to json"),
+        middleware: features::enabled_middleware(router_builder),
+    }
+}
+
+/// Check if a load request points to a module over HTTP rather than on disk
+fn is_module_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
 }
 
 impl Default for AxumWasm {
@@ -82,16 +333,67 @@ impl Runtime for AxumWasm {
         &self,
         request: tonic::Request<LoadRequest>,
     ) -> Result<tonic::Response<LoadResponse>, Status> {
-        let wasm_path = request.into_inner().path;
+        let LoadRequest {
+            path: wasm_path,
+            service_name,
+            tags,
+            metadata,
+            features,
+            secrets,
+            ..
+        } = request.into_inner();
         trace!(wasm_path, "loading shuttle-next project");
 
-        let router = RouterBuilder::new()
-            .map_err(|err| Status::from_error(err.into()))?
-            .src(wasm_path)
+        let features = FeatureFlags::new(features);
+
+        let tags = Tags::new(tags).map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let metadata = DeploymentMetadata::new(metadata)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+
+        let Some(deployment_slot) = self.deployments.try_acquire() else {
+            warn!(
+                max = self.deployments.max,
+                "rejecting load since the maximum number of deployments are active"
+            );
+
+            return Err(Status::resource_exhausted(
+                "the maximum number of deployments are already loaded",
+            ));
+        };
+
+        let router_builder = if is_module_url(&wasm_path) {
+            self.fetch_module(&wasm_path).await?
+        } else {
+            self.router_builder.clone().src(wasm_path)
+        };
+
+        let router_builder = features
+            .apply(router_builder)
+            .secrets(secrets)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let config = config_response(&router_builder);
+
+        let router = router_builder
             .build()
-            .map_err(|err| Status::from_error(err.into()))?;
+            .map_err(|err| Status::from_error(err.into()))?
+            .with_stats(self.stats.clone())
+            .with_maintenance(self.maintenance.clone())
+            .with_aborts(self.aborts.clone())
+            .with_deployment_slot(deployment_slot)
+            .with_tags(tags)
+            .with_metadata(metadata)
+            .with_deployment_id(&service_name);
+
+        if let Some(module) = &router.module {
+            info!(sha256 = %module.sha256(), "loaded module");
+        }
 
+        *self.module.lock().unwrap() = router.module.clone();
+        *self.config.lock().unwrap() = Some(config);
+        *self.guest_pool.lock().unwrap() = router.guest_pool.clone();
         *self.router.lock().unwrap() = Some(router);
+        *self.service_name.lock().unwrap() = service_name;
+        self.stats.dropped_logs.store(0, Ordering::Relaxed);
 
         let message = LoadResponse {
             success: true,
@@ -112,6 +414,17 @@ impl Runtime for AxumWasm {
             .context("invalid socket address")
             .map_err(|err| Status::invalid_argument(err.to_string()))?;
 
+        // Bind here so that a port conflict fails the start instead of the background server
+        let listener = self.bind(address)?;
+        let https_redirect = match &self.router_builder.https_redirect {
+            Some(https_redirect) => {
+                let address = SocketAddr::new(address.ip(), https_redirect.port);
+
+                Some((https_redirect.clone(), self.bind(address)?))
+            }
+            None => None,
+        };
+
         let logs_tx = self.logs_tx.clone();
 
         let (kill_tx, kill_rx) = tokio::sync::oneshot::channel();
@@ -126,11 +439,46 @@ impl Runtime for AxumWasm {
             .context("tried to start a service that was not loaded")
             .map_err(|err| Status::internal(err.to_string()))?;
 
+        // Serve a startup response instead of queueing up connections while warming up
+        let warm_up = router
+            .warmup_path
+            .clone()
+            .map(|path| (router.clone(), path));
+        let ready = router.ready.clone();
+        if warm_up.is_some() {
+            ready.store(false, Ordering::Release);
+        }
+
         let stopped_tx = self.stopped_tx.clone();
 
-        tokio::spawn(run_until_stopped(
-            router, address, logs_tx, kill_rx, stopped_tx,
-        ));
+        *self.started_at.lock().unwrap() = Some(Instant::now());
+
+        // The redirects are served for as long as the service is
+        let https_redirect = https_redirect
+            .map(|(https_redirect, listener)| tokio::spawn(https_redirect.serve(listener)));
+        let server = tokio::spawn({
+            let logs_tx = logs_tx.clone();
+            let logs_stopped = self.logs_stopped.clone();
+            async move {
+                let report =
+                    run_until_stopped(router, listener, logs_tx, kill_rx, stopped_tx).await;
+                if let Some(https_redirect) = https_redirect {
+                    https_redirect.abort();
+                }
+
+                // The server can stop without a stop request, like when its maximum lifetime
+                // is reached, and the logs stream should end then too
+                logs_stopped.send_replace(true);
+
+                report
+            }
+        });
+        *self.server.lock().unwrap() = Some(server);
+
+        if let Some((router, path)) = warm_up {
+            router.warm_up(&path, logs_tx).await;
+            ready.store(true, Ordering::Release);
+        }
 
         let message = StartResponse { success: true };
 
@@ -145,8 +493,41 @@ impl Runtime for AxumWasm {
     ) -> Result<tonic::Response<Self::SubscribeLogsStream>, Status> {
         let logs_rx = self.logs_rx.lock().unwrap().deref_mut().take();
 
-        if let Some(logs_rx) = logs_rx {
-            Ok(tonic::Response::new(ReceiverStream::new(logs_rx)))
+        if let Some(mut logs_rx) = logs_rx {
+            let mut stopped = self.logs_stopped.subscribe();
+            let (tx, rx) = mpsc::channel(LOGS_FORWARD_BUFFER);
+
+            // Forward the logs until the service is stopped, and then only the logs which were
+            // buffered by then, so the subscriber gets every log before the stream ends
+            tokio::spawn(async move {
+                loop {
+                    let log = if *stopped.borrow() {
+                        logs_rx.try_recv().ok()
+                    } else {
+                        tokio::select! {
+                            log = logs_rx.recv() => log,
+                            changed = stopped.changed() => {
+                                if changed.is_err() {
+                                    break;
+                                }
+                                continue;
+                            }
+                        }
+                    };
+
+                    let Some(log) = log else {
+                        break;
+                    };
+
+                    if tx.send(log).await.is_err() {
+                        break;
+                    }
+                }
+
+                trace!("logs stream ended");
+            });
+
+            Ok(tonic::Response::new(ReceiverStream::new(rx)))
         } else {
             Err(Status::internal("logs have already been subscribed to"))
         }
@@ -161,16 +542,33 @@ impl Runtime for AxumWasm {
         let kill_tx = self.kill_tx.lock().unwrap().deref_mut().take();
 
         if let Some(kill_tx) = kill_tx {
+            // The server may have stopped on its own already, like when its maximum lifetime
+            // was reached, in which case only its report is left to collect
             if kill_tx.send("stopping deployment".to_owned()).is_err() {
-                error!("the receiver dropped");
-                return Err(Status::internal("failed to stop deployment"));
+                trace!("the server had already stopped");
             }
 
-            Ok(tonic::Response::new(StopResponse { success: true }))
+            let server = self.server.lock().unwrap().take();
+            let report = match server {
+                Some(server) => server.await.ok().map(Into::into),
+                None => None,
+            };
+
+            // Every request was drained with the server, so end the logs stream once the logs
+            // they left behind are sent
+            self.logs_stopped.send_replace(true);
+
+            Ok(tonic::Response::new(StopResponse {
+                success: true,
+                report,
+            }))
         } else {
             warn!("trying to stop a service that was not started");
 
-            Ok(tonic::Response::new(StopResponse { success: false }))
+            Ok(tonic::Response::new(StopResponse {
+                success: false,
+                report: None,
+            }))
         }
     }
 
@@ -187,27 +585,321 @@ impl Runtime for AxumWasm {
         tokio::spawn(async move {
             trace!("moved stop channel into thread");
             while let Ok((reason, message)) = stopped_rx.recv().await {
-                tx.send(Ok(SubscribeStopResponse {
+                let response = SubscribeStopResponse {
                     reason: reason as i32,
                     message,
-                }))
-                .await
-                .unwrap();
+                };
+
+                if tx.send(Ok(response)).await.is_err() {
+                    break;
+                }
             }
         });
 
         Ok(tonic::Response::new(ReceiverStream::new(rx)))
     }
+
+    async fn status(
+        &self,
+        _request: tonic::Request<StatusRequest>,
+    ) -> Result<tonic::Response<StatusResponse>, Status> {
+        let uptime_secs = self
+            .started_at
+            .lock()
+            .unwrap()
+            .map(|started_at| started_at.elapsed().as_secs())
+            .unwrap_or_default();
+
+        let message = StatusResponse {
+            in_flight_requests: self.stats.in_flight.load(Ordering::Relaxed),
+            total_requests: self.stats.total.load(Ordering::Relaxed),
+            dropped_logs: self.stats.dropped_logs.load(Ordering::Relaxed),
+            shed_requests: self.stats.shed.load(Ordering::Relaxed),
+            uptime_secs,
+            service_name: self.service_name.lock().unwrap().clone(),
+            guest_threads: self
+                .guest_pool
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|guest_pool| guest_pool.size() as u64)
+                .unwrap_or_default(),
+        };
+
+        Ok(tonic::Response::new(message))
+    }
+
+    async fn set_maintenance(
+        &self,
+        request: tonic::Request<SetMaintenanceRequest>,
+    ) -> Result<tonic::Response<SetMaintenanceResponse>, Status> {
+        let enabled = self
+            .maintenance
+            .set(request.into_inner())
+            .map_err(Status::invalid_argument)?;
+
+        if enabled {
+            warn!("maintenance mode enabled");
+        } else {
+            warn!("maintenance mode disabled");
+        }
+
+        Ok(tonic::Response::new(SetMaintenanceResponse { enabled }))
+    }
+
+    async fn abort_request(
+        &self,
+        request: tonic::Request<AbortRequestRequest>,
+    ) -> Result<tonic::Response<AbortRequestResponse>, Status> {
+        let AbortRequestRequest { request_id } = request.into_inner();
+
+        let aborted = self.aborts.abort(request_id);
+        if aborted {
+            warn!(request_id, "aborting request");
+        } else {
+            trace!(request_id, "request to abort is not in flight");
+        }
+
+        Ok(tonic::Response::new(AbortRequestResponse { aborted }))
+    }
+
+    async fn get_config(
+        &self,
+        _request: tonic::Request<GetConfigRequest>,
+    ) -> Result<tonic::Response<GetConfigResponse>, Status> {
+        // Before a load the template is what the next deployment will run with
+        let message = self
+            .config
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap_or_else(|| config_response(&self.router_builder));
+
+        Ok(tonic::Response::new(message))
+    }
+
+    async fn version(
+        &self,
+        _request: tonic::Request<VersionRequest>,
+    ) -> Result<tonic::Response<VersionResponse>, Status> {
+        let module = self.module.lock().unwrap().clone();
+        let metadata = module
+            .as_ref()
+            .map(|module| module.metadata())
+            .unwrap_or_default();
+        let module_sha256 = module
+            .map(|module| module.sha256().to_string())
+            .unwrap_or_default();
+
+        let message = VersionResponse {
+            version: metadata.version.clone(),
+            commit: metadata.commit.clone(),
+            module_sha256,
+        };
+
+        Ok(tonic::Response::new(message))
+    }
+}
+
+/// Request counters shared between the runtime and every copy of its router
+#[derive(Default)]
+struct RequestStats {
+    in_flight: AtomicU64,
+    peak_in_flight: AtomicU64,
+    total: AtomicU64,
+    dropped_logs: AtomicU64,
+    shed: AtomicU64,
+}
+
+impl RequestStats {
+    /// Count a new request as in-flight until the returned guard is dropped
+    fn track(self: &Arc<Self>) -> InFlightGuard {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak_in_flight.fetch_max(in_flight, Ordering::Relaxed);
+        let id = self.total.fetch_add(1, Ordering::Relaxed) + 1;
+
+        InFlightGuard {
+            stats: self.clone(),
+            id,
+        }
+    }
+}
+
+struct InFlightGuard {
+    stats: Arc<RequestStats>,
+    /// Identifies the request among all requests handled by this runtime
+    id: u64,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.stats.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Summary of a server's run, from when it started until it stopped
+#[derive(Debug, PartialEq, Eq)]
+struct ShutdownReport {
+    total_requests: u64,
+    drained_requests: u64,
+    dropped_requests: u64,
+    peak_in_flight_requests: u64,
+    uptime: Duration,
+}
+
+impl From<ShutdownReport> for runtime::ShutdownReport {
+    fn from(report: ShutdownReport) -> Self {
+        Self {
+            total_requests: report.total_requests,
+            drained_requests: report.drained_requests,
+            dropped_requests: report.dropped_requests,
+            peak_in_flight_requests: report.peak_in_flight_requests,
+            uptime_secs: report.uptime.as_secs(),
+        }
+    }
+}
+
+/// Wait up to `timeout` for the requests in flight to finish. Returns how many of them
+/// finished and how many were still in flight at the timeout.
+async fn drain(stats: &RequestStats, timeout: Duration) -> (u64, u64) {
+    let in_flight = stats.in_flight.load(Ordering::Relaxed);
+    let deadline = Instant::now() + timeout;
+
+    while stats.in_flight.load(Ordering::Relaxed) > 0 && Instant::now() < deadline {
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+
+    // Requests on connections which are still open can start while draining
+    let dropped = stats.in_flight.load(Ordering::Relaxed).min(in_flight);
+
+    (in_flight - dropped, dropped)
+}
+
+/// Tracks the number of deployments which hold compiled modules in memory
+struct DeploymentSlots {
+    active: AtomicUsize,
+    max: usize,
+}
+
+impl DeploymentSlots {
+    fn new(max: usize) -> Self {
+        Self {
+            active: AtomicUsize::new(0),
+            max,
+        }
+    }
+
+    /// Claim a slot for a new deployment if the maximum has not been reached yet
+    fn try_acquire(self: &Arc<Self>) -> Option<Arc<DeploymentSlot>> {
+        self.active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |active| {
+                (active < self.max).then_some(active + 1)
+            })
+            .ok()?;
+
+        Some(Arc::new(DeploymentSlot(self.clone())))
+    }
+}
+
+/// A claimed deployment slot which is given back once every copy of its router is dropped
+struct DeploymentSlot(Arc<DeploymentSlots>);
+
+impl Drop for DeploymentSlot {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::AcqRel);
+    }
 }
-struct RouterBuilder {
+
+#[derive(Clone)]
+pub struct RouterBuilder {
     engine: Engine,
     linker: Linker<WasiCtx>,
     src: Option<PathBuf>,
+    module_bytes: Option<Vec<u8>>,
+    mounts: Vec<(String, PathBuf)>,
+    default_response_headers: HeaderMap,
+    default_content_type: HeaderValue,
+    strict_content_type: bool,
+    body_write_timeout: Duration,
+    instantiate_timeout: Option<Duration>,
+    handler_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    response_chunk_size: usize,
+    response_buffer_threshold: Option<usize>,
+    allowed_hosts: Option<Vec<String>>,
+    title_case_headers: bool,
+    problem_json: bool,
+    cors: Option<CorsConfig>,
+    capture: Option<CaptureConfig>,
+    slow_request_threshold: Option<Duration>,
+    builtin_responses: BuiltinResponses,
+    warmup_path: Option<String>,
+    health_path: Option<String>,
+    routes_path: Option<String>,
+    body_limit_override: Option<BodyLimitOverride>,
+    max_body_size: u64,
+    max_route_body_size: u64,
+    payload_too_large: PayloadTooLarge,
+    guest_pool_size: Option<usize>,
+    guest_idle_timeout: Option<Duration>,
+    body_as_stdin: bool,
+    request_body_high_water_mark: Option<usize>,
+    deterministic: bool,
+    wasm_backtraces: bool,
+    debug_wire: bool,
+    json_logs: bool,
+    log_file: Option<(PathBuf, LogRotation)>,
+    method_override: bool,
+    auto_options: bool,
+    max_host_calls: Option<u64>,
+    warm_pool_size: usize,
+    instance_rate: InstanceRate,
+    load_retries: LoadRetries,
+    watch_source: bool,
+    strip_request_headers: Vec<HeaderName>,
+    strip_response_headers: Vec<HeaderName>,
+    status_remap: HashMap<hyper::StatusCode, hyper::StatusCode>,
+    error_pages: HashMap<hyper::StatusCode, ErrorPage>,
+    secrets: Secrets,
+    redirects: Redirects,
+    trailing_slash: TrailingSlash,
+    static_files: Vec<StaticFiles>,
+    host_routes_after_guest: bool,
+    max_response_headers: usize,
+    max_response_headers_size: usize,
+    max_response_header_bytes: usize,
+    max_request_head_size: usize,
+    max_log_size: usize,
+    max_lifetime: Option<Duration>,
+    body_transform: Option<Arc<dyn ResponseBodyTransform>>,
+    request_context: Option<Arc<dyn RequestContext>>,
+    listener: ListenerOptions,
+    keep_alive: KeepAlive,
+    https_redirect: Option<HttpsRedirect>,
+    memory_pressure: Option<MemoryPressureConfig>,
+    idempotency_keys: Option<(Duration, usize)>,
+    fd_limit: Option<FdLimit>,
+    coalesce_requests: bool,
+    drain_timeout: Duration,
+    startup_detail: Option<String>,
+    startup_retry_after: Duration,
+}
+
+/// Extra information to give clients whose request body is too large
+#[derive(Clone, Default)]
+struct PayloadTooLarge {
+    message: Option<String>,
+    docs: Option<HeaderValue>,
 }
 
 impl RouterBuilder {
-    fn new() -> anyhow::Result<Self> {
-        let engine = Engine::default();
+    pub fn new() -> anyhow::Result<Self> {
+        Self::from_config(Config::new())
+    }
+
+    fn from_config(mut config: Config) -> anyhow::Result<Self> {
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config)?;
 
         let mut linker: Linker<WasiCtx> = Linker::new(&engine);
         wasmtime_wasi::add_to_linker(&mut linker, |s| s)?;
@@ -216,233 +908,4432 @@ impl RouterBuilder {
             engine,
             linker,
             src: None,
+            module_bytes: None,
+            mounts: Vec::new(),
+            default_response_headers: HeaderMap::new(),
+            default_content_type: HeaderValue::from_static(DEFAULT_CONTENT_TYPE),
+            strict_content_type: false,
+            body_write_timeout: DEFAULT_BODY_WRITE_TIMEOUT,
+            instantiate_timeout: None,
+            handler_timeout: None,
+            request_timeout: None,
+            response_chunk_size: DEFAULT_RESPONSE_CHUNK_SIZE,
+            response_buffer_threshold: None,
+            allowed_hosts: None,
+            title_case_headers: false,
+            problem_json: false,
+            cors: None,
+            capture: None,
+            slow_request_threshold: None,
+            builtin_responses: Default::default(),
+            warmup_path: None,
+            health_path: None,
+            routes_path: None,
+            body_limit_override: None,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            max_route_body_size: DEFAULT_MAX_ROUTE_BODY_SIZE,
+            payload_too_large: Default::default(),
+            guest_pool_size: None,
+            guest_idle_timeout: None,
+            body_as_stdin: false,
+            request_body_high_water_mark: None,
+            deterministic: false,
+            wasm_backtraces: false,
+            debug_wire: false,
+            json_logs: false,
+            log_file: None,
+            method_override: false,
+            auto_options: false,
+            max_host_calls: None,
+            warm_pool_size: 0,
+            instance_rate: Default::default(),
+            load_retries: Default::default(),
+            watch_source: false,
+            strip_request_headers: Vec::new(),
+            strip_response_headers: Vec::new(),
+            status_remap: HashMap::new(),
+            error_pages: HashMap::new(),
+            secrets: Default::default(),
+            redirects: Default::default(),
+            trailing_slash: Default::default(),
+            static_files: Vec::new(),
+            host_routes_after_guest: false,
+            max_response_headers: DEFAULT_MAX_RESPONSE_HEADERS,
+            max_response_headers_size: DEFAULT_MAX_RESPONSE_HEADERS_SIZE,
+            max_response_header_bytes: DEFAULT_MAX_RESPONSE_HEADER_BYTES,
+            max_request_head_size: DEFAULT_MAX_REQUEST_HEAD_SIZE,
+            max_log_size: DEFAULT_MAX_LOG_SIZE,
+            max_lifetime: None,
+            body_transform: None,
+            request_context: None,
+            listener: Default::default(),
+            keep_alive: Default::default(),
+            https_redirect: None,
+            memory_pressure: None,
+            idempotency_keys: None,
+            fd_limit: None,
+            coalesce_requests: false,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            startup_detail: None,
+            startup_retry_after: DEFAULT_STARTUP_RETRY_AFTER,
         })
     }
 
-    fn src<P: AsRef<Path>>(mut self, src: P) -> Self {
+    pub fn src<P: AsRef<Path>>(mut self, src: P) -> Self {
         self.src = Some(src.as_ref().to_path_buf());
         self
     }
 
-    fn build(self) -> anyhow::Result<Router> {
-        let file = self.src.context("module path should be set")?;
-        let module = Module::from_file(&self.engine, file)?;
-
-        for export in module.exports() {
-            trace!("export: {}", export.name());
-        }
+    /// Use the compiled module in `bytes` instead of reading it from `src`. Such a module is
+    /// never watched for changes.
+    pub fn module_bytes(mut self, bytes: Vec<u8>) -> Self {
+        self.module_bytes = Some(bytes);
+        self
+    }
 
-        Ok(Router {
-            linker: self.linker,
-            engine: self.engine,
-            module,
-        })
+    /// Serve requests with paths under `prefix` using the module at `src` instead. The longest
+    /// matching prefix wins and the path is passed on unchanged. With modules mounted, `src` is
+    /// optional and requests matching no prefix get a `404 Not Found` when it is not set.
+    pub fn mount(mut self, prefix: impl Into<String>, src: impl AsRef<Path>) -> Self {
+        self.mounts
+            .push((prefix.into(), src.as_ref().to_path_buf()));
+        self
     }
-}
 
-#[derive(Clone)]
-struct Router {
-    linker: Linker<WasiCtx>,
-    engine: Engine,
-    module: Module,
-}
+    /// Queue up to `backlog` connections which have not been accepted yet, before new
+    /// connections are refused
+    pub fn listen_backlog(mut self, backlog: u32) -> Self {
+        self.listener.backlog = backlog;
+        self
+    }
 
-impl Router {
-    /// Send a HTTP request with body to given endpoint on the axum-wasm router and return the response
-    async fn handle_request(
-        &mut self,
-        req: hyper::Request<Body>,
-        logs_tx: Sender<Result<runtime::LogItem, Status>>,
-    ) -> anyhow::Result<Response<Body>> {
-        let wasi = WasiCtxBuilder::new()
-            .inherit_stdio()
-            .inherit_args()
-            .context("failed to read args")?
-            .build();
+    /// Set `SO_REUSEADDR` on the listener so a restarted service can bind its address while
+    /// connections from before the restart are still closing. This is enabled by default.
+    pub fn reuse_address(mut self, enabled: bool) -> Self {
+        self.listener.reuse_address = enabled;
+        self
+    }
 
-        let mut store = Store::new(&self.engine, wasi);
-        self.linker.module(&mut store, "axum", &self.module)?;
+    /// Set `SO_REUSEPORT` on the listener so multiple processes can share its address, with
+    /// the kernel spreading new connections between them
+    pub fn reuse_port(mut self, enabled: bool) -> Self {
+        self.listener.reuse_port = enabled;
+        self
+    }
 
-        let (logs_stream, logs_client) =
-            UnixStream::pair().context("failed to open logs unixstream")?;
-        let (mut parts_stream, parts_client) =
-            UnixStream::pair().context("failed to open parts unixstream")?;
-        let (mut body_stream, body_client) =
-            UnixStream::pair().context("failed to open body write unixstream")?;
+    /// Keep HTTP/1 connections open for more requests after a response. This is enabled by
+    /// default, and turning it off closes every connection after its first response.
+    pub fn keep_alive(mut self, enabled: bool) -> Self {
+        self.keep_alive.enabled = enabled;
+        self
+    }
 
-        let logs_client = WasiUnixStream::from_cap_std(logs_client);
-        let parts_client = WasiUnixStream::from_cap_std(parts_client);
-        let body_client = WasiUnixStream::from_cap_std(body_client);
+    /// Close connections once they have served `max` requests, so long-lived clients are
+    /// spread over servers again
+    pub fn max_requests_per_connection(mut self, max: u64) -> Self {
+        self.keep_alive.max_requests = Some(max.max(1));
+        self
+    }
 
-        store
-            .data_mut()
-            .insert_file(LOGS_FD, Box::new(logs_client), FileCaps::all());
+    /// Close connections which have been idle between requests for `timeout`, or never close
+    /// idle connections with `None`. This is 75 seconds by default.
+    pub fn keep_alive_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.keep_alive.idle_timeout = timeout;
+        self
+    }
 
-        store
-            .data_mut()
-            .insert_file(PARTS_FD, Box::new(parts_client), FileCaps::all());
-        store
-            .data_mut()
-            .insert_file(BODY_FD, Box::new(body_client), FileCaps::all());
+    /// Close connections whose current request has taken longer than `duration`, from reading
+    /// its body until its response is sent. Unlike the request timeout, this bounds clients
+    /// which upload or download slowly, whatever the guest is doing.
+    pub fn max_request_duration(mut self, duration: Duration) -> Self {
+        self.keep_alive.max_request_duration = Some(duration);
+        self
+    }
 
-        tokio::task::spawn_blocking(move || {
-            let mut iter = logs_stream.bytes().filter_map(Result::ok);
+    /// Listen for plain HTTP on `port` of the address the service is started on, and answer
+    /// every request there with a redirect to the same URL over HTTPS on `https_port`. The guest
+    /// is never called for these requests. `status` should be a redirect status like `301` or
+    /// `308`.
+    pub fn https_redirect(
+        mut self,
+        port: u16,
+        https_port: u16,
+        status: hyper::StatusCode,
+    ) -> anyhow::Result<Self> {
+        if !status.is_redirection() {
+            bail!("https redirects should use a 3xx status, not {status}");
+        }
 
-            while let Some(log) = Log::from_bytes(&mut iter) {
-                logs_tx.blocking_send(Ok(log.into())).expect("to send log");
-            }
+        self.https_redirect = Some(HttpsRedirect {
+            port,
+            https_port,
+            status,
         });
+        Ok(self)
+    }
 
-        let (parts, body) = req.into_parts();
+    /// Headers to add to every response, unless the guest already set them.
+    /// Useful for enforcing security headers such as `X-Content-Type-Options`.
+    pub fn default_response_headers(mut self, headers: HeaderMap) -> Self {
+        self.default_response_headers = headers;
+        self
+    }
 
-        // Serialise request parts to rmp
-        let request_rmp = RequestWrapper::from(parts)
-            .into_rmp()
-            .context("failed to make request wrapper")?;
+    /// `Content-Type` to give responses with a body which the guest did not set one for.
+    /// Defaults to `application/octet-stream`.
+    pub fn default_content_type(mut self, content_type: String) -> anyhow::Result<Self> {
+        self.default_content_type = HeaderValue::try_from(content_type)
+            .context("default content type should be a valid header value")?;
+        Ok(self)
+    }
 
-        // Write request parts to wasm module
-        parts_stream
-            .write_all(&request_rmp)
-            .context("failed to write http parts to wasm")?;
+    /// Set every tunable in `config` at once. Options which are not part of the config keep
+    /// their current values.
+    pub fn with_config(self, config: RuntimeConfig) -> Self {
+        let RuntimeConfig {
+            max_body_size,
+            max_route_body_size,
+            body_write_timeout_ms,
+            instantiate_timeout_ms,
+            handler_timeout_ms,
+            request_timeout_ms,
+            response_chunk_size,
+            response_buffer_threshold,
+            max_response_headers,
+            max_response_headers_size,
+            max_response_header_bytes,
+            max_request_head_size,
+            max_log_size,
+            slow_request_threshold_ms,
+            guest_threads,
+            guest_thread_idle_timeout_ms,
+            strict_content_type,
+            problem_json,
+            title_case_headers,
+            body_as_stdin,
+            request_body_high_water_mark,
+            max_lifetime_ms,
+            listen_backlog,
+            reuse_port,
+            keep_alive,
+            max_requests_per_connection,
+            keep_alive_timeout_ms,
+            max_request_duration_ms,
+            coalesce_requests,
+            wasm_backtraces,
+            json_logs,
+            method_override,
+            auto_options,
+            max_host_calls,
+            warm_pool_size,
+            max_instances_per_second,
+            instance_burst,
+            max_instance_wait_ms,
+            module_load_retries,
+            module_load_retry_delay_ms,
+            drain_timeout_ms,
+            startup_detail,
+            startup_retry_after_secs,
+            max_request_fds_percent,
+        } = config;
 
-        // To protect our server, reject requests with bodies larger than
-        // 64kbs of data.
-        let body_size = body.size_hint().upper().unwrap_or(u64::MAX);
+        // Clear what the config leaves unset, so every tunable which is set goes through its
+        // setter and is checked the same way as when it is set directly
+        let mut builder = self;
+        builder.slow_request_threshold = None;
+        builder.guest_pool_size = None;
+        builder.guest_idle_timeout = None;
+        builder.max_lifetime = None;
+        builder.instantiate_timeout = None;
+        builder.handler_timeout = None;
+        builder.request_timeout = None;
+        builder.startup_detail = None;
+        builder.request_body_high_water_mark = None;
+        builder.response_buffer_threshold = None;
+        builder.max_host_calls = None;
+        builder.keep_alive.max_requests = None;
+        builder.keep_alive.max_request_duration = None;
+        builder.instance_rate.per_second = None;
+        builder.fd_limit = None;
 
-        if body_size > 1024 * 64 {
-            let response = Response::builder()
-                .status(hyper::http::StatusCode::PAYLOAD_TOO_LARGE)
-                .body(Body::empty())
-                .expect("building request with empty body should not fail");
+        builder
+            .max_body_size(max_body_size)
+            .max_route_body_size(max_route_body_size)
+            .body_write_timeout(Duration::from_millis(body_write_timeout_ms))
+            .response_chunk_size(response_chunk_size)
+            .max_response_headers(max_response_headers, max_response_headers_size)
+            .max_response_header_bytes(max_response_header_bytes)
+            .max_request_head_size(max_request_head_size)
+            .max_log_size(max_log_size)
+            .strict_content_type(strict_content_type)
+            .problem_json(problem_json)
+            .title_case_headers(title_case_headers)
+            .body_as_stdin(body_as_stdin)
+            .listen_backlog(listen_backlog)
+            .reuse_port(reuse_port)
+            .keep_alive(keep_alive)
+            .keep_alive_timeout(keep_alive_timeout_ms.map(Duration::from_millis))
+            .coalesce_requests(coalesce_requests)
+            .wasm_backtraces(wasm_backtraces)
+            .json_logs(json_logs)
+            .method_override(method_override)
+            .auto_options(auto_options)
+            .warm_pool_size(warm_pool_size)
+            .module_load_retries(
+                module_load_retries,
+                Duration::from_millis(module_load_retry_delay_ms),
+            )
+            .drain_timeout(Duration::from_millis(drain_timeout_ms))
+            .startup_retry_after(Duration::from_secs(startup_retry_after_secs))
+            .instance_burst(instance_burst)
+            .max_instance_wait(Duration::from_millis(max_instance_wait_ms))
+            .set_some(
+                slow_request_threshold_ms.map(Duration::from_millis),
+                Self::slow_request_threshold,
+            )
+            .set_some(guest_threads, Self::guest_thread_pool_size)
+            .set_some(
+                guest_thread_idle_timeout_ms.map(Duration::from_millis),
+                Self::guest_thread_idle_timeout,
+            )
+            .set_some(
+                max_lifetime_ms.map(Duration::from_millis),
+                Self::max_lifetime,
+            )
+            .set_some(
+                instantiate_timeout_ms.map(Duration::from_millis),
+                Self::instantiate_timeout,
+            )
+            .set_some(
+                handler_timeout_ms.map(Duration::from_millis),
+                Self::handler_timeout,
+            )
+            .set_some(
+                request_timeout_ms.map(Duration::from_millis),
+                Self::request_timeout,
+            )
+            .set_some(startup_detail, Self::startup_detail)
+            .set_some(request_body_high_water_mark, Self::stream_request_body)
+            .set_some(response_buffer_threshold, Self::buffer_responses_up_to)
+            .set_some(max_host_calls, Self::max_host_calls)
+            .set_some(
+                max_requests_per_connection,
+                Self::max_requests_per_connection,
+            )
+            .set_some(
+                max_request_duration_ms.map(Duration::from_millis),
+                Self::max_request_duration,
+            )
+            .set_some(max_instances_per_second, Self::max_instances_per_second)
+            .set_some(max_request_fds_percent, Self::max_request_fds_percent)
+    }
 
-            // Return early if body is too big
-            return Ok(response);
+    /// Set a tunable with `set` if the config has a `value` for it
+    fn set_some<T>(self, value: Option<T>, set: impl FnOnce(Self, T) -> Self) -> Self {
+        match value {
+            Some(value) => set(self, value),
+            None => self,
         }
+    }
 
-        let body_bytes = hyper::body::to_bytes(body)
-            .await
-            .context("failed to concatenate request body buffers")?;
+    /// Get the tunables this builder is set to, as the config which would set them. A fixed
+    /// request fd budget cannot be part of the config.
+    pub fn runtime_config(&self) -> RuntimeConfig {
+        let millis = |duration: Duration| duration.as_millis() as u64;
 
-        // Write body to wasm
-        body_stream
-            .write_all(body_bytes.as_ref())
-            .context("failed to write body to wasm")?;
+        RuntimeConfig {
+            max_body_size: self.max_body_size,
+            max_route_body_size: self.max_route_body_size,
+            body_write_timeout_ms: millis(self.body_write_timeout),
+            instantiate_timeout_ms: self.instantiate_timeout.map(millis),
+            handler_timeout_ms: self.handler_timeout.map(millis),
+            request_timeout_ms: self.request_timeout.map(millis),
+            response_chunk_size: self.response_chunk_size,
+            response_buffer_threshold: self.response_buffer_threshold,
+            max_response_headers: self.max_response_headers,
+            max_response_headers_size: self.max_response_headers_size,
+            max_response_header_bytes: self.max_response_header_bytes,
+            max_request_head_size: self.max_request_head_size,
+            max_log_size: self.max_log_size,
+            slow_request_threshold_ms: self.slow_request_threshold.map(millis),
+            guest_threads: self.guest_pool_size,
+            guest_thread_idle_timeout_ms: self.guest_idle_timeout.map(millis),
+            strict_content_type: self.strict_content_type,
+            problem_json: self.problem_json,
+            title_case_headers: self.title_case_headers,
+            body_as_stdin: self.body_as_stdin,
+            request_body_high_water_mark: self.request_body_high_water_mark,
+            max_lifetime_ms: self.max_lifetime.map(millis),
+            listen_backlog: self.listener.backlog,
+            reuse_port: self.listener.reuse_port,
+            keep_alive: self.keep_alive.enabled,
+            max_requests_per_connection: self.keep_alive.max_requests,
+            keep_alive_timeout_ms: self.keep_alive.idle_timeout.map(millis),
+            max_request_duration_ms: self.keep_alive.max_request_duration.map(millis),
+            coalesce_requests: self.coalesce_requests,
+            wasm_backtraces: self.wasm_backtraces,
+            json_logs: self.json_logs,
+            method_override: self.method_override,
+            auto_options: self.auto_options,
+            max_host_calls: self.max_host_calls,
+            warm_pool_size: self.warm_pool_size,
+            max_instances_per_second: self.instance_rate.per_second,
+            instance_burst: self.instance_rate.burst,
+            max_instance_wait_ms: millis(self.instance_rate.max_wait),
+            module_load_retries: self.load_retries.attempts,
+            module_load_retry_delay_ms: millis(self.load_retries.delay),
+            drain_timeout_ms: millis(self.drain_timeout),
+            startup_detail: self.startup_detail.clone(),
+            startup_retry_after_secs: self.startup_retry_after.as_secs(),
+            max_request_fds_percent: match self.fd_limit {
+                Some(FdLimit::PercentOfRlimit(percent)) => Some(percent),
+                _ => None,
+            },
+        }
+    }
 
-        // Shut down the write part of the stream to signal EOF
-        body_stream
-            .shutdown(Shutdown::Write)
-            .expect("failed to shut down body write half");
+    /// How long to wait for the guest to accept the request parts and body before failing the
+    /// request
+    pub fn body_write_timeout(mut self, timeout: Duration) -> Self {
+        self.body_write_timeout = timeout;
+        self
+    }
 
-        // Call our function in wasm, telling it to route the request we've written to it
-        // and write back a response
-        trace!("calling Router");
-        self.linker
-            .get(&mut store, "axum", "__SHUTTLE_Axum_call")
-            .context("wasm module should be loaded and the router function should be available")?
-            .into_func()
-            .context("router function should be a function")?
-            .typed::<(RawFd, RawFd, RawFd), ()>(&store)?
-            .call(
-                &mut store,
-                (LOGS_FD as i32, PARTS_FD as i32, BODY_FD as i32),
-            )?;
+    /// Respond with a `503 Service Unavailable` when instantiating the module for a request
+    /// takes longer than `timeout`. Instantiating cannot be interrupted, so the request fails
+    /// once it is done.
+    pub fn instantiate_timeout(mut self, timeout: Duration) -> Self {
+        self.instantiate_timeout = Some(timeout);
+        self
+    }
 
-        // Read response parts from wasm
-        let reader = BufReader::new(&mut parts_stream);
+    /// Trap the guest and respond with a `504 Gateway Timeout` when it takes longer than
+    /// `timeout` to produce a response
+    pub fn handler_timeout(mut self, timeout: Duration) -> Self {
+        self.handler_timeout = Some(timeout);
+        self
+    }
+
+    /// Cap how long instantiating, writing the request to the guest and handling it can take
+    /// together. Each phase gets at most what is left of `timeout`, and fails the way its own
+    /// timeout does when that runs out.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Maximum number of bytes read from the guest's response body for each chunk passed to hyper.
+    /// Smaller chunks lower the latency to the first byte, larger chunks give better throughput.
+    pub fn response_chunk_size(mut self, size: usize) -> Self {
+        self.response_chunk_size = size.max(1);
+        self
+    }
+
+    /// Read response bodies of at most `threshold` bytes whole and send them in one frame with
+    /// an exact `Content-Length`. Larger bodies are still streamed in chunks.
+    pub fn buffer_responses_up_to(mut self, threshold: usize) -> Self {
+        self.response_buffer_threshold = Some(threshold);
+        self
+    }
+
+    /// Only serve requests with a `Host` matching one of `hosts`, all other requests get a
+    /// `421 Misdirected Request`. A host starting with `*.` matches any of its subdomains.
+    /// All hosts are allowed when this is not set.
+    pub fn allowed_hosts(mut self, hosts: Vec<String>) -> Self {
+        self.allowed_hosts = Some(hosts);
+        self
+    }
+
+    /// Serve these `(from, to, status)` redirects without calling the guest. A `from` ending in
+    /// `*` matches every path with that prefix, otherwise the path has to match exactly.
+    pub fn redirects(
+        mut self,
+        redirects: Vec<(String, String, hyper::StatusCode)>,
+    ) -> anyhow::Result<Self> {
+        self.redirects = Redirects::new(redirects)?;
+        Ok(self)
+    }
+
+    /// Normalize a trailing slash on request paths with `policy` before anything else routes
+    /// them. Redirects are a `308 Permanent Redirect` which never reaches the guest. Paths are
+    /// passed on as they are by default.
+    pub fn trailing_slash(mut self, policy: TrailingSlash) -> Self {
+        self.trailing_slash = policy;
+        self
+    }
+
+    /// Respond with a `502 Bad Gateway` when the guest sets more than `count` response headers,
+    /// or when their names and values add up to more than `size` bytes
+    pub fn max_response_headers(mut self, count: usize, size: usize) -> Self {
+        self.max_response_headers = count;
+        self.max_response_headers_size = size;
+        self
+    }
+
+    /// Respond with a `502 Bad Gateway` when the headers of a guest response, including the ones
+    /// added by the host, take more than `size` bytes to send
+    pub fn max_response_header_bytes(mut self, size: usize) -> Self {
+        self.max_response_header_bytes = size;
+        self
+    }
+
+    /// Buffer at most `size` bytes of the request line and headers of a request while parsing
+    /// it, and close the connection of requests with larger heads before they reach the guest.
+    /// This is 64 KiB by default, and at least 8 KiB.
+    pub fn max_request_head_size(mut self, size: usize) -> Self {
+        self.max_request_head_size = size.max(MIN_REQUEST_HEAD_SIZE);
+        self
+    }
+
+    /// Truncate the file, target, and fields of guest logs to `size` bytes each
+    pub fn max_log_size(mut self, size: usize) -> Self {
+        self.max_log_size = size;
+        self
+    }
+
+    /// Serve the files in `dir` for GET and HEAD requests with paths starting with `prefix`,
+    /// without calling the guest. Requests for files which do not exist are passed to the guest.
+    /// A precompressed `.gz` sidecar of a file is served instead to clients accepting gzip.
+    pub fn static_files(mut self, prefix: impl Into<String>, dir: impl AsRef<Path>) -> Self {
+        self.static_files.push(StaticFiles::new(prefix, dir));
+        self
+    }
+
+    /// Only try the redirects and static files when the guest defers a request to the host by
+    /// responding with [DEFER_TO_HOST_STATUS], instead of before calling the guest
+    pub fn host_routes_after_guest(mut self, enabled: bool) -> Self {
+        self.host_routes_after_guest = enabled;
+        self
+    }
+
+    /// Remove these headers from requests before they are passed to the guest, such as headers
+    /// added by internal proxies
+    pub fn strip_request_headers(mut self, headers: Vec<HeaderName>) -> Self {
+        self.strip_request_headers = headers;
+        self
+    }
+
+    /// Remove these headers from guest responses before they are sent to the client, such as
+    /// `Server` or internal debug headers
+    pub fn strip_response_headers(mut self, headers: Vec<HeaderName>) -> Self {
+        self.strip_response_headers = headers;
+        self
+    }
+
+    /// Send guest responses with a status in `remap` with the status it maps to instead, like
+    /// a custom `499` as a `500` for proxies which reject unknown statuses. Other statuses are
+    /// sent as they are. Every remapped response is logged.
+    pub fn status_remap(mut self, remap: HashMap<hyper::StatusCode, hyper::StatusCode>) -> Self {
+        self.status_remap = remap;
+        self
+    }
+
+    /// Serve these pages instead of the default body for errors the host generates with their
+    /// status, like a branded page for a `503 Service Unavailable` while starting. Responses
+    /// from the guest are never replaced, and other statuses keep the default body.
+    pub fn error_pages(mut self, pages: HashMap<hyper::StatusCode, ErrorPage>) -> Self {
+        self.error_pages = pages;
+        self
+    }
+
+    /// Give the guest these secrets, like API keys or database URLs, as environment variables.
+    /// Every request sees the same secrets, and their values are never logged.
+    pub fn secrets(mut self, secrets: HashMap<String, String>) -> anyhow::Result<Self> {
+        self.secrets = Secrets::new(secrets)?;
+        Ok(self)
+    }
+
+    /// Write HTTP/1 response header names in Title-Case (like `Content-Type`) instead of lowercase
+    /// for clients which depend on a specific casing. The casing set by the guest cannot be
+    /// preserved since it is normalized when the response headers are passed to the host.
+    pub fn title_case_headers(mut self, enabled: bool) -> Self {
+        self.title_case_headers = enabled;
+        self
+    }
+
+    /// Render the error responses generated by the host as RFC 7807 `application/problem+json`
+    /// documents instead of empty bodies. Responses from the guest are never changed.
+    pub fn problem_json(mut self, enabled: bool) -> Self {
+        self.problem_json = enabled;
+        self
+    }
+
+    /// Handle CORS on the host. Preflight requests are answered without calling the guest and
+    /// the CORS headers are added to the responses of all other requests.
+    pub fn cors(mut self, cors: CorsConfig) -> Self {
+        self.cors = Some(cors);
+        self
+    }
+
+    /// Record requests and the guest's responses so they can be replayed with [replay].
+    /// This buffers the whole response body of every request while enabled.
+    pub fn capture(mut self, capture: CaptureConfig) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Add a warning to the deployment's logs for every request which spends longer than
+    /// `threshold` in wasm, even if it succeeds
+    pub fn slow_request_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_request_threshold = Some(threshold);
+        self
+    }
+
+    /// Content to serve for `/favicon.ico` without calling the guest, or `None` to let the
+    /// guest handle it. Defaults to a transparent icon.
+    pub fn favicon(mut self, favicon: Option<Vec<u8>>) -> Self {
+        self.builtin_responses.favicon = favicon.map(Into::into);
+        self.builtin_responses.last_modified = SystemTime::now();
+        self
+    }
+
+    /// Content to serve for `/robots.txt` without calling the guest, or `None` to let the
+    /// guest handle it. Defaults to allowing all crawlers.
+    pub fn robots_txt(mut self, robots_txt: Option<String>) -> Self {
+        self.builtin_responses.robots_txt = robots_txt.map(Into::into);
+        self.builtin_responses.last_modified = SystemTime::now();
+        self
+    }
+
+    /// Reject a share of new requests with a `503 Service Unavailable` while the process uses
+    /// too much memory, as configured by `memory_pressure`. In-flight requests are not affected.
+    pub fn memory_pressure(mut self, memory_pressure: MemoryPressureConfig) -> Self {
+        self.memory_pressure = Some(memory_pressure);
+        self
+    }
+
+    /// Remember the responses to requests with an `Idempotency-Key` header for `ttl`, and answer
+    /// retries with the same key with the remembered response instead of calling the guest again.
+    /// Only methods which are not safe to repeat, like `POST`, are tracked, for at most
+    /// `max_keys` keys at a time. Keys are only ever matched within one deployment.
+    pub fn idempotency_keys(mut self, ttl: Duration, max_keys: usize) -> Self {
+        self.idempotency_keys = Some((ttl, max_keys));
+        self
+    }
+
+    /// Reject new requests with a `503 Service Unavailable` while handling them would take the
+    /// file descriptors of the unix streams of all in-flight requests over `max`. Each request
+    /// uses six of them, on top of its connection.
+    pub fn max_request_fds(mut self, max: u64) -> Self {
+        self.fd_limit = Some(FdLimit::Fixed(max));
+        self
+    }
+
+    /// Like [RouterBuilder::max_request_fds], but as a `percent` of the limit on open files of
+    /// the process when the router is built. There is no budget when that limit is unlimited.
+    pub fn max_request_fds_percent(mut self, percent: u8) -> Self {
+        self.fd_limit = Some(FdLimit::PercentOfRlimit(percent));
+        self
+    }
+
+    /// Let concurrent identical `GET` and `HEAD` requests without credentials wait for the
+    /// first of them and share its response, instead of each calling the guest. The shared
+    /// responses are buffered, and responses which set cookies or are private are not shared.
+    pub fn coalesce_requests(mut self, enabled: bool) -> Self {
+        self.coalesce_requests = enabled;
+        self
+    }
+
+    /// Send a `GET` request to `path` when starting, before the service is reported as started,
+    /// so that the first real request does not pay the cold start cost. A failing warm-up request
+    /// is logged but does not fail the start.
+    pub fn warmup_path(mut self, path: impl Into<String>) -> Self {
+        self.warmup_path = Some(path.into());
+        self
+    }
+
+    /// Answer health checks on `path`, like `/__health`, with a JSON report. Guests with a
+    /// `__SHUTTLE_health` export report on their dependencies with it, and are otherwise healthy
+    /// when they can be instantiated. Unhealthy guests get a `503 Service Unavailable`. Health
+    /// checks go through the same host filtering and instance rate limiting as other requests,
+    /// but are answered in maintenance.
+    pub fn health_path(mut self, path: impl Into<String>) -> Self {
+        self.health_path = Some(path.into());
+        self
+    }
+
+    /// Answer `GET` requests on `path`, like `/__routes`, with a JSON array of the routes the
+    /// guest advertises in its `shuttle:routes` custom section, as `{"path": .., "methods":
+    /// [..]}` objects. Guests which advertise no routes get an empty array. The guest never
+    /// sees these requests.
+    pub fn routes_path(mut self, path: impl Into<String>) -> Self {
+        self.routes_path = Some(path.into());
+        self
+    }
+
+    /// Reject requests with bodies larger than `size` bytes with a `413 Payload Too Large`.
+    /// Routes the guest gives their own limit get that limit instead, up to the
+    /// [RouterBuilder::max_route_body_size].
+    ///
+    /// Guests give their routes limits in a `shuttle:body-limits` custom section of the module,
+    /// rather than with an export, so the host can read them once when the module is loaded
+    /// without instantiating it. The section holds a JSON object from route to limit in bytes,
+    /// like `{"/upload": 104857600, "/files/*": 1048576}`, where a route ending in `*` matches
+    /// every path with that prefix.
+    pub fn max_body_size(mut self, size: u64) -> Self {
+        self.max_body_size = size;
+        self
+    }
+
+    /// Cap the body limits the guest gives its routes at `size` bytes, since their bodies are
+    /// buffered in host memory. A route is never capped below the
+    /// [RouterBuilder::max_body_size]. Defaults to 16 MiB.
+    pub fn max_route_body_size(mut self, size: u64) -> Self {
+        self.max_route_body_size = size;
+        self
+    }
+
+    /// Explain why a request body was rejected for being too large with `message`, where
+    /// `{limit}` is replaced by the limit in bytes. This is opt-in since it reveals the limit.
+    pub fn payload_too_large_message(mut self, message: impl Into<String>) -> Self {
+        self.payload_too_large.message = Some(message.into());
+        self
+    }
+
+    /// Point clients whose request body is too large to documentation at `url` using a
+    /// `Link` header
+    pub fn payload_too_large_docs(mut self, url: &str) -> anyhow::Result<Self> {
+        let link = HeaderValue::from_str(&format!("<{url}>; rel=\"help\""))
+            .context("documentation url should be a valid header value")?;
+
+        self.payload_too_large.docs = Some(link);
+        Ok(self)
+    }
+
+    /// Run guest calls on a dedicated pool of up to one OS thread per CPU, instead of tokio's
+    /// shared blocking pool
+    pub fn guest_thread_pool(mut self, enabled: bool) -> Self {
+        self.guest_pool_size = enabled.then(GuestPool::default_size);
+        self
+    }
+
+    /// Run guest calls on a dedicated pool of up to `size` OS threads
+    pub fn guest_thread_pool_size(mut self, size: usize) -> Self {
+        self.guest_pool_size = Some(size);
+        self
+    }
+
+    /// Stop guest pool threads which have not run a guest call for `timeout`, so the pool
+    /// shrinks during quiet periods. Threads are kept forever by default.
+    pub fn guest_thread_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.guest_idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Pipe the request body to the guest's stdin instead of the body file descriptor, for
+    /// guests adapted from CLI tools
+    pub fn body_as_stdin(mut self, enabled: bool) -> Self {
+        self.body_as_stdin = enabled;
+        self
+    }
+
+    /// Stream request bodies into the guest while it runs instead of reading them whole first.
+    /// At most `high_water_mark` bytes are buffered which the guest has not read yet, and the
+    /// client has to wait while the guest reads slower than it sends. Bodies piped to stdin or
+    /// captured are still read whole.
+    pub fn stream_request_body(mut self, high_water_mark: usize) -> Self {
+        self.request_body_high_water_mark = Some(high_water_mark);
+        self
+    }
+
+    /// Run guest calls on the task handling their request and forward their logs once they
+    /// return, instead of on blocking threads while they run. With a single-threaded tokio
+    /// runtime requests are then handled one at a time and their logs arrive in a predictable
+    /// order, which is meant for tests. Request bodies are never streamed and the guest thread
+    /// pool is not used. Timeouts and aborts still apply, since the epoch is advanced from a
+    /// thread of its own.
+    pub fn deterministic(mut self, enabled: bool) -> Self {
+        self.deterministic = enabled;
+        self
+    }
+
+    /// Log where in the guest a request failed when it trapped, with the names of the wasm
+    /// functions it was in, at debug level. This is off by default to keep guests which trap
+    /// often from flooding the logs.
+    pub fn wasm_backtraces(mut self, enabled: bool) -> Self {
+        self.wasm_backtraces = enabled;
+        self
+    }
+
+    /// Log the raw messagepack of the request parts written to the guest and the response
+    /// parts it wrote back as hex at debug level, to debug the wire protocol between them. At
+    /// most ten messages a minute are dumped. The dumps contain request headers like cookies,
+    /// so this is for debugging only and should never be enabled in production.
+    pub fn debug_wire(mut self, enabled: bool) -> Self {
+        self.debug_wire = enabled;
+        self
+    }
+
+    /// Also write every forwarded log to stdout as a JSON line, with its timestamp, level,
+    /// deployment id, request id, message and remaining fields, for log collectors. The
+    /// deployment id is the name of the service the deployment was loaded for.
+    pub fn json_logs(mut self, enabled: bool) -> Self {
+        self.json_logs = enabled;
+        self
+    }
+
+    /// Also write every forwarded log to the file at `path` as a JSON line, the same way
+    /// [RouterBuilder::json_logs] writes them to stdout, rotating it by `rotation`. The logs are
+    /// written whether or not anything subscribed to them.
+    pub fn log_to_file<P: AsRef<Path>>(mut self, path: P, rotation: LogRotation) -> Self {
+        self.log_file = Some((path.as_ref().to_path_buf(), rotation));
+        self
+    }
+
+    /// Turn `POST` requests with an `X-HTTP-Method-Override` header of `PUT`, `PATCH` or
+    /// `DELETE` into requests with that method before the guest sees them, for clients which
+    /// can only send `GET` and `POST`. Other methods in the header are ignored. This is off by
+    /// default, since it lets clients pick a method intermediaries did not see.
+    pub fn method_override(mut self, enabled: bool) -> Self {
+        self.method_override = enabled;
+        self
+    }
+
+    /// Answer `OPTIONS` requests on the host with a `204 No Content` and an `Allow` header
+    /// listing the methods the guest advertises for the path in its `shuttle:routes` custom
+    /// section, or every common method when it advertises none. CORS preflights are still
+    /// answered by the CORS layer when one is configured. This is off by default, which leaves
+    /// `OPTIONS` requests to the guest.
+    pub fn auto_options(mut self, enabled: bool) -> Self {
+        self.auto_options = enabled;
+        self
+    }
+
+    /// Trap the guest once it makes more than `limit` host calls, like WASI functions, while
+    /// handling one request. The calls of every request are counted on its span either way.
+    pub fn max_host_calls(mut self, limit: u64) -> Self {
+        self.max_host_calls = Some(limit);
+        self
+    }
+
+    /// Keep `size` instances of the module ready for requests, made when the server starts and
+    /// again in the background as requests take them. This lowers the latency of the first
+    /// requests at the cost of a slower start and the memory of the ready instances. Modules
+    /// mounted on a path are still instantiated per request.
+    pub fn warm_pool_size(mut self, size: usize) -> Self {
+        self.warm_pool_size = size;
+        self
+    }
+
+    /// Create at most `rate` instances of the module per second for requests, so a burst of
+    /// requests does not instantiate all at once. Requests wait for their turn, and get a `503
+    /// Service Unavailable` when it is further away than the max instance wait. Instances from
+    /// the warm pool are not limited.
+    pub fn max_instances_per_second(mut self, rate: u32) -> Self {
+        self.instance_rate.per_second = Some(rate.max(1));
+        self
+    }
+
+    /// Let `burst` instances be created at once after a quiet period, despite the instance
+    /// rate. This is one by default.
+    pub fn instance_burst(mut self, burst: u32) -> Self {
+        self.instance_rate.burst = burst.max(1);
+        self
+    }
+
+    /// Wait at most `wait` for the turn to create an instance when the instance rate is
+    /// limited. This is one second by default.
+    pub fn max_instance_wait(mut self, wait: Duration) -> Self {
+        self.instance_rate.max_wait = wait;
+        self
+    }
+
+    /// Stop the server once it has been running for `lifetime`, as if it was asked to stop.
+    /// Stopping it earlier cancels this.
+    pub fn max_lifetime(mut self, lifetime: Duration) -> Self {
+        self.max_lifetime = Some(lifetime);
+        self
+    }
+
+    /// How long to wait for in-flight requests to finish after the server is stopped, before
+    /// reporting the remaining ones as dropped
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Explain with `detail` why requests which arrive after the server is bound, but before it
+    /// is ready to serve, get a `503 Service Unavailable`. The server is not ready while the
+    /// warm-up request is being handled.
+    pub fn startup_detail(mut self, detail: impl Into<String>) -> Self {
+        self.startup_detail = Some(detail.into());
+        self
+    }
+
+    /// How long clients are told to wait before retrying a request which arrived before the
+    /// server was ready. Defaults to a second.
+    pub fn startup_retry_after(mut self, retry_after: Duration) -> Self {
+        self.startup_retry_after = retry_after;
+        self
+    }
+
+    /// Reload the module when its `src` file changes. Requests which already started finish
+    /// on the old module, while new requests use the new one.
+    pub fn watch_source(mut self, watch: bool) -> Self {
+        self.watch_source = watch;
+        self
+    }
+
+    /// Retry loading the module from `src` up to `attempts` times, `delay` apart, when it fails
+    /// in a way which can pass, like when the file is missing or still being written while a
+    /// deploy stages it. Invalid modules fail right away. There are no retries by default.
+    pub fn module_load_retries(mut self, attempts: u32, delay: Duration) -> Self {
+        self.load_retries = LoadRetries { attempts, delay };
+        self
+    }
+
+    /// Let trusted callers raise the body limit of a request up to `ceiling` bytes with an
+    /// `X-Max-Body-Override` header signed using `secret`. Unsigned, invalid or expired headers
+    /// are ignored, and the header is never passed on to the guest. See [BodyLimitOverride] for
+    /// the format of the header.
+    pub fn body_limit_override(mut self, secret: &[u8], ceiling: u64) -> Self {
+        self.body_limit_override = Some(BodyLimitOverride::new(secret, ceiling));
+        self
+    }
+
+    /// Rewrite the bodies of guest responses with `transform` as they are streamed to the client.
+    /// The `Content-Length` of transformed responses is removed since it may no longer match.
+    pub fn body_transform(mut self, transform: impl ResponseBodyTransform) -> Self {
+        self.body_transform = Some(Arc::new(transform));
+        self
+    }
+
+    /// Pass the context `provider` gives each request, like the user an auth check identified,
+    /// to the guest in `x-shuttle-context-*` headers. Clients cannot send these headers
+    /// themselves, so the guest can trust them.
+    pub fn request_context(mut self, provider: impl RequestContext) -> Self {
+        self.request_context = Some(Arc::new(provider));
+        self
+    }
+
+    /// Reject responses whose body does not match their `Content-Type` with a `502 Bad Gateway`.
+    /// This buffers the whole response body for the content types that are checked.
+    pub fn strict_content_type(mut self, strict: bool) -> Self {
+        self.strict_content_type = strict;
+        self
+    }
+
+    fn build(self) -> anyhow::Result<Router> {
+        let mounts = Mounts::load(&self.engine, self.mounts)?;
+
+        let loaded = match (&self.module_bytes, &self.src) {
+            (Some(bytes), _) => Some(LoadedModule::from_bytes(&self.engine, bytes)?),
+            (None, Some(file)) => Some(LoadedModule::from_file_with_retries(
+                &self.engine,
+                file,
+                &self.load_retries,
+            )?),
+            (None, None) if !mounts.is_empty() => None,
+            (None, None) => bail!("module path should be set"),
+        };
+
+        let module = loaded.map(|loaded| {
+            for export in loaded.module.exports() {
+                trace!("export: {}", export.name());
+            }
+
+            Arc::new(SwappableModule::new(loaded))
+        });
+
+        Ok(Router {
+            linker: self.linker,
+            engine: self.engine,
+            module,
+            mounts: Arc::new(mounts),
+            watched_src: self
+                .src
+                .filter(|_| self.watch_source && self.module_bytes.is_none()),
+            strip_request_headers: Arc::new(self.strip_request_headers),
+            strip_response_headers: Arc::new(self.strip_response_headers),
+            status_remap: Arc::new(self.status_remap),
+            error_pages: Arc::new(ErrorPages::new(self.error_pages)),
+            redirects: Arc::new(self.redirects),
+            trailing_slash: self.trailing_slash,
+            static_files: Arc::new(self.static_files),
+            host_routes_after_guest: self.host_routes_after_guest,
+            max_response_headers: self.max_response_headers,
+            max_response_headers_size: self.max_response_headers_size,
+            max_response_header_bytes: self.max_response_header_bytes,
+            max_request_head_size: self.max_request_head_size,
+            max_log_size: self.max_log_size,
+            default_response_headers: Arc::new(self.default_response_headers),
+            default_content_type: self.default_content_type,
+            strict_content_type: self.strict_content_type,
+            body_write_timeout: self.body_write_timeout,
+            instantiate_timeout: self.instantiate_timeout,
+            handler_timeout: self.handler_timeout,
+            request_timeout: self.request_timeout,
+            response_chunk_size: self.response_chunk_size,
+            response_buffer_threshold: self.response_buffer_threshold,
+            allowed_hosts: self.allowed_hosts.map(Arc::new),
+            title_case_headers: self.title_case_headers,
+            problem_json: self.problem_json,
+            cors: self.cors.map(Arc::new),
+            capture: self.capture.map(Capture::open).transpose()?.map(Arc::new),
+            slow_request_threshold: self.slow_request_threshold,
+            builtin_responses: Arc::new(self.builtin_responses),
+            warmup_path: self.warmup_path,
+            health_path: self.health_path,
+            routes_path: self.routes_path,
+            body_limit_override: self.body_limit_override.map(Arc::new),
+            max_body_size: self.max_body_size,
+            max_route_body_size: self.max_route_body_size,
+            payload_too_large: Arc::new(self.payload_too_large),
+            body_as_stdin: self.body_as_stdin,
+            request_body_high_water_mark: self.request_body_high_water_mark,
+            deterministic: self.deterministic,
+            wasm_backtraces: self.wasm_backtraces,
+            wire_tap: self.debug_wire.then(|| {
+                warn!(
+                    "dumping raw wire messages with request headers, which is only for debugging"
+                );
+
+                Arc::new(WireTap::new())
+            }),
+            json_logs: self.json_logs,
+            method_override: self.method_override,
+            auto_options: self.auto_options,
+            log_file: self
+                .log_file
+                .map(|(path, rotation)| LogFile::open(path, rotation))
+                .transpose()?
+                .map(Arc::new),
+            deployment_id: Arc::from(""),
+            keep_alive: self.keep_alive,
+            max_host_calls: self.max_host_calls,
+            max_lifetime: self.max_lifetime,
+            drain_timeout: self.drain_timeout,
+            ready: Arc::new(AtomicBool::new(true)),
+            startup_detail: self.startup_detail.map(Arc::from),
+            startup_retry_after: self.startup_retry_after,
+            body_transform: self.body_transform,
+            request_context: self.request_context,
+            coalescer: self.coalesce_requests.then(Default::default),
+            idempotency: self
+                .idempotency_keys
+                .map(|(ttl, max_keys)| Arc::new(IdempotencyStore::new(ttl, max_keys))),
+            memory_pressure: self
+                .memory_pressure
+                .map(|config| Arc::new(MemoryPressure::new(config))),
+            fd_budget: self
+                .fd_limit
+                .map(FdLimit::resolve)
+                .transpose()?
+                .flatten()
+                .map(|max| Arc::new(FdBudget::new(max))),
+            guest_pool: self
+                .guest_pool_size
+                .map(|size| Arc::new(GuestPool::new(size, self.guest_idle_timeout))),
+            instance_rate: InstanceRateLimiter::new(&self.instance_rate).map(Arc::new),
+            warm_pool: (self.warm_pool_size > 0)
+                .then(|| Arc::new(WarmPool::new(self.warm_pool_size))),
+            guest: Arc::new(WasmGuest),
+            tags: Default::default(),
+            metadata: Default::default(),
+            stats: Default::default(),
+            maintenance: Default::default(),
+            aborts: Default::default(),
+            deployment_slot: None,
+            wasi_template: Arc::new(WasiTemplate::from_env(&self.secrets)),
+        })
+    }
+}
+
+/// The parts of a WASI context which are the same for every request, so that they only
+/// need to be collected once
+struct WasiTemplate {
+    args: Vec<String>,
+    envs: Vec<(String, String)>,
+}
+
+impl WasiTemplate {
+    fn from_env(secrets: &Secrets) -> Self {
+        Self {
+            args: std::env::args().collect(),
+            envs: secrets.envs(),
+        }
+    }
+
+    /// Build a fresh context for a single request from this template
+    fn build(&self) -> anyhow::Result<WasiCtx> {
+        let wasi = WasiCtxBuilder::new()
+            .inherit_stdio()
+            .args(&self.args)
+            .context("failed to set args")?
+            .envs(&self.envs)
+            .context("failed to set secrets")?
+            .build();
+
+        Ok(wasi)
+    }
+}
+
+#[derive(Clone)]
+struct Router {
+    linker: Linker<WasiCtx>,
+    engine: Engine,
+    module: Option<Arc<SwappableModule>>,
+    mounts: Arc<Mounts>,
+    watched_src: Option<PathBuf>,
+    strip_request_headers: Arc<Vec<HeaderName>>,
+    strip_response_headers: Arc<Vec<HeaderName>>,
+    status_remap: Arc<HashMap<hyper::StatusCode, hyper::StatusCode>>,
+    error_pages: Arc<ErrorPages>,
+    redirects: Arc<Redirects>,
+    trailing_slash: TrailingSlash,
+    static_files: Arc<Vec<StaticFiles>>,
+    host_routes_after_guest: bool,
+    max_response_headers: usize,
+    max_response_headers_size: usize,
+    max_response_header_bytes: usize,
+    max_request_head_size: usize,
+    max_log_size: usize,
+    default_response_headers: Arc<HeaderMap>,
+    default_content_type: HeaderValue,
+    strict_content_type: bool,
+    body_write_timeout: Duration,
+    instantiate_timeout: Option<Duration>,
+    handler_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    response_chunk_size: usize,
+    response_buffer_threshold: Option<usize>,
+    allowed_hosts: Option<Arc<Vec<String>>>,
+    stats: Arc<RequestStats>,
+    maintenance: Arc<Maintenance>,
+    aborts: Arc<AbortRegistry>,
+    deployment_slot: Option<Arc<DeploymentSlot>>,
+    wasi_template: Arc<WasiTemplate>,
+    title_case_headers: bool,
+    problem_json: bool,
+    cors: Option<Arc<CorsConfig>>,
+    capture: Option<Arc<Capture>>,
+    slow_request_threshold: Option<Duration>,
+    builtin_responses: Arc<BuiltinResponses>,
+    tags: Arc<Tags>,
+    metadata: Arc<DeploymentMetadata>,
+    warmup_path: Option<String>,
+    health_path: Option<String>,
+    routes_path: Option<String>,
+    body_limit_override: Option<Arc<BodyLimitOverride>>,
+    max_body_size: u64,
+    max_route_body_size: u64,
+    payload_too_large: Arc<PayloadTooLarge>,
+    body_as_stdin: bool,
+    request_body_high_water_mark: Option<usize>,
+    deterministic: bool,
+    wasm_backtraces: bool,
+    wire_tap: Option<Arc<WireTap>>,
+    json_logs: bool,
+    method_override: bool,
+    auto_options: bool,
+    log_file: Option<Arc<LogFile>>,
+    deployment_id: Arc<str>,
+    keep_alive: KeepAlive,
+    max_host_calls: Option<u64>,
+    max_lifetime: Option<Duration>,
+    drain_timeout: Duration,
+    ready: Arc<AtomicBool>,
+    startup_detail: Option<Arc<str>>,
+    startup_retry_after: Duration,
+    body_transform: Option<Arc<dyn ResponseBodyTransform>>,
+    request_context: Option<Arc<dyn RequestContext>>,
+    memory_pressure: Option<Arc<MemoryPressure>>,
+    fd_budget: Option<Arc<FdBudget>>,
+    coalescer: Option<Arc<Coalescer>>,
+    idempotency: Option<Arc<IdempotencyStore>>,
+    guest_pool: Option<Arc<GuestPool>>,
+    instance_rate: Option<Arc<InstanceRateLimiter>>,
+    warm_pool: Option<Arc<WarmPool<Store<WasiCtx>>>>,
+    /// Handles the requests which get past the host
+    guest: Arc<dyn Guest>,
+}
+
+impl Router {
+    /// Report request counters to `stats` instead of this router's own counters
+    fn with_stats(mut self, stats: Arc<RequestStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Let the runtime abort the requests of this router
+    fn with_aborts(mut self, aborts: Arc<AbortRegistry>) -> Self {
+        self.aborts = aborts;
+        self
+    }
+
+    /// Follow the maintenance mode toggled on the runtime
+    fn with_maintenance(mut self, maintenance: Arc<Maintenance>) -> Self {
+        self.maintenance = maintenance;
+        self
+    }
+
+    /// Label everything this router logs with `tags`
+    fn with_tags(mut self, tags: Tags) -> Self {
+        self.tags = Arc::new(tags);
+        self
+    }
+
+    /// Run guest calls with `guest` instead of the router function of the wasm module
+    #[cfg(test)]
+    fn with_guest(mut self, guest: Arc<dyn Guest>) -> Self {
+        self.guest = guest;
+        self
+    }
+
+    /// Identify the deployment of this router by `id` in its JSON logs
+    fn with_deployment_id(mut self, id: &str) -> Self {
+        self.deployment_id = Arc::from(id);
+        self
+    }
+
+    /// Add `metadata` to everything this router logs
+    fn with_metadata(mut self, metadata: DeploymentMetadata) -> Self {
+        self.metadata = Arc::new(metadata);
+        self
+    }
+
+    /// Hold on to `slot` for as long as this router (or any of its clones) is alive
+    fn with_deployment_slot(mut self, slot: Arc<DeploymentSlot>) -> Self {
+        self.deployment_slot = Some(slot);
+        self
+    }
+
+    /// Build the response for an error generated by the host
+    fn error_response(&self, error: HostError) -> Response<Body> {
+        self.error_pages
+            .response(error.status())
+            .unwrap_or_else(|| error.into_response(self.problem_json))
+    }
+
+    /// Build the response for a request which is shed to protect the process
+    fn shed_response(&self) -> Response<Body> {
+        self.stats.shed.fetch_add(1, Ordering::Relaxed);
+
+        let mut response = self.error_response(HostError::Overloaded);
+        response
+            .headers_mut()
+            .insert(hyper::header::RETRY_AFTER, HeaderValue::from_static("1"));
+
+        response
+    }
+
+    /// Build the response for requests which arrive before the router is ready
+    fn starting_response(&self) -> Response<Body> {
+        let mut response = match &self.startup_detail {
+            Some(detail) if !self.error_pages.contains(HostError::Starting.status()) => {
+                HostError::Starting.into_response_with_detail(self.problem_json, detail)
+            }
+            _ => self.error_response(HostError::Starting),
+        };
+        response.headers_mut().insert(
+            hyper::header::RETRY_AFTER,
+            HeaderValue::from(self.startup_retry_after.as_secs()),
+        );
+
+        response
+    }
+
+    /// Send a synthetic request through the guest to get it ready for real traffic
+    async fn warm_up(&self, path: &str, logs_tx: Sender<Result<runtime::LogItem, Status>>) {
+        // The warm-up request should not be filtered or counted like a real request, and is
+        // served while the router is not ready for real requests yet
+        let mut router = self.clone();
+        router.allowed_hosts = None;
+        router.stats = Default::default();
+        router.aborts = Default::default();
+        router.ready = Arc::new(AtomicBool::new(true));
+
+        let request = match Request::get(path).body(Body::empty()) {
+            Ok(request) => request,
+            Err(error) => {
+                warn!(%error, path, "invalid warm-up path");
+                return;
+            }
+        };
+
+        let start = Instant::now();
+        match router.handle_request(request, logs_tx).await {
+            Ok(response) if !response.status().is_server_error() => {
+                trace!(path, elapsed = ?start.elapsed(), "warm-up request done");
+            }
+            Ok(response) => {
+                warn!(path, status = %response.status(), "warm-up request failed");
+            }
+            Err(error) => warn!(%error, path, "warm-up request failed"),
+        }
+    }
+
+    /// Make a fresh instance of `module` for a single request
+    fn instantiate(&self, module: &Module) -> anyhow::Result<Store<WasiCtx>> {
+        let wasi = self.wasi_template.build()?;
+
+        let mut store = Store::new(&self.engine, wasi);
+        self.linker.module(&mut store, "axum", module)?;
+
+        Ok(store)
+    }
+
+    /// Keep the warm pool filled with instances of the module, if it has one
+    fn refill_warm_pool(&self) -> Option<tokio::task::JoinHandle<()>> {
+        let warm_pool = self.warm_pool.clone()?;
+        let module = self.module.clone()?;
+        let router = self.clone();
+
+        Some(tokio::spawn(warm_pool.refill(move || {
+            let (module, generation) = module.versioned();
+
+            Ok((generation, router.instantiate(&module)?))
+        })))
+    }
+
+    /// Check the health of the guest in a fresh instance of the module
+    async fn health_response(&self, head: bool) -> Response<Body> {
+        let Some(module) = self.module.as_ref().map(|module| module.current()) else {
+            return HealthReport::failed("no module is loaded").into_response(head);
+        };
+
+        if let Some(instance_rate) = &self.instance_rate {
+            if !instance_rate.acquire().await {
+                warn!("shedding health check since its turn to create an instance is too far away");
+
+                return self.shed_response();
+            }
+        }
+
+        let router = self.clone();
+        let checked = tokio::task::spawn_blocking(move || {
+            let store = router.instantiate(&module)?;
+
+            health::check(&router.linker, &module, store)
+        })
+        .await;
+
+        let report = match checked {
+            Ok(Ok(report)) => report,
+            Ok(Err(error)) => {
+                warn!(error = %error, "health check of the guest failed");
+
+                HealthReport::failed("the health check failed")
+            }
+            Err(error) => {
+                warn!(error = %error, "health check of the guest panicked");
+
+                HealthReport::failed("the health check failed")
+            }
+        };
+
+        report.into_response(head)
+    }
+
+    /// Check if any redirects or static files are configured
+    fn has_host_routes(&self) -> bool {
+        !self.redirects.is_empty() || !self.static_files.is_empty()
+    }
+
+    /// Get the response from the redirects or static files for a request, if any match it
+    async fn host_route<B>(&self, req: &Request<B>) -> Option<Response<Body>> {
+        if let Some(response) = self.redirects.respond(req) {
+            return Some(response);
+        }
+
+        for static_files in self.static_files.iter() {
+            if let Some(response) = static_files.respond(req).await {
+                return Some(response);
+            }
+        }
+
+        None
+    }
+
+    /// Respond with a guest response body which was read whole, so it is sent in one frame with
+    /// an exact `Content-Length`
+    fn buffered_response(
+        &self,
+        mut wrapper: ResponseWrapper,
+        mut body: Vec<u8>,
+        transformer: Option<Box<dyn BodyTransformer>>,
+    ) -> anyhow::Result<Response<Body>> {
+        if let Some(mut transformer) = transformer {
+            let mut transformed = transformer.transform(body);
+            transformed.extend(transformer.finish());
+            body = transformed;
+        }
+
+        if !body.is_empty() {
+            set_default_content_type(&mut wrapper.headers, &self.default_content_type);
+            wrapper
+                .headers
+                .insert(hyper::header::CONTENT_LENGTH, HeaderValue::from(body.len()));
+        }
+
+        if self.response_headers_too_large(&wrapper.headers) {
+            return Ok(self.error_response(HostError::InvalidResponse));
+        }
+
+        wrapper
+            .into_response_builder()
+            .body(body.into())
+            .context("failed to construct http response")
+    }
+
+    /// Check if the headers of a guest response take more bytes to send than allowed
+    fn response_headers_too_large(&self, headers: &HeaderMap) -> bool {
+        let size = serialized_headers_size(headers);
+
+        if size > self.max_response_header_bytes {
+            warn!(
+                size,
+                limit = self.max_response_header_bytes,
+                "response headers are too large to send"
+            );
+
+            return true;
+        }
+
+        false
+    }
+
+    /// Build the response for a request body over `limit` bytes
+    fn payload_too_large(&self, limit: u64) -> Response<Body> {
+        let mut response = match &self.payload_too_large.message {
+            Some(message)
+                if !self
+                    .error_pages
+                    .contains(HostError::PayloadTooLarge.status()) =>
+            {
+                HostError::PayloadTooLarge.into_response_with_detail(
+                    self.problem_json,
+                    &message.replace("{limit}", &limit.to_string()),
+                )
+            }
+            _ => self.error_response(HostError::PayloadTooLarge),
+        };
+
+        if let Some(docs) = &self.payload_too_large.docs {
+            response
+                .headers_mut()
+                .insert(hyper::header::LINK, docs.clone());
+        }
+
+        response
+    }
+
+    /// Get the maximum body size allowed for a request, which is the limit the guest gives its
+    /// route up to the route ceiling or else the default, unless a signed override raises it
+    fn body_limit(&self, parts: &Parts, route_body_limits: &RouteBodyLimits) -> u64 {
+        let route_ceiling = self.max_route_body_size.max(self.max_body_size);
+        let default_limit = route_body_limits
+            .limit_for(parts.uri.path())
+            .map_or(self.max_body_size, |limit| limit.min(route_ceiling));
+
+        let override_limit = self
+            .body_limit_override
+            .as_ref()
+            .and_then(|body_limit_override| body_limit_override.limit_for(parts));
+
+        match override_limit {
+            Some(limit) => limit.max(default_limit),
+            None => default_limit,
+        }
+    }
+
+    /// Create a log item for the deployment's logs which comes from the host rather than the guest
+    fn host_log(&self, level: Level, fields: serde_json::Value) -> runtime::LogItem {
+        let mut log = Log {
+            level,
+            timestamp: Utc::now(),
+            file: String::new(),
+            line: 0,
+            target: "shuttle_next".to_string(),
+            fields: serde_json::to_vec(&fields).expect("json values should serialize"),
+        }
+        .into();
+
+        self.tags.apply(&mut log);
+        self.metadata.apply(&mut log);
+
+        log
+    }
+
+    /// Forward a log of the host to the subscribers
+    async fn forward_log(
+        &self,
+        logs_tx: &Sender<Result<runtime::LogItem, Status>>,
+        log: runtime::LogItem,
+    ) {
+        if self.json_logs {
+            json_logs::write_json_line(&self.deployment_id, &log);
+        }
+
+        if let Some(log_file) = &self.log_file {
+            log_file.write(&self.deployment_id, &log);
+        }
+
+        if logs_tx.send(Ok(log)).await.is_err() {
+            self.stats.dropped_logs.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Handle a request, sharing the response with identical requests when coalescing is enabled
+    async fn handle_request(
+        &self,
+        req: hyper::Request<Body>,
+        logs_tx: Sender<Result<runtime::LogItem, Status>>,
+    ) -> anyhow::Result<Response<Body>> {
+        if let Some((store, key)) = self
+            .idempotency
+            .clone()
+            .zip(IdempotencyKey::for_request(&req))
+        {
+            return self.serve_idempotent(&store, key, req, logs_tx).await;
+        }
+
+        let Some((coalescer, key)) = self.coalescer.clone().zip(CoalesceKey::for_request(&req))
+        else {
+            return self.serve_request(req, logs_tx).await;
+        };
+
+        let leader = match coalescer.join(key) {
+            Flight::Leader(leader) => leader,
+            Flight::Follower(mut rx) => {
+                let in_flight = self.stats.track();
+
+                if let Ok(shared) = rx.recv().await {
+                    trace!(uri = %req.uri(), "coalesced request with an identical one");
+                    return Ok(shared.to_response());
+                }
+
+                // The identical request failed or its response cannot be shared
+                drop(in_flight);
+                return self.serve_request(req, logs_tx).await;
+            }
+        };
+
+        let response = self.serve_request(req, logs_tx).await?;
+
+        if !coalesce::is_shareable(response.headers()) {
+            return Ok(response);
+        }
+
+        let shared = Arc::new(
+            SharedResponse::buffer(response)
+                .await
+                .context("failed to buffer the response to share")?,
+        );
+        leader.finish(shared.clone());
+
+        Ok(shared.to_response())
+    }
+
+    /// Serve a request with an idempotency key, or replay the response to an earlier request
+    /// with the same key
+    async fn serve_idempotent(
+        &self,
+        store: &Arc<IdempotencyStore>,
+        key: IdempotencyKey,
+        req: hyper::Request<Body>,
+        logs_tx: Sender<Result<runtime::LogItem, Status>>,
+    ) -> anyhow::Result<Response<Body>> {
+        let guard = match store.begin(key) {
+            Idempotency::New(guard) => guard,
+            Idempotency::Replay(shared) => {
+                trace!(uri = %req.uri(), "replaying the response for an idempotency key");
+
+                let mut response = shared.to_response();
+                response.headers_mut().insert(
+                    HeaderName::from_static("idempotent-replayed"),
+                    HeaderValue::from_static("true"),
+                );
+
+                return Ok(response);
+            }
+            Idempotency::InFlight => return Ok(self.error_response(HostError::IdempotencyConflict)),
+            Idempotency::Reused => return Ok(self.error_response(HostError::IdempotencyKeyReused)),
+            Idempotency::Untracked => {
+                warn!("idempotency store is full, handling the request without its key");
+
+                return self.serve_request(req, logs_tx).await;
+            }
+        };
+
+        let response = self.serve_request(req, logs_tx).await?;
+
+        // Failures are not remembered, so a retry can succeed
+        if response.status().is_server_error() {
+            return Ok(response);
+        }
+
+        let shared = Arc::new(
+            SharedResponse::buffer(response)
+                .await
+                .context("failed to buffer the response to remember")?,
+        );
+        guard.finish(shared.clone());
+
+        Ok(shared.to_response())
+    }
+
+    /// Send a HTTP request with body to given endpoint on the axum-wasm router and return the response
+    async fn serve_request(
+        &self,
+        mut req: hyper::Request<Body>,
+        logs_tx: Sender<Result<runtime::LogItem, Status>>,
+    ) -> anyhow::Result<Response<Body>> {
+        let in_flight = self.stats.track();
+        let timeouts = RequestTimeouts::start(
+            self.instantiate_timeout,
+            self.body_write_timeout,
+            self.handler_timeout,
+            self.request_timeout,
+        );
+
+        if !self.ready.load(Ordering::Acquire) {
+            return Ok(self.starting_response());
+        }
+
+        if self
+            .memory_pressure
+            .as_ref()
+            .is_some_and(|memory_pressure| memory_pressure.should_shed())
+        {
+            return Ok(self.shed_response());
+        }
+
+        if let Some(reason) = ambiguous_framing(req.headers()) {
+            warn!(reason, "rejecting request with ambiguous body framing");
+
+            return Ok(self.error_response(HostError::AmbiguousFraming));
+        }
+
+        if let Some(reason) = malformed_uri(req.uri()) {
+            warn!(reason, uri = %req.uri(), "rejecting request with a malformed uri");
+
+            return Ok(self.error_response(HostError::MalformedUri));
+        }
+
+        if let Some(allowed_hosts) = &self.allowed_hosts {
+            let host = request_host(&req).unwrap_or_default();
+
+            if !is_host_allowed(host, allowed_hosts) {
+                warn!(host, "rejecting request for a host that is not allowed");
+
+                return Ok(self.error_response(HostError::MisdirectedRequest));
+            }
+        }
+
+        // Health checks are answered even in maintenance, since the guest is still there
+        if self.health_path.as_deref() == Some(req.uri().path())
+            && (req.method() == hyper::Method::GET || req.method() == hyper::Method::HEAD)
+        {
+            return Ok(self
+                .health_response(req.method() == hyper::Method::HEAD)
+                .await);
+        }
+
+        if self.routes_path.as_deref() == Some(req.uri().path())
+            && (req.method() == hyper::Method::GET || req.method() == hyper::Method::HEAD)
+        {
+            let routes = self
+                .module
+                .as_ref()
+                .map(|module| module.route_methods())
+                .unwrap_or_default();
+
+            return Ok(route_listing::listing_response(
+                &routes,
+                req.method() == hyper::Method::HEAD,
+            ));
+        }
+
+        if let Some(response) = self.maintenance.respond(&req) {
+            return Ok(response);
+        }
+
+        if self.method_override {
+            if let Some(method) = method_override::apply(&mut req) {
+                trace!(%method, "overrode the method of the request");
+            }
+        }
+
+        if let Some(response) = self.trailing_slash.apply(&mut req) {
+            return Ok(response);
+        }
+
+        let origin = req.headers().get(hyper::header::ORIGIN).cloned();
+
+        if let Some(cors) = &self.cors {
+            if CorsConfig::is_preflight(&req) {
+                return Ok(cors.preflight_response(origin.as_ref()));
+            }
+        }
+
+        if let Some(response) = self.builtin_responses.respond(&req) {
+            return Ok(response);
+        }
+
+        if !self.host_routes_after_guest {
+            if let Some(response) = self.host_route(&req).await {
+                return Ok(response);
+            }
+        }
+
+        self.call_wasm(req, logs_tx, in_flight.id, &timeouts, origin)
+            .await
+    }
+
+    /// Handle a request which got past the host with a fresh instance of the wasm module it is
+    /// routed to
+    async fn call_wasm(
+        &self,
+        req: hyper::Request<Body>,
+        logs_tx: Sender<Result<runtime::LogItem, Status>>,
+        request_id: u64,
+        timeouts: &RequestTimeouts,
+        origin: Option<HeaderValue>,
+    ) -> anyhow::Result<Response<Body>> {
+        // Pick the module before instantiating anything, so only the matched module is used
+        let mounted = self.mounts.module_for(req.uri().path());
+        let Some(swappable) = mounted.or(self.module.as_ref()) else {
+            trace!(
+                path = req.uri().path(),
+                "no module is mounted for the request"
+            );
+
+            return Ok(self.error_response(HostError::NotFound));
+        };
+        let (module, generation) = swappable.versioned();
+        let route_body_limits = swappable.body_limits();
+
+        if self.auto_options && req.method() == hyper::Method::OPTIONS {
+            return Ok(swappable.route_methods().options_response(req.uri().path()));
+        }
+
+        // Keep what the host routes need in case the guest defers to the host
+        let deferred_request = self.has_host_routes().then(|| {
+            let mut deferred_request = Request::new(());
+            *deferred_request.method_mut() = req.method().clone();
+            *deferred_request.uri_mut() = req.uri().clone();
+            *deferred_request.headers_mut() = req.headers().clone();
+            deferred_request
+        });
+
+        // Shed before paying for an instance which could not get its unix streams anyway
+        let fd_permit = match &self.fd_budget {
+            Some(fd_budget) => match fd_budget.try_acquire() {
+                Some(fd_permit) => Some(fd_permit),
+                None => {
+                    warn!("shedding request since its unix streams would exceed the fd budget");
+
+                    return Ok(self.shed_response());
+                }
+            },
+            None => None,
+        };
+
+        let warm = self
+            .warm_pool
+            .as_ref()
+            .filter(|_| mounted.is_none())
+            .and_then(|warm_pool| warm_pool.take(generation));
+
+        if let Some(instance_rate) = self.instance_rate.as_ref().filter(|_| warm.is_none()) {
+            if !instance_rate.acquire().await {
+                warn!(
+                    request_id = request_id,
+                    "shedding request since its turn to create an instance is too far away"
+                );
+
+                return Ok(self.shed_response());
+            }
+        }
+
+        let instantiate_timeout = timeouts.instantiate();
+        let instantiation_start = Instant::now();
+        let mut store = match warm {
+            Some(store) => store,
+            None => self.instantiate(&module)?,
+        };
+        let instantiation = instantiation_start.elapsed();
+
+        if instantiate_timeout.is_some_and(|timeout| instantiation > timeout) {
+            warn!(
+                request_id = request_id,
+                ?instantiation,
+                timeout = ?instantiate_timeout,
+                "instantiating the module took too long"
+            );
+
+            return Ok(self.error_response(HostError::InstantiateTimeout));
+        }
+
+        let (logs_stream, logs_client) =
+            UnixStream::pair().context("failed to open logs unixstream")?;
+        let (mut parts_stream, parts_client) =
+            UnixStream::pair().context("failed to open parts unixstream")?;
+        let (mut body_stream, body_client) =
+            UnixStream::pair().context("failed to open body write unixstream")?;
+
+        let streams = GuestStreams {
+            logs: logs_client,
+            parts: parts_client,
+            body: body_client,
+        };
+
+        let host_logs_tx = logs_tx.clone();
+
+        let tags = self.tags.clone();
+        let metadata = self.metadata.clone();
+        let max_log_size = self.max_log_size;
+        let write_json = self.json_logs;
+        let log_file = self.log_file.clone();
+        let deployment_id = self.deployment_id.clone();
+        let sequence = Arc::new(LogSequence::new(request_id));
+        let guest_sequence = sequence.clone();
+        let label_log = move |log: Log| {
+            let mut log = log.into();
+            guest_sequence.apply(&mut log);
+            tags.apply(&mut log);
+            metadata.apply(&mut log);
+
+            if write_json {
+                json_logs::write_json_line(&deployment_id, &log);
+            }
+
+            if let Some(log_file) = &log_file {
+                log_file.write(&deployment_id, &log);
+            }
+
+            log
+        };
+
+        // Deterministic calls forward their logs once the guest is done with them. They are
+        // still read while it runs, so a guest which logs more than the logs stream buffers
+        // does not block on it.
+        let deferred_logs = if self.deterministic {
+            Some(tokio::task::spawn_blocking(move || {
+                let mut logs = Vec::new();
+                read_guest_logs(logs_stream, max_log_size, |log| logs.push(label_log(log)));
+
+                logs
+            }))
+        } else {
+            let stats = self.stats.clone();
+
+            tokio::task::spawn_blocking(move || {
+                read_guest_logs(logs_stream, max_log_size, |log| {
+                    if logs_tx.blocking_send(Ok(label_log(log))).is_err() {
+                        stats.dropped_logs.fetch_add(1, Ordering::Relaxed);
+                    }
+                })
+            });
+
+            None
+        };
+
+        let (mut parts, body) = req.into_parts();
+
+        let path = parts.uri.path().to_owned();
+        let is_head = parts.method == hyper::Method::HEAD;
+        let body_limit = self.body_limit(&parts, &route_body_limits);
+
+        strip_headers(&mut parts.headers, &self.strip_request_headers);
+        if self.body_limit_override.is_some() {
+            parts.headers.remove(body_limit::OVERRIDE_HEADER);
+        }
+        if let Some(request_context) = &self.request_context {
+            request_context::apply(request_context.as_ref(), &mut parts);
+        }
+
+        let captured_request = self.capture.as_ref().map(|_| RequestWrapper {
+            method: parts.method.clone(),
+            uri: parts.uri.clone(),
+            version: parts.version,
+            headers: parts.headers.clone(),
+        });
+
+        // Serialise request parts to rmp
+        let request_rmp = RequestWrapper::from(parts)
+            .into_rmp()
+            .context("failed to make request wrapper")?;
+
+        if let Some(wire_tap) = &self.wire_tap {
+            wire_tap.dump("request", &request_rmp, request_rmp.len());
+        }
+
+        // Write request parts to wasm module. Parts larger than the socket buffer only fit once
+        // the guest reads them, so a guest which never does would otherwise hang this worker.
+        let write_timeout = timeouts.write();
+        parts_stream
+            .set_write_timeout(Some(write_timeout))
+            .context("failed to set parts write timeout")?;
+
+        if let Err(error) = parts_stream.write_all(&request_rmp) {
+            if !matches!(
+                error.kind(),
+                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+            ) {
+                return Err(error).context("failed to write http parts to wasm");
+            }
+
+            warn!(
+                request_id = request_id,
+                %error,
+                timeout = ?write_timeout,
+                "guest did not read its request parts in time"
+            );
+
+            let mut log = self.host_log(
+                Level::Error,
+                serde_json::json!({
+                    "message": "guest did not read its request parts fd",
+                    "parts_size": request_rmp.len(),
+                }),
+            );
+            sequence.apply(&mut log);
+
+            self.forward_log(&host_logs_tx, log).await;
+
+            return Ok(self.error_response(HostError::GuestNotReading));
+        }
+
+        // To protect our server, reject requests with bodies larger than
+        // 64kbs of data, unless a trusted caller raised the limit.
+        let body_size = body.size_hint().upper().unwrap_or(u64::MAX);
+
+        if body_size > body_limit {
+            let response = self.payload_too_large(body_limit);
+
+            // Return early if body is too big
+            return Ok(response);
+        }
+
+        // Bound the write so a guest that stops reading cannot hang its writer
+        let write_timeout = timeouts.write();
+        body_stream
+            .set_write_timeout(Some(write_timeout))
+            .context("failed to set body write timeout")?;
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let streamed_body_high_water_mark = self
+            .request_body_high_water_mark
+            .filter(|_| !self.body_as_stdin && !self.deterministic && captured_request.is_none());
+
+        let (body_bytes, body_writer) = match streamed_body_high_water_mark {
+            Some(high_water_mark) => {
+                let stream = body_stream
+                    .try_clone()
+                    .context("failed to clone body unixstream")?;
+                let body_streamer = request_body::stream_body(
+                    body,
+                    stream,
+                    high_water_mark,
+                    body_limit,
+                    cancelled.clone(),
+                );
+
+                (Bytes::new(), Some(body_streamer))
+            }
+            None => {
+                let body_bytes = hyper::body::to_bytes(body)
+                    .await
+                    .context("failed to concatenate request body buffers")?;
+
+                let body_fd_bytes = if self.body_as_stdin {
+                    store
+                        .data_mut()
+                        .set_stdin(Box::new(ReadPipe::new(std::io::Cursor::new(
+                            body_bytes.clone(),
+                        ))));
+
+                    Bytes::new()
+                } else {
+                    body_bytes.clone()
+                };
+
+                // Write body to wasm while the guest reads it
+                let stream = body_stream
+                    .try_clone()
+                    .context("failed to clone body unixstream")?;
+                let body_writer =
+                    request_body::write_body(body_fd_bytes, stream, cancelled.clone());
+
+                (body_bytes, Some(body_writer))
+            }
+        };
+
+        // Call our function in wasm, telling it to route the request we've written to it
+        // and write back a response
+        trace!("calling Router");
+        let call = self
+            .linker
+            .get(&mut store, "axum", "__SHUTTLE_Axum_call")
+            .context("wasm module should be loaded and the router function should be available")?
+            .into_func()
+            .context("router function should be a function")?
+            .typed::<(RawFd, RawFd, RawFd), ()>(&store)?;
+
+        // Stop the guest once the client has gone away or the request body could not be written
+        // to it, once an operator aborted the request, or once it ran out of time to respond
+        let abort_guard = self.aborts.register(request_id);
+        let host_calls = HostCalls::new(self.max_host_calls);
+        host_calls.watch(&mut store);
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let interrupt = CallInterrupt {
+            cancelled: cancelled.clone(),
+            aborted: abort_guard.flag(),
+            timed_out: timed_out.clone(),
+            handler_deadline: timeouts.handler_deadline(),
+        };
+
+        // Hyper drops this future when the client disconnects, which is only noticed if the
+        // call is not blocking the future itself
+        let cancel_guard = CancelOnDrop::new(cancelled.clone());
+        let call_start = Instant::now();
+        let guest = self.guest.clone();
+        let run_call = move || guest.call(store, call, streams, interrupt);
+        let call_result = match &self.guest_pool {
+            _ if self.deterministic => run_call(),
+            Some(pool) => pool.run(run_call).await?,
+            None => tokio::task::spawn_blocking(run_call)
+                .await
+                .context("wasm call panicked")?,
+        };
+        cancel_guard.disarm();
+
+        // The store is gone with the call, so the logs stream ends after the logs of this call
+        if let Some(deferred_logs) = deferred_logs {
+            let logs = deferred_logs.await.context("guest logs reader panicked")?;
+
+            for log in logs {
+                if host_logs_tx.send(Ok(log)).await.is_err() {
+                    self.stats.dropped_logs.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+        // The writer sets `cancelled` when it fails, before the guest could see the end of the
+        // body, so the guest only responded to the whole body if it is not set
+        if let Some(body_writer) = body_writer {
+            if !cancelled.load(Ordering::Relaxed) {
+                // The guest may have responded without reading the whole body
+                body_writer.abort();
+            } else if let Err(error) = body_writer.await.context("request body writer panicked")? {
+                match error {
+                    request_body::RequestBodyError::TooLarge(limit) => {
+                        return Ok(self.payload_too_large(limit));
+                    }
+                    request_body::RequestBodyError::Write(error)
+                        if matches!(
+                            error.kind(),
+                            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                        ) =>
+                    {
+                        warn!(
+                            request_id = request_id,
+                            timeout = ?write_timeout,
+                            "guest did not read the request body in time"
+                        );
+
+                        return Ok(self.error_response(HostError::GuestNotReading));
+                    }
+                    error => return Err(error).context("failed to write the request body to wasm"),
+                }
+            }
+        }
+
+        Span::current().record("host_calls", host_calls.count());
+
+        if let Err(error) = call_result {
+            if host_calls.exceeded() {
+                warn!(
+                    request_id = request_id,
+                    %path,
+                    "trapped the guest for making too many host calls"
+                );
+
+                let mut log = self.host_log(
+                    Level::Warn,
+                    serde_json::json!({
+                        "message": "request made too many host calls",
+                        "path": path,
+                        "host_calls": host_calls.count(),
+                    }),
+                );
+                sequence.apply(&mut log);
+
+                self.forward_log(&host_logs_tx, log).await;
+
+                return Ok(self.error_response(HostError::Internal));
+            }
+
+            if timed_out.load(Ordering::Relaxed) {
+                warn!(
+                    request_id = request_id,
+                    %path,
+                    handler = ?call_start.elapsed(),
+                    "trapped the guest for taking too long to respond"
+                );
+
+                let mut log = self.host_log(
+                    Level::Warn,
+                    serde_json::json!({
+                        "message": "request timed out in the handler",
+                        "path": path,
+                        "duration_ms": call_start.elapsed().as_millis() as u64,
+                    }),
+                );
+                sequence.apply(&mut log);
+
+                self.forward_log(&host_logs_tx, log).await;
+
+                return Ok(self.error_response(HostError::HandlerTimeout));
+            }
+
+            if !abort_guard.is_aborted() {
+                return Err(error);
+            }
+
+            warn!(request_id = request_id, %path, "aborted the request in wasm");
+
+            let mut log = self.host_log(
+                Level::Warn,
+                serde_json::json!({
+                    "message": "request was aborted",
+                    "path": path,
+                }),
+            );
+            sequence.apply(&mut log);
+
+            self.forward_log(&host_logs_tx, log).await;
+
+            return Ok(self.error_response(HostError::Internal));
+        }
+        drop(abort_guard);
+
+        let elapsed = call_start.elapsed();
+        let span = Span::current();
+        span.record("instantiation_us", instantiation.as_micros() as u64);
+        span.record("handler_us", elapsed.as_micros() as u64);
+        trace!(?instantiation, handler = ?elapsed, "guest call done");
+
+        if self
+            .slow_request_threshold
+            .is_some_and(|threshold| elapsed > threshold)
+        {
+            let mut log = self.host_log(
+                Level::Warn,
+                serde_json::json!({
+                    "message": "slow request",
+                    "path": path,
+                    "duration_ms": elapsed.as_millis() as u64,
+                }),
+            );
+            sequence.apply(&mut log);
+
+            self.forward_log(&host_logs_tx, log).await;
+        }
+
+        // Read response parts from wasm
+        let mut reader = BufReader::new(Recorder::new(&mut parts_stream, self.wire_tap.is_some()));
+        let dump_response = |reader: &BufReader<Recorder<_>>| {
+            if let Some(wire_tap) = &self.wire_tap {
+                let recorder = reader.get_ref();
+                wire_tap.dump("response", recorder.recorded(), recorder.size());
+            }
+        };
+
+        // Deserialize response parts from rust messagepack
+        let mut wrapper: ResponseWrapper = match rmps::from_read(&mut reader) {
+            Ok(wrapper) => wrapper,
+            Err(error) => {
+                dump_response(&reader);
+
+                // A guest which crashed or returned before writing anything leaves the
+                // parts empty, which is a different failure from writing malformed parts
+                if reader.get_ref().size() == 0 {
+                    warn!(
+                        request_id = request_id,
+                        %path,
+                        "guest returned without writing a response"
+                    );
+
+                    let mut log = self.host_log(
+                        Level::Error,
+                        serde_json::json!({
+                            "message": "guest produced no response",
+                            "path": path,
+                        }),
+                    );
+                    sequence.apply(&mut log);
+
+                    self.forward_log(&host_logs_tx, log).await;
+
+                    return Ok(self.error_response(HostError::NoResponse));
+                }
+
+                return Err(error).context("failed to deserialize response parts");
+            }
+        };
+        dump_response(&reader);
+
+        let headers_size = headers_size(&wrapper.headers);
+        if wrapper.headers.len() > self.max_response_headers
+            || headers_size > self.max_response_headers_size
+        {
+            warn!(
+                count = wrapper.headers.len(),
+                size = headers_size,
+                "guest responded with too many headers"
+            );
+
+            return Ok(self.error_response(HostError::InvalidResponse));
+        }
+
+        if wrapper.status.as_u16() == DEFER_TO_HOST_STATUS {
+            trace!(%path, "guest deferred the request to the host");
+
+            let response = match &deferred_request {
+                Some(deferred_request) => self.host_route(deferred_request).await,
+                None => None,
+            };
+
+            return Ok(response.unwrap_or_else(|| self.error_response(HostError::NotFound)));
+        }
+
+        if let Some(&status) = self.status_remap.get(&wrapper.status) {
+            info!(
+                request_id = request_id,
+                %path,
+                from = %wrapper.status,
+                to = %status,
+                "remapped the status of the guest response"
+            );
+
+            wrapper.status = status;
+        }
+
+        strip_headers(&mut wrapper.headers, &self.strip_response_headers);
+        merge_default_headers(&mut wrapper.headers, &self.default_response_headers);
+
+        if let Some(cors) = &self.cors {
+            cors.apply(origin.as_ref(), &mut wrapper.headers);
+        }
+
+        let check_json = self.strict_content_type && is_json(&wrapper.headers);
+
+        let transformer = self
+            .body_transform
+            .as_deref()
+            .and_then(|transform| transform::transformer_for(transform, &wrapper.headers));
+        if transformer.is_some() {
+            wrapper.headers.remove(hyper::header::CONTENT_LENGTH);
+        }
+
+        if check_json || captured_request.is_some() {
+            let mut response_bytes = Vec::new();
+            if let Err(error) = body_stream.read_to_end(&mut response_bytes) {
+                error!(%error, "failed to read response body from the guest");
+
+                return Ok(self.error_response(HostError::InvalidResponse));
+            }
+
+            if check_json
+                && serde_json::from_slice::<serde::de::IgnoredAny>(&response_bytes).is_err()
+            {
+                warn!("guest response claims to be JSON but the body is not valid JSON");
+
+                return Ok(self.error_response(HostError::InvalidResponse));
+            }
+
+            if let (Some(capture), Some(request)) = (&self.capture, captured_request) {
+                let response = ResponseWrapper {
+                    status: wrapper.status,
+                    version: wrapper.version,
+                    headers: wrapper.headers.clone(),
+                };
+
+                capture.record(request, &body_bytes, response, &response_bytes);
+            }
+
+            return self.buffered_response(wrapper, response_bytes, transformer);
+        }
+
+        // Read the start of the response body while we can still respond with an error: the
+        // first chunk, or the whole body when it is small enough to buffer. Any later failures
+        // abort the connection so the client sees an incomplete transfer.
+        let mut chunks = body_chunks(body_stream, self.response_chunk_size);
+        let buffer_threshold = self.response_buffer_threshold.unwrap_or(0);
+        let mut head = Vec::new();
+        let mut head_size = 0;
+        let complete = loop {
+            match chunks.next() {
+                Some(Ok(chunk)) => {
+                    head_size += chunk.len();
+                    head.push(chunk);
+                }
+                Some(Err(_)) => return Ok(self.error_response(HostError::InvalidResponse)),
+                None => break true,
+            }
+
+            if head_size > buffer_threshold {
+                break false;
+            }
+        };
+
+        if complete && self.response_buffer_threshold.is_some() {
+            return self.buffered_response(wrapper, head.concat(), transformer);
+        }
+
+        // A length the guest declared is kept, so the body is not sent chunked, unless hyper
+        // could not frame the body by it
+        let declared_length = declared_content_length(&wrapper.headers);
+        if declared_length.is_none() && wrapper.headers.contains_key(hyper::header::CONTENT_LENGTH)
+        {
+            warn!("dropping the invalid Content-Length of the guest response");
+            wrapper.headers.remove(hyper::header::CONTENT_LENGTH);
+        }
+
+        let has_body = !is_head
+            && wrapper.status != hyper::StatusCode::NO_CONTENT
+            && wrapper.status != hyper::StatusCode::NOT_MODIFIED;
+
+        if head_size > 0 {
+            set_default_content_type(&mut wrapper.headers, &self.default_content_type);
+        }
+
+        if self.response_headers_too_large(&wrapper.headers) {
+            return Ok(self.error_response(HostError::InvalidResponse));
+        }
+
+        let head = head.into_iter().map(Ok);
+        let chunks: Box<dyn Iterator<Item = std::io::Result<Vec<u8>>> + Send> = match transformer {
+            Some(transformer) => {
+                Box::new(transform::transform_chunks(head.chain(chunks), transformer))
+            }
+            None => Box::new(head.chain(chunks)),
+        };
+        let chunks: Box<dyn Iterator<Item = std::io::Result<Vec<u8>>> + Send> =
+            match declared_length {
+                Some(length) if has_body => Box::new(check_length(chunks, length)),
+                _ => chunks,
+            };
+
+        // The body stream is read until the client has the whole response, so keep its file
+        // descriptors counted until then
+        let chunks = chunks.map(move |chunk| {
+            let _fd_permit = &fd_permit;
+            chunk
+        });
+
+        // Convert the rest of the response body to a Stream and pass it to hyper
+        let stream = futures::stream::iter(chunks);
+        let body = hyper::Body::wrap_stream(stream);
+
+        let response: Response<Body> = wrapper
+            .into_response_builder()
+            .body(body)
+            .context("failed to construct http response")?;
+
+        Ok(response)
+    }
+}
+
+/// Cancels a request in wasm when dropped, unless it was disarmed after the request completed
+struct CancelOnDrop {
+    cancelled: Arc<AtomicBool>,
+    armed: bool,
+}
+
+impl CancelOnDrop {
+    fn new(cancelled: Arc<AtomicBool>) -> Self {
+        Self {
+            cancelled,
+            armed: true,
+        }
+    }
+
+    fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if self.armed {
+            warn!("client disconnected, cancelling the request in wasm");
+            self.cancelled.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Read the logs of a guest until it closes its end of `logs_stream`, passing each to `forward`
+fn read_guest_logs(logs_stream: impl Read, max_log_size: usize, mut forward: impl FnMut(Log)) {
+    let mut iter = logs_stream.bytes().filter_map(Result::ok);
+
+    while let Some((log, truncated)) = Log::from_bytes_capped(&mut iter, max_log_size) {
+        if truncated {
+            warn!(max_log_size, "truncated an oversized guest log");
+        }
+
+        forward(log);
+    }
+}
+
+/// Get the length of its body a guest declared with `Content-Length`, unless it is missing or
+/// not a single valid length
+fn declared_content_length(headers: &HeaderMap) -> Option<u64> {
+    let mut lengths = headers
+        .get_all(hyper::header::CONTENT_LENGTH)
+        .iter()
+        .map(|value| value.to_str().ok()?.trim().parse::<u64>().ok());
+
+    let length = lengths.next()??;
+
+    lengths.all(|other| other == Some(length)).then_some(length)
+}
+
+/// Fail a response body once it turns out not to have the `length` its `Content-Length`
+/// declared, so the transfer breaks instead of the client getting a cut short body
+fn check_length<I>(mut chunks: I, length: u64) -> impl Iterator<Item = std::io::Result<Vec<u8>>>
+where
+    I: Iterator<Item = std::io::Result<Vec<u8>>>,
+{
+    let mut sent = 0;
+    let mut failed = false;
+
+    std::iter::from_fn(move || {
+        if failed {
+            return None;
+        }
+
+        let mismatch = match chunks.next() {
+            Some(Ok(chunk)) => {
+                sent += chunk.len() as u64;
+                if sent <= length {
+                    return Some(Ok(chunk));
+                }
+
+                "longer"
+            }
+            None if sent < length => "shorter",
+            other => return other,
+        };
+
+        failed = true;
+        warn!(
+            length,
+            "guest response body is {mismatch} than its Content-Length"
+        );
+
+        Some(Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("response body is {mismatch} than its Content-Length"),
+        )))
+    })
+}
+
+/// Read a guest's response body in chunks of at most `chunk_size` bytes.
+/// Stops after the first error, which is logged.
+fn body_chunks<R: Read>(
+    mut reader: R,
+    chunk_size: usize,
+) -> impl Iterator<Item = std::io::Result<Vec<u8>>> {
+    let mut failed = false;
+
+    std::iter::from_fn(move || {
+        if failed {
+            return None;
+        }
+
+        let mut buf = vec![0; chunk_size];
+
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => return None,
+                Ok(len) => {
+                    buf.truncate(len);
+                    return Some(Ok(buf));
+                }
+                Err(error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(error) => {
+                    error!(%error, "failed to read response body from the guest");
+                    failed = true;
+                    return Some(Err(error));
+                }
+            }
+        }
+    })
+}
+
+/// Check for header combinations which make the length of a request body ambiguous, since
+/// these can be used to smuggle requests past proxies. Returns the reason if any are found.
+fn ambiguous_framing(headers: &HeaderMap) -> Option<&'static str> {
+    let transfer_encodings = headers
+        .get_all(hyper::header::TRANSFER_ENCODING)
+        .iter()
+        .map(|value| value.to_str().unwrap_or_default())
+        .flat_map(|value| value.split(','))
+        .filter(|encoding| !encoding.trim().is_empty())
+        .count();
+    let content_lengths: Vec<_> = headers
+        .get_all(hyper::header::CONTENT_LENGTH)
+        .iter()
+        .collect();
+
+    if transfer_encodings > 0 && !content_lengths.is_empty() {
+        Some("both Transfer-Encoding and Content-Length are set")
+    } else if transfer_encodings > 1 {
+        Some("multiple transfer encodings are set")
+    } else if content_lengths.windows(2).any(|pair| pair[0] != pair[1]) {
+        Some("conflicting Content-Length values are set")
+    } else {
+        None
+    }
+}
+
+/// Check for URIs which the guest cannot route sensibly, so they are rejected before doing any
+/// work in wasm. Returns the reason if the URI is malformed.
+fn malformed_uri(uri: &hyper::Uri) -> Option<&'static str> {
+    let path = uri.path();
+
+    if uri
+        .scheme_str()
+        .is_some_and(|scheme| scheme != "http" && scheme != "https")
+    {
+        Some("the scheme is not http or https")
+    } else if !path.starts_with('/') {
+        Some("the path is not absolute")
+    } else if path.split('/').count() - 1 > MAX_PATH_SEGMENTS {
+        Some("the path has too many segments")
+    } else if path
+        .chars()
+        .chain(uri.query().unwrap_or_default().chars())
+        .any(|c| c.is_control() || c == '\\')
+    {
+        Some("the uri contains forbidden characters")
+    } else if !is_percent_encoding_valid(path) {
+        Some("the path has an invalid percent-encoding")
+    } else {
+        None
+    }
+}
+
+/// Check that every `%` in `value` starts a percent-encoded byte
+fn is_percent_encoding_valid(value: &str) -> bool {
+    let bytes = value.as_bytes();
+
+    bytes.iter().enumerate().all(|(index, byte)| {
+        *byte != b'%'
+            || bytes
+                .get(index + 1..index + 3)
+                .is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+    })
+}
+
+/// Get the host a request is for, without its port
+fn request_host<B>(req: &Request<B>) -> Option<&str> {
+    let host = req
+        .headers()
+        .get(hyper::header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| req.uri().host())?;
+
+    // Keep IPv6 literals intact while stripping the port
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !name.ends_with(':') && port.bytes().all(|b| b.is_ascii_digit()) => {
+            name
+        }
+        _ => host,
+    };
+
+    Some(host)
+}
+
+/// Check if `host` matches any of the `allowed` hosts, where `*.` matches any subdomain
+fn is_host_allowed(host: &str, allowed: &[String]) -> bool {
+    allowed
+        .iter()
+        .any(|allowed| match allowed.strip_prefix("*.") {
+            Some(domain) => host
+                .len()
+                .checked_sub(domain.len() + 1)
+                .filter(|&dot| host.as_bytes()[dot] == b'.' && dot > 0)
+                .map(|dot| host[dot + 1..].eq_ignore_ascii_case(domain))
+                .unwrap_or(false),
+            None => host.eq_ignore_ascii_case(allowed),
+        })
+}
+
+/// Add every header from `defaults` that is not already present in `headers`
+fn merge_default_headers(headers: &mut HeaderMap, defaults: &HeaderMap) {
+    for name in defaults.keys() {
+        if !headers.contains_key(name) {
+            for value in defaults.get_all(name) {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Check if the client asked for its connection to be closed after the response
+fn requests_close(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(hyper::header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|option| option.trim().eq_ignore_ascii_case("close"))
+}
+
+/// Set the `Content-Type` header to `default` if it is missing
+fn set_default_content_type(headers: &mut HeaderMap, default: &HeaderValue) {
+    headers
+        .entry(hyper::header::CONTENT_TYPE)
+        .or_insert_with(|| default.clone());
+}
+
+/// The total size of the names and values in `headers`
+fn headers_size(headers: &HeaderMap) -> usize {
+    headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len())
+        .sum()
+}
+
+/// The number of bytes `headers` take in an HTTP/1 response, as `name: value\r\n` lines
+fn serialized_headers_size(headers: &HeaderMap) -> usize {
+    headers_size(headers) + headers.len() * ": \r\n".len()
+}
+
+/// Remove every value of the `names` headers
+fn strip_headers(headers: &mut HeaderMap, names: &[HeaderName]) {
+    for name in names {
+        headers.remove(name);
+    }
+}
+
+/// Check if the `Content-Type` header declares a JSON body
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(hyper::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|mime| {
+            let mime = mime.trim();
+            mime.eq_ignore_ascii_case("application/json") || mime.ends_with("+json")
+        })
+        .unwrap_or(false)
+}
+
+/// Start or stop shedding requests as the memory usage of the process changes, and tell the
+/// deployment's logs when it does
+async fn monitor_memory_pressure(
+    router: Router,
+    pressure: Arc<MemoryPressure>,
+    logs_tx: Sender<Result<runtime::LogItem, Status>>,
+) {
+    let mut interval = tokio::time::interval(pressure.check_interval());
+
+    loop {
+        interval.tick().await;
+
+        let rss = match memory_pressure::current_rss() {
+            Ok(rss) => rss,
+            Err(error) => {
+                warn!(%error, "failed to check memory usage, requests will not be shed");
+                return;
+            }
+        };
+
+        let log = match pressure.update(rss) {
+            Some(true) => {
+                warn!(rss, "memory usage is high, shedding new requests");
+
+                router.host_log(
+                    Level::Warn,
+                    serde_json::json!({
+                        "message": "memory usage is high, shedding new requests",
+                        "rss_bytes": rss,
+                    }),
+                )
+            }
+            Some(false) => {
+                info!(rss, "memory usage recovered, no longer shedding requests");
+
+                router.host_log(
+                    Level::Info,
+                    serde_json::json!({
+                        "message": "memory usage recovered, no longer shedding requests",
+                        "rss_bytes": rss,
+                    }),
+                )
+            }
+            None => continue,
+        };
+
+        router.forward_log(&logs_tx, log).await;
+    }
+}
+
+/// Start a hyper server with a service that calls an axum router in WASM,
+/// and a kill receiver for stopping the server. Returns a summary of the server's run
+/// once it has stopped and the requests in flight have been drained.
+async fn run_until_stopped(
+    router: Router,
+    listener: TcpListener,
+    logs_tx: Sender<Result<runtime::LogItem, Status>>,
+    kill_rx: tokio::sync::oneshot::Receiver<String>,
+    stopped_tx: broadcast::Sender<(StopReason, String)>,
+) -> ShutdownReport {
+    let title_case_headers = router.title_case_headers;
+    let max_request_head_size = router.max_request_head_size;
+    let keep_alive = router.keep_alive.clone();
+    let max_lifetime = router.max_lifetime;
+    let drain_timeout = router.drain_timeout;
+
+    let started_at = Instant::now();
+    let stats = router.stats.clone();
+    let total_at_start = stats.total.load(Ordering::Relaxed);
+    stats
+        .peak_in_flight
+        .store(stats.in_flight.load(Ordering::Relaxed), Ordering::Relaxed);
+
+    let address = listener.local_addr();
+    let server_builder = match Incoming::new(
+        listener,
+        keep_alive.idle_timeout,
+        keep_alive.max_request_duration,
+    ) {
+        Ok(incoming) => hyper::Server::builder(incoming),
+        Err(error) => {
+            error!(%error, "failed to serve on the bound listener");
+            // Nobody may have subscribed to the stop yet
+            let _ = stopped_tx.send((StopReason::Crash, error.to_string()));
+            return ShutdownReport {
+                total_requests: 0,
+                drained_requests: 0,
+                dropped_requests: 0,
+                peak_in_flight_requests: 0,
+                uptime: Duration::ZERO,
+            };
+        }
+    };
+
+    // Advance the epoch so that requests in wasm get a chance to notice they were cancelled
+    let epoch_ticker = EpochTicker::start(router.engine.clone(), EPOCH_TICK);
+
+    let source_watcher =
+        router
+            .watched_src
+            .clone()
+            .zip(router.module.clone())
+            .map(|(path, module)| {
+                tokio::spawn(watch::watch_source(router.engine.clone(), path, module))
+            });
+
+    let warm_pool_refill = router.refill_warm_pool();
+
+    let memory_monitor = router.memory_pressure.clone().map(|memory_pressure| {
+        tokio::spawn(monitor_memory_pressure(
+            router.clone(),
+            memory_pressure,
+            logs_tx.clone(),
+        ))
+    });
+
+    // Connections outlive the server while their requests are drained, so they are closed
+    // after their current response instead of being kept alive for more requests
+    let draining = Arc::new(AtomicBool::new(false));
+
+    let make_service = make_service_fn({
+        let draining = draining.clone();
+        move |conn: &Connection| {
+            let router = router.clone();
+            let logs_tx = logs_tx.clone();
+            let draining = draining.clone();
+            let connection = conn.state();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |mut req: Request<Body>| {
+                    let router = router.clone();
+                    let logs_tx = logs_tx.clone();
+                    let draining = draining.clone();
+                    let span = span::request_span(&mut req);
+                    let active_request = connection.begin_request();
+                    let close_requested = requests_close(req.headers())
+                        || router
+                            .keep_alive
+                            .max_requests
+                            .is_some_and(|max| active_request.number() >= max);
+                    async move {
+                        let start = Instant::now();
+                        let mut response =
+                            match router.handle(req, logs_tx).instrument(span.clone()).await {
+                                Ok(res) => res,
+                                Err(err) => {
+                                    error!("error sending request: {}", err);
+                                    if router.wasm_backtraces {
+                                        if let Some(backtrace) = backtrace::describe(&err) {
+                                            debug!("request failed in wasm: {backtrace}");
+                                        }
+                                    }
+                                    router.error_response(HostError::Internal)
+                                }
+                            };
+
+                        if close_requested || draining.load(Ordering::Relaxed) {
+                            response.headers_mut().insert(
+                                hyper::header::CONNECTION,
+                                HeaderValue::from_static("close"),
+                            );
+                        }
+
+                        span.record("http.status_code", response.status().as_u16());
+                        span.record("duration_ms", start.elapsed().as_millis() as u64);
+
+                        Ok::<_, Infallible>(active_request.hold_until_sent(response))
+                    }
+                }))
+            }
+        }
+    });
+
+    let server = server_builder
+        .http1_title_case_headers(title_case_headers)
+        .http1_max_buf_size(max_request_head_size)
+        .http1_keepalive(keep_alive.enabled)
+        .serve(make_service);
+
+    let lifetime_expired = async move {
+        match max_lifetime {
+            Some(lifetime) => tokio::time::sleep(lifetime).await,
+            None => std::future::pending().await,
+        }
+    };
+
+    trace!(?address, "starting hyper server");
+    tokio::select! {
+        // Nobody may have subscribed to the stop yet, so failed sends are fine in every branch
+        _ = server => {
+            let _ = stopped_tx.send((StopReason::End, String::new()));
+            trace!("axum wasm server stopped");
+        },
+        message = kill_rx => {
+            match message {
+                Ok(msg) =>{
+                    let _ = stopped_tx.send((StopReason::Request, String::new()));
+                    trace!("{msg}")
+                } ,
+                Err(_) => {
+                    let _ = stopped_tx
+                        .send((StopReason::Crash, "the kill sender dropped".to_string()));
+                    trace!("the sender dropped")
+                }
+            }
+        },
+        _ = lifetime_expired => {
+            let _ = stopped_tx.send((
+                StopReason::End,
+                "the maximum lifetime was reached".to_string(),
+            ));
+            trace!("axum wasm server reached its maximum lifetime");
+        }
+    };
+
+    let uptime = started_at.elapsed();
+    draining.store(true, Ordering::Relaxed);
+
+    // Keep the epoch ticking while draining so that requests can still be cancelled
+    let (drained_requests, dropped_requests) = drain(&stats, drain_timeout).await;
+    if dropped_requests > 0 {
+        warn!(
+            dropped_requests,
+            "requests were still in flight after draining"
+        );
+    }
+
+    drop(epoch_ticker);
+
+    if let Some(source_watcher) = source_watcher {
+        source_watcher.abort();
+    }
+
+    if let Some(warm_pool_refill) = warm_pool_refill {
+        warm_pool_refill.abort();
+    }
+
+    if let Some(memory_monitor) = memory_monitor {
+        memory_monitor.abort();
+    }
+
+    ShutdownReport {
+        total_requests: stats.total.load(Ordering::Relaxed) - total_at_start,
+        drained_requests,
+        dropped_requests,
+        peak_in_flight_requests: stats.peak_in_flight.load(Ordering::Relaxed),
+        uptime,
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::process::Command;
+
+    use super::*;
+    use hyper::{http::HeaderValue, Method, Request, StatusCode, Version};
+
+    // Compile axum wasm module
+    fn compile_module() {
+        Command::new("cargo")
+            .arg("build")
+            .arg("--target")
+            .arg("wasm32-wasi")
+            .current_dir("tests/resources/axum-wasm-expanded")
+            .spawn()
+            .unwrap()
+            .wait()
+            .unwrap();
+    }
+
+    // Advance the epoch of the router's engine, which only a running server does otherwise
+    fn tick_epoch(router: &Router) -> EpochTicker {
+        EpochTicker::start(router.engine.clone(), EPOCH_TICK)
+    }
+
+    #[test]
+    fn module_urls() {
+        assert!(is_module_url("https://storage.example/module.wasm"));
+        assert!(is_module_url("http://localhost:8000/module.wasm"));
+        assert!(!is_module_url("/opt/shuttle/module.wasm"));
+        assert!(!is_module_url("module.wasm"));
+    }
+
+    #[cfg(not(feature = "module-url"))]
+    #[tokio::test]
+    async fn module_urls_need_the_feature() {
+        let error = AxumWasm::default()
+            .load(tonic::Request::new(LoadRequest {
+                path: "https://storage.example/module.wasm".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.code(), tonic::Code::InvalidArgument);
+        assert!(error.message().contains("module-url"));
+    }
+
+    #[test]
+    fn default_headers_do_not_override_guest() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-frame-options", HeaderValue::from_static("SAMEORIGIN"));
+
+        let mut defaults = HeaderMap::new();
+        defaults.insert("x-frame-options", HeaderValue::from_static("DENY"));
+        defaults.insert(
+            "x-content-type-options",
+            HeaderValue::from_static("nosniff"),
+        );
+
+        merge_default_headers(&mut headers, &defaults);
+
+        assert_eq!(headers["x-frame-options"], "SAMEORIGIN");
+        assert_eq!(headers["x-content-type-options"], "nosniff");
+    }
+
+    #[test]
+    fn default_content_type_does_not_override_guest() {
+        let default = HeaderValue::from_static(DEFAULT_CONTENT_TYPE);
+
+        let mut headers = HeaderMap::new();
+        set_default_content_type(&mut headers, &default);
+        assert_eq!(headers["content-type"], "application/octet-stream");
+
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", HeaderValue::from_static("text/html"));
+        set_default_content_type(&mut headers, &default);
+        assert_eq!(headers["content-type"], "text/html");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn default_content_type() {
+        compile_module();
+
+        let router = RouterBuilder::new()
+            .unwrap()
+            .src("tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm")
+            .default_content_type("application/x-untyped".to_string())
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let (tx, _rx) = mpsc::channel(64);
+
+        let request = |path: &str| {
+            Request::get(format!("https://axum-wasm.example{path}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // The guest did not set a content type
+        let res = router
+            .clone()
+            .handle_request(request("/untyped"), tx.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers()[hyper::header::CONTENT_TYPE],
+            "application/x-untyped"
+        );
+
+        // The guest did set a content type
+        let res = router.handle_request(request("/method"), tx).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers()[hyper::header::CONTENT_TYPE],
+            "text/plain; charset=utf-8"
+        );
+    }
+
+    #[tokio::test]
+    async fn starting_response_until_ready() {
+        compile_module();
+
+        let router = RouterBuilder::new()
+            .unwrap()
+            .src("tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm")
+            .startup_detail("warming up")
+            .startup_retry_after(Duration::from_secs(5))
+            .build()
+            .unwrap();
+        router.ready.store(false, Ordering::Release);
+
+        let (tx, _rx) = mpsc::channel(64);
+
+        let request = || {
+            Request::get("https://axum-wasm.example/method")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let res = router
+            .clone()
+            .handle_request(request(), tx.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers()[hyper::header::RETRY_AFTER], "5");
+        assert_eq!(
+            hyper::body::to_bytes(res.into_body()).await.unwrap(),
+            "warming up"
+        );
+
+        // The warm-up request is served before the router is ready
+        router.warm_up("/method", tx.clone()).await;
+
+        router.ready.store(true, Ordering::Release);
+        let res = router.handle_request(request(), tx).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn fd_budget_sheds_requests() {
+        compile_module();
+
+        let router = RouterBuilder::new()
+            .unwrap()
+            .src("tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm")
+            .max_request_fds(fd_budget::FDS_PER_REQUEST)
+            .build()
+            .unwrap();
+
+        let (tx, _rx) = mpsc::channel(64);
+
+        let request = || {
+            Request::get("https://axum-wasm.example/method")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // Another request holds the whole budget
+        let permit = router.fd_budget.as_ref().unwrap().try_acquire().unwrap();
+
+        let res = router
+            .clone()
+            .handle_request(request(), tx.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers()[hyper::header::RETRY_AFTER], "1");
+        assert_eq!(router.stats.shed.load(Ordering::Relaxed), 1);
+
+        drop(permit);
+        let res = router.handle_request(request(), tx).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_share_the_logs_channel() {
+        compile_module();
+
+        const REQUESTS: usize = 32;
+
+        let metadata = HashMap::from([("deployment".to_string(), "concurrent-logs".to_string())]);
+        let router = RouterBuilder::new()
+            .unwrap()
+            .src("tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm")
+            .build()
+            .unwrap()
+            .with_metadata(DeploymentMetadata::new(metadata).unwrap());
+
+        // A small channel so the log forwarders of different requests contend for it
+        let (tx, mut rx) = mpsc::channel(4);
+
+        let collector = tokio::spawn(async move {
+            let mut logs = Vec::new();
+            while let Some(log) = rx.recv().await {
+                logs.push(log.unwrap());
+            }
+            logs
+        });
+
+        let responses = futures::future::join_all((0..REQUESTS).map(|id| {
+            let router = router.clone();
+            let tx = tx.clone();
+            async move {
+                let request = Request::get(format!("https://axum-wasm.example/log?id={id}"))
+                    .body(Body::empty())
+                    .unwrap();
+                let res = router.handle_request(request, tx).await.unwrap();
+
+                hyper::body::to_bytes(res.into_body()).await.unwrap()
+            }
+        }))
+        .await;
+
+        for (id, body) in responses.iter().enumerate() {
+            assert_eq!(body, id.to_string().as_str());
+        }
+
+        // The forwarders finish once the guests close their end of the logs stream
+        drop(tx);
+        drop(router);
+        let logs = collector.await.unwrap();
+
+        // Group the logs of the guest by the request the host attributed them to
+        let mut by_request: BTreeMap<u64, Vec<serde_json::Value>> = BTreeMap::new();
+        for log in logs {
+            let fields: serde_json::Value = serde_json::from_slice(&log.fields).unwrap();
+            if fields.get("step").is_none() {
+                continue;
+            }
+
+            assert_eq!(fields["metadata"]["deployment"], "concurrent-logs");
+
+            let request_id = fields["request_id"].as_u64().unwrap();
+            by_request.entry(request_id).or_default().push(fields);
+        }
+
+        assert_eq!(by_request.len(), REQUESTS);
+
+        let mut ids = Vec::new();
+        for fields in by_request.values_mut() {
+            fields.sort_by_key(|fields| fields["seq"].as_u64().unwrap());
+
+            // Every log of a request comes from the same guest call, in the order it logged them
+            let steps: Vec<_> = fields.iter().map(|fields| &fields["step"]).collect();
+            assert_eq!(steps, [0, 1, 2]);
+            assert!(fields.iter().all(|log| log["id"] == fields[0]["id"]));
+
+            ids.push(fields[0]["id"].as_str().unwrap().parse::<usize>().unwrap());
+        }
+
+        ids.sort_unstable();
+        assert_eq!(ids, (0..REQUESTS).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn deterministic_requests_log_in_order() {
+        compile_module();
+
+        let router = RouterBuilder::new()
+            .unwrap()
+            .src("tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm")
+            .deterministic(true)
+            .build()
+            .unwrap();
+
+        let (tx, mut rx) = mpsc::channel(64);
+
+        let responses = futures::future::join_all((0..8).map(|id| {
+            let router = router.clone();
+            let tx = tx.clone();
+            async move {
+                let request = Request::get(format!("https://axum-wasm.example/log?id={id}"))
+                    .body(Body::empty())
+                    .unwrap();
+                let res = router.handle_request(request, tx).await.unwrap();
+
+                hyper::body::to_bytes(res.into_body()).await.unwrap()
+            }
+        }))
+        .await;
+
+        for (id, body) in responses.iter().enumerate() {
+            assert_eq!(body, id.to_string().as_str());
+        }
+
+        // Every log is forwarded by the time its response is returned, in the order the
+        // requests were made
+        drop(tx);
+        let mut logged = Vec::new();
+        while let Some(log) = rx.recv().await {
+            let fields: serde_json::Value = serde_json::from_slice(&log.unwrap().fields).unwrap();
+            if fields.get("step").is_some() {
+                logged.push((
+                    fields["id"].as_str().unwrap().parse::<u64>().unwrap(),
+                    fields["step"].as_u64().unwrap(),
+                ));
+            }
+        }
+
+        let expected: Vec<_> = (0..8)
+            .flat_map(|id| (0..3).map(move |step| (id, step)))
+            .collect();
+        assert_eq!(logged, expected);
+    }
+
+    #[tokio::test]
+    async fn deterministic_requests_with_many_logs() {
+        compile_module();
+
+        let router = RouterBuilder::new()
+            .unwrap()
+            .src("tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm")
+            .deterministic(true)
+            .build()
+            .unwrap();
+
+        let (tx, mut rx) = mpsc::channel(64);
+        let forwarded = tokio::spawn(async move {
+            let mut forwarded = 0;
+            while rx.recv().await.is_some() {
+                forwarded += 1;
+            }
+
+            forwarded
+        });
+
+        // Far more logs than the logs stream buffers, which the guest would block on if they
+        // were only read once it returned
+        let request = Request::get("https://axum-wasm.example/log?id=1&steps=20000")
+            .body(Body::empty())
+            .unwrap();
+        let res = tokio::time::timeout(Duration::from_secs(30), router.handle_request(request, tx))
+            .await
+            .expect("a guest with many logs should not block")
+            .unwrap();
+        assert_eq!(hyper::body::to_bytes(res.into_body()).await.unwrap(), "1");
+
+        assert!(forwarded.await.unwrap() >= 20000);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn deterministic_requests_time_out() {
+        compile_module();
+
+        let router = RouterBuilder::new()
+            .unwrap()
+            .src("tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm")
+            .deterministic(true)
+            .handler_timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+
+        // The guest blocks the only thread of the runtime, so the epoch has to come from elsewhere
+        let _ticker = tick_epoch(&router);
+
+        let (tx, _rx) = mpsc::channel(64);
+        let res = router
+            .handle_request(
+                Request::get("https://axum-wasm.example/spin")
+                    .body(Body::empty())
+                    .unwrap(),
+                tx,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn warm_pool() {
+        compile_module();
+
+        let router = RouterBuilder::new()
+            .unwrap()
+            .src("tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm")
+            .warm_pool_size(2)
+            .build()
+            .unwrap();
+
+        let warm_pool = router.warm_pool.clone().unwrap();
+        let filled = || async {
+            tokio::time::timeout(Duration::from_secs(10), async {
+                while warm_pool.len() < 2 {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .expect("warm pool should be filled");
+        };
+
+        let refill = router.refill_warm_pool().unwrap();
+        filled().await;
+
+        // Requests take their instances from the pool, which is filled again after them
+        for _ in 0..3 {
+            let (tx, _rx) = mpsc::channel(64);
+            let request = Request::get("https://axum-wasm.example/hello")
+                .body(Body::empty())
+                .unwrap();
+            let res = router.handle_request(request, tx).await.unwrap();
+
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(
+                hyper::body::to_bytes(res.into_body()).await.unwrap(),
+                "Hello, World!"
+            );
+        }
+        filled().await;
+
+        refill.abort();
+    }
+
+    #[tokio::test]
+    async fn abort_request() {
+        compile_module();
+
+        let router = RouterBuilder::new()
+            .unwrap()
+            .src("tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm")
+            .build()
+            .unwrap();
+
+        let (tx, mut rx) = mpsc::channel(64);
+
+        let ticker = tick_epoch(&router);
+
+        let request = Request::get("https://axum-wasm.example/spin")
+            .body(Body::empty())
+            .unwrap();
+        let stuck = tokio::spawn({
+            let router = router.clone();
+            async move { router.handle_request(request, tx).await.unwrap() }
+        });
+
+        // The first request of a router has id 1
+        while !router.aborts.abort(1) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let res = stuck.await.unwrap();
+        drop(ticker);
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let mut logged = false;
+        while let Ok(log) = rx.try_recv() {
+            let fields: serde_json::Value = serde_json::from_slice(&log.unwrap().fields).unwrap();
+            logged |= fields["message"] == "request was aborted" && fields["path"] == "/spin";
+        }
+        assert!(logged, "the forced abort should be logged");
+
+        // The request is not in flight anymore
+        assert!(!router.aborts.abort(1));
+    }
+
+    #[tokio::test]
+    async fn handler_timeout() {
+        compile_module();
+
+        let router = RouterBuilder::new()
+            .unwrap()
+            .src("tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm")
+            .handler_timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+
+        let (tx, mut rx) = mpsc::channel(64);
+
+        let ticker = tick_epoch(&router);
+
+        let res = router
+            .handle_request(
+                Request::get("https://axum-wasm.example/spin")
+                    .body(Body::empty())
+                    .unwrap(),
+                tx.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        let mut logged = false;
+        while let Ok(log) = rx.try_recv() {
+            let fields: serde_json::Value = serde_json::from_slice(&log.unwrap().fields).unwrap();
+            logged |= fields["message"] == "request timed out in the handler";
+        }
+        assert!(logged, "the timeout should be logged");
+
+        // Requests which are quick enough are not affected
+        let res = router
+            .handle_request(
+                Request::get("https://axum-wasm.example/hello")
+                    .body(Body::empty())
+                    .unwrap(),
+                tx,
+            )
+            .await
+            .unwrap();
+        drop(ticker);
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn health() {
+        let (tx, _rx) = mpsc::channel(64);
+        let get = || {
+            Request::get("https://axum-wasm.example/__health")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // Without a health export, a guest which can be instantiated is healthy
+        let router = RouterBuilder::new()
+            .unwrap()
+            .module_bytes(
+                br#"(module (func (export "__SHUTTLE_Axum_call") (param i32 i32 i32)))"#.to_vec(),
+            )
+            .health_path("/__health")
+            .build()
+            .unwrap();
+        let res = router.handle_request(get(), tx.clone()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body, r#"{"status":"healthy","checks":{}}"#);
+
+        // With one, the guest reports on its dependencies
+        let router = RouterBuilder::new()
+            .unwrap()
+            .module_bytes(
+                br#"(module
+                    (import "wasi_snapshot_preview1" "fd_write"
+                        (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 16)
+                        "{\"status\":\"degraded\",\"checks\":{\"cache\":{\"status\":\"degraded\",\"detail\":\"slow\"}}}")
+                    (func (export "__SHUTTLE_Axum_call") (param i32 i32 i32))
+                    (func (export "__SHUTTLE_health") (param $fd i32)
+                        (i32.store (i32.const 0) (i32.const 16))
+                        (i32.store (i32.const 4) (i32.const 78))
+                        (drop (call $fd_write (local.get $fd) (i32.const 0) (i32.const 1) (i32.const 8)))))"#
+                    .to_vec(),
+            )
+            .health_path("/__health")
+            .build()
+            .unwrap();
+        let res = router.handle_request(get(), tx.clone()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["checks"]["cache"]["detail"], "slow");
+
+        // Health checks for hosts which are not allowed are rejected like other requests
+        let router = RouterBuilder::new()
+            .unwrap()
+            .module_bytes(
+                br#"(module (func (export "__SHUTTLE_Axum_call") (param i32 i32 i32)))"#.to_vec(),
+            )
+            .health_path("/__health")
+            .allowed_hosts(vec!["shuttle.example".to_string()])
+            .build()
+            .unwrap();
+        let res = router.handle_request(get(), tx.clone()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::MISDIRECTED_REQUEST);
+
+        // Without a health path, the guest handles the request
+        let router = RouterBuilder::new()
+            .unwrap()
+            .module_bytes(
+                br#"(module (func (export "__SHUTTLE_Axum_call") (param i32 i32 i32)))"#.to_vec(),
+            )
+            .build()
+            .unwrap()
+            .with_guest(Arc::new(handler::mock::MockGuest::respond(
+                StatusCode::IM_A_TEAPOT,
+                "",
+            )));
+        let res = router.handle_request(get(), tx).await.unwrap();
+        assert_eq!(res.status(), StatusCode::IM_A_TEAPOT);
+    }
+
+    #[tokio::test]
+    async fn route_body_limit_past_the_socket_buffer() {
+        compile_module();
+
+        let router = RouterBuilder::new()
+            .unwrap()
+            .src("tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm")
+            .build()
+            .unwrap();
+
+        let (tx, _rx) = mpsc::channel(64);
+
+        // Far more than a unix socket buffers, so it is only written whole while the guest reads
+        let body = vec![b'a'; 512 * 1024];
+        let res = router
+            .clone()
+            .handle_request(
+                Request::post("https://axum-wasm.example/length")
+                    .body(Body::from(body.clone()))
+                    .unwrap(),
+                tx.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            hyper::body::to_bytes(res.into_body()).await.unwrap(),
+            "524288"
+        );
+
+        // Routes without a limit of their own keep the default one
+        let res = router
+            .handle_request(
+                Request::post("https://axum-wasm.example/uppercase")
+                    .body(Body::from(body))
+                    .unwrap(),
+                tx,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn body_limit_override_past_the_socket_buffer() {
+        compile_module();
+
+        let router = RouterBuilder::new()
+            .unwrap()
+            .src("tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm")
+            .body_limit_override(b"secret", 1024 * 1024)
+            .build()
+            .unwrap();
+
+        let expires = SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 60;
+        let message = format!("524288:{expires}:/uppercase");
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, b"secret");
+        let signature: String = ring::hmac::sign(&key, message.as_bytes())
+            .as_ref()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+
+        let (tx, _rx) = mpsc::channel(64);
+        let res = router
+            .handle_request(
+                Request::post("https://axum-wasm.example/uppercase")
+                    .header(
+                        body_limit::OVERRIDE_HEADER,
+                        format!("524288.{expires}.{signature}"),
+                    )
+                    .body(Body::from(vec![b'a'; 512 * 1024]))
+                    .unwrap(),
+                tx,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            hyper::body::to_bytes(res.into_body()).await.unwrap(),
+            vec![b'A'; 512 * 1024]
+        );
+    }
+
+    #[tokio::test]
+    async fn streamed_request_body() {
+        compile_module();
+
+        let router = RouterBuilder::new()
+            .unwrap()
+            .src("tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm")
+            .stream_request_body(4)
+            .max_body_size(16)
+            .build()
+            .unwrap();
+
+        let (tx, _rx) = mpsc::channel(64);
+
+        // Without a length, so only streaming it finds out how large the body is
+        let request = |chunks: &'static [&'static str]| {
+            let body = Body::wrap_stream(futures::stream::iter(
+                chunks.iter().map(|chunk| Ok::<_, std::io::Error>(*chunk)),
+            ));
+
+            Request::post("https://axum-wasm.example/uppercase")
+                .body(body)
+                .unwrap()
+        };
+
+        let res = router
+            .clone()
+            .handle_request(request(&["hello", " ", "world"]), tx.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            hyper::body::to_bytes(res.into_body()).await.unwrap(),
+            "HELLO WORLD"
+        );
+
+        let res = router
+            .handle_request(request(&["hello", " ", "world", " ", "again"]), tx)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn buffered_responses() {
+        compile_module();
+
+        let router = RouterBuilder::new()
+            .unwrap()
+            .src("tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm")
+            .response_chunk_size(4)
+            .buffer_responses_up_to(16)
+            .build()
+            .unwrap();
+
+        let (tx, _rx) = mpsc::channel(64);
+
+        let request = |body: &'static str| {
+            Request::post("https://axum-wasm.example/uppercase")
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        // Small bodies are sent whole with their length, even though the guest streams them
+        let res = router
+            .clone()
+            .handle_request(request("hello world"), tx.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[hyper::header::CONTENT_LENGTH], "11");
+        assert_eq!(res.body().size_hint().exact(), Some(11));
+        assert_eq!(
+            hyper::body::to_bytes(res.into_body()).await.unwrap(),
+            "HELLO WORLD"
+        );
+
+        // Larger bodies are streamed
+        let res = router
+            .handle_request(request("hello world, this is streamed"), tx)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get(hyper::header::CONTENT_LENGTH).is_none());
+        assert_eq!(res.body().size_hint().exact(), None);
+        assert_eq!(
+            hyper::body::to_bytes(res.into_body()).await.unwrap(),
+            "HELLO WORLD, THIS IS STREAMED"
+        );
+    }
+
+    #[tokio::test]
+    async fn stop_ends_logs_after_buffered_ones() {
+        use futures::StreamExt;
+
+        let axum = AxumWasm::new();
+        let logs = axum
+            .subscribe_logs(tonic::Request::new(SubscribeLogsRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+
+        for message in ["first", "last"] {
+            axum.logs_tx
+                .send(Ok(runtime::LogItem {
+                    fields: message.as_bytes().to_vec(),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap();
+        }
+
+        let (kill_tx, _kill_rx) = oneshot::channel();
+        *axum.kill_tx.lock().unwrap() = Some(kill_tx);
+        axum.stop(tonic::Request::new(StopRequest {}))
+            .await
+            .unwrap();
+
+        // The stream ends even though the runtime still holds a sender
+        let fields: Vec<_> = tokio::time::timeout(Duration::from_secs(5), logs.collect::<Vec<_>>())
+            .await
+            .expect("logs stream should end after a stop")
+            .into_iter()
+            .map(|log| log.unwrap().fields)
+            .collect();
+        assert_eq!(fields, [b"first".to_vec(), b"last".to_vec()]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn max_lifetime_stops_the_service() {
+        use futures::StreamExt;
+
+        let axum = AxumWasm::new();
+        *axum.router.lock().unwrap() = Some(
+            RouterBuilder::new()
+                .unwrap()
+                .module_bytes(
+                    br#"(module (func (export "__SHUTTLE_Axum_call") (param i32 i32 i32)))"#
+                        .to_vec(),
+                )
+                .max_lifetime(Duration::from_secs(60))
+                .build()
+                .unwrap(),
+        );
+        let logs = axum
+            .subscribe_logs(tonic::Request::new(SubscribeLogsRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        let mut stops = axum
+            .subscribe_stop(tonic::Request::new(SubscribeStopRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+
+        axum.start(tonic::Request::new(StartRequest {
+            ip: "127.0.0.1:0".to_string(),
+        }))
+        .await
+        .unwrap();
+
+        let stop = stops.next().await.unwrap().unwrap();
+        assert_eq!(stop.reason, StopReason::End as i32);
+        assert_eq!(stop.message, "the maximum lifetime was reached");
+
+        // The logs stream ends with the service
+        tokio::time::timeout(Duration::from_secs(5), logs.collect::<Vec<_>>())
+            .await
+            .expect("logs stream should end when the lifetime is reached");
+
+        // Stopping it afterwards still collects its report
+        let response = axum
+            .stop(tonic::Request::new(StopRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.success);
+        assert!(response.report.is_some());
+    }
+
+    #[tokio::test]
+    async fn start_fails_when_port_is_taken() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let ip = taken.local_addr().unwrap().to_string();
+
+        let status = AxumWasm::new()
+            .start(tonic::Request::new(StartRequest { ip }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), tonic::Code::AlreadyExists);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drain_waits_for_in_flight_requests() {
+        let stats = Arc::new(RequestStats::default());
+
+        assert_eq!(drain(&stats, Duration::from_secs(1)).await, (0, 0));
+
+        let first = stats.track();
+        let second = stats.track();
+        assert_eq!(stats.peak_in_flight.load(Ordering::Relaxed), 2);
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            drop(first);
+        });
+
+        let start = Instant::now();
+        assert_eq!(drain(&stats, Duration::from_secs(1)).await, (1, 1));
+        assert!(start.elapsed() >= Duration::from_secs(1));
+
+        drop(second);
+        assert_eq!(drain(&stats, Duration::from_secs(1)).await, (0, 0));
+        assert_eq!(stats.peak_in_flight.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn route_body_limits_are_capped() {
+        let section = br#"{"/upload": 18446744073709551615, "/login": 1024}"#;
+        let name = b"shuttle:body-limits";
+
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        module.push(0);
+        module.push((1 + name.len() + section.len()) as u8);
+        module.push(name.len() as u8);
+        module.extend_from_slice(name);
+        module.extend_from_slice(section);
+        let limits = RouteBodyLimits::from_module(&module);
+
+        let router = RouterBuilder::new()
+            .unwrap()
+            .module_bytes(
+                br#"(module (func (export "__SHUTTLE_Axum_call") (param i32 i32 i32)))"#.to_vec(),
+            )
+            .max_route_body_size(1024 * 1024)
+            .build()
+            .unwrap();
+        let parts = |path| Request::post(path).body(()).unwrap().into_parts().0;
+
+        assert_eq!(router.body_limit(&parts("/upload"), &limits), 1024 * 1024);
+        assert_eq!(router.body_limit(&parts("/login"), &limits), 1024);
+        assert_eq!(
+            router.body_limit(&parts("/"), &limits),
+            DEFAULT_MAX_BODY_SIZE
+        );
+    }
+
+    #[test]
+    fn config_is_checked_like_setters() {
+        let builder = RouterBuilder::new()
+            .unwrap()
+            .instantiate_timeout(Duration::from_secs(1))
+            .with_config(RuntimeConfig {
+                response_chunk_size: 0,
+                max_requests_per_connection: Some(0),
+                max_instances_per_second: Some(0),
+                instance_burst: 0,
+                ..Default::default()
+            });
+
+        let config = builder.runtime_config();
+        assert_eq!(config.response_chunk_size, 1);
+        assert_eq!(config.max_requests_per_connection, Some(1));
+        assert_eq!(config.max_instances_per_second, Some(1));
+        assert_eq!(config.instance_burst, 1);
+
+        // What the config leaves unset is cleared
+        assert_eq!(config.instantiate_timeout_ms, None);
+    }
+
+    #[tokio::test]
+    async fn get_config() {
+        compile_module();
+
+        let axum = AxumWasm::with_router_builder(
+            RouterBuilder::new()
+                .unwrap()
+                .max_body_size(1024)
+                .problem_json(true),
+        );
+
+        let axum = &axum;
+        let get_config = || async move {
+            let response = axum
+                .get_config(tonic::Request::new(GetConfigRequest {}))
+                .await
+                .unwrap()
+                .into_inner();
+            let config: RuntimeConfig = serde_json::from_str(&response.config).unwrap();
+
+            (config, response.middleware)
+        };
+
+        // Before a load the template is in force
+        let (config, middleware) = get_config().await;
+        assert_eq!(
+            config,
+            RuntimeConfig {
+                max_body_size: 1024,
+                problem_json: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(middleware, ["problem-json"]);
+
+        axum.load(tonic::Request::new(LoadRequest {
+            path: "tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm".to_string(),
+            secrets: HashMap::from([("API_KEY".to_string(), "hunter2".to_string())]),
+            features: vec!["-problem-json".to_string(), "coalesce-requests".to_string()],
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+
+        let (config, middleware) = get_config().await;
+        assert!(config.coalesce_requests);
+        assert!(!config.problem_json);
+        assert_eq!(config.max_body_size, 1024);
+        assert_eq!(middleware, ["coalesce-requests"]);
+
+        let response = axum
+            .get_config(tonic::Request::new(GetConfigRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!response.config.contains("hunter2"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn uptime_follows_clock() {
+        let axum = AxumWasm::new();
+        *axum.started_at.lock().unwrap() = Some(Instant::now());
+
+        tokio::time::advance(Duration::from_secs(90)).await;
+
+        let status = axum
+            .status(tonic::Request::new(StatusRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(status.uptime_secs, 90);
+    }
+
+    #[test]
+    fn headers_size_counts_every_value() {
+        let mut headers = HeaderMap::new();
+        headers.append("x-a", HeaderValue::from_static("12345"));
+        headers.append("x-a", HeaderValue::from_static("67"));
+
+        assert_eq!(headers_size(&headers), 3 + 5 + 3 + 2);
+        assert_eq!(serialized_headers_size(&headers), 3 + 5 + 3 + 2 + 2 * 4);
+    }
+
+    #[tokio::test]
+    async fn idempotency_keys() {
+        compile_module();
+
+        let router = RouterBuilder::new()
+            .unwrap()
+            .src("tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm")
+            .idempotency_keys(Duration::from_secs(60), 16)
+            .build()
+            .unwrap();
+
+        let (tx, _rx) = mpsc::channel(64);
+
+        let request = |uri: &str, body: &'static str| {
+            Request::post(uri)
+                .header("idempotency-key", "order-1")
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let res = router
+            .clone()
+            .handle_request(
+                request("https://axum-wasm.example/uppercase", "first"),
+                tx.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            hyper::body::to_bytes(res.into_body()).await.unwrap(),
+            "FIRST"
+        );
+        assert_eq!(router.stats.total.load(Ordering::Relaxed), 1);
+
+        // The retry gets the first response without the guest handling it
+        let res = router
+            .clone()
+            .handle_request(
+                request("https://axum-wasm.example/uppercase", "second"),
+                tx.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.headers()["idempotent-replayed"], "true");
+        assert_eq!(
+            hyper::body::to_bytes(res.into_body()).await.unwrap(),
+            "FIRST"
+        );
+        assert_eq!(router.stats.total.load(Ordering::Relaxed), 1);
+
+        let res = router
+            .clone()
+            .handle_request(request("https://axum-wasm.example/method", "third"), tx)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        // Keys belong to the router of one deployment
+        let other = RouterBuilder::new()
+            .unwrap()
+            .src("tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm")
+            .idempotency_keys(Duration::from_secs(60), 16)
+            .build()
+            .unwrap();
+        let (tx, _rx) = mpsc::channel(64);
+        let res = other
+            .handle_request(request("https://axum-wasm.example/uppercase", "second"), tx)
+            .await
+            .unwrap();
+        assert_eq!(
+            hyper::body::to_bytes(res.into_body()).await.unwrap(),
+            "SECOND"
+        );
+    }
 
-        // Deserialize response parts from rust messagepack
-        let wrapper: ResponseWrapper =
-            rmps::from_read(reader).context("failed to deserialize response parts")?;
+    #[test]
+    fn content_length() {
+        let headers = |values: &[&'static str]| {
+            let mut headers = HeaderMap::new();
+            for value in values {
+                headers.append(
+                    hyper::header::CONTENT_LENGTH,
+                    HeaderValue::from_static(value),
+                );
+            }
+            headers
+        };
 
-        // Read response body from wasm, convert it to a Stream and pass it to hyper
-        let reader = BufReader::new(body_stream);
-        let stream = futures::stream::iter(reader.bytes()).try_chunks(2);
-        let body = hyper::Body::wrap_stream(stream);
+        assert_eq!(declared_content_length(&headers(&["5"])), Some(5));
+        assert_eq!(declared_content_length(&headers(&["5", "5"])), Some(5));
+        assert_eq!(declared_content_length(&headers(&[])), None);
+        assert_eq!(declared_content_length(&headers(&["5", "6"])), None);
+        assert_eq!(declared_content_length(&headers(&["-1"])), None);
 
-        let response: Response<Body> = wrapper
-            .into_response_builder()
-            .body(body)
-            .context("failed to construct http response")?;
+        let chunks = |chunks: &[&[u8]]| {
+            chunks
+                .iter()
+                .map(|chunk| Ok(chunk.to_vec()))
+                .collect::<Vec<_>>()
+                .into_iter()
+        };
 
-        Ok(response)
+        let body: Vec<_> = check_length(chunks(&[b"hel", b"lo"]), 5).collect();
+        assert!(body.iter().all(Result::is_ok));
+        assert_eq!(body.len(), 2);
+
+        let body: Vec<_> = check_length(chunks(&[b"hel", b"lo"]), 4).collect();
+        assert!(body[0].is_ok());
+        assert!(body[1].is_err());
+        assert_eq!(body.len(), 2);
+
+        let body: Vec<_> = check_length(chunks(&[b"hel", b"lo"]), 6).collect();
+        assert!(body[..2].iter().all(Result::is_ok));
+        assert!(body[2].is_err());
+        assert_eq!(body.len(), 3);
     }
-}
 
-/// Start a hyper server with a service that calls an axum router in WASM,
-/// and a kill receiver for stopping the server.
-async fn run_until_stopped(
-    router: Router,
-    address: SocketAddr,
-    logs_tx: Sender<Result<runtime::LogItem, Status>>,
-    kill_rx: tokio::sync::oneshot::Receiver<String>,
-    stopped_tx: broadcast::Sender<(StopReason, String)>,
-) {
-    let make_service = make_service_fn(move |_conn| {
-        let router = router.clone();
-        let logs_tx = logs_tx.clone();
-        async move {
-            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
-                let mut router = router.clone();
-                let logs_tx = logs_tx.clone();
-                async move {
-                    Ok::<_, Infallible>(match router.handle_request(req, logs_tx).await {
-                        Ok(res) => res,
-                        Err(err) => {
-                            error!("error sending request: {}", err);
-                            Response::builder()
-                                .status(hyper::http::StatusCode::INTERNAL_SERVER_ERROR)
-                                .body(Body::empty())
-                                .expect("building request with empty body should not fail")
-                        }
-                    })
-                }
-            }))
-        }
-    });
+    #[tokio::test]
+    async fn declared_content_length_is_kept() {
+        compile_module();
 
-    let server = hyper::Server::bind(&address).serve(make_service);
+        let router = RouterBuilder::new()
+            .unwrap()
+            .src("tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm")
+            .build()
+            .unwrap();
 
-    trace!("starting hyper server on: {}", &address);
-    tokio::select! {
-        _ = server => {
-            stopped_tx.send((StopReason::End, String::new())).unwrap();
-            trace!("axum wasm server stopped");
-        },
-        message = kill_rx => {
-            match message {
-                Ok(msg) =>{
-                    stopped_tx.send((StopReason::Request, String::new())).unwrap();
-                    trace!("{msg}")
-                } ,
-                Err(_) => {
-                    stopped_tx
-                        .send((StopReason::Crash, "the kill sender dropped".to_string()))
-                        .unwrap();
-                    trace!("the sender dropped")
-                }
+        let (tx, _rx) = mpsc::channel(64);
+
+        let request = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+
+        let res = router
+            .clone()
+            .handle_request(request("https://axum-wasm.example/sized"), tx.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.headers()[hyper::header::CONTENT_LENGTH], "5");
+        assert_eq!(
+            hyper::body::to_bytes(res.into_body()).await.unwrap(),
+            "hello"
+        );
+
+        // A body shorter than its declared length breaks off
+        let res = router
+            .clone()
+            .handle_request(
+                request("https://axum-wasm.example/sized?length=8"),
+                tx.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.headers()[hyper::header::CONTENT_LENGTH], "8");
+        assert!(hyper::body::to_bytes(res.into_body()).await.is_err());
+
+        // Only the head of the response is sent, so its length cannot be checked
+        let res = router
+            .clone()
+            .handle_request(
+                Request::head("https://axum-wasm.example/sized?length=8")
+                    .body(Body::empty())
+                    .unwrap(),
+                tx.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.headers()[hyper::header::CONTENT_LENGTH], "8");
+
+        // Lengths hyper cannot frame the body by are dropped
+        let res = router
+            .handle_request(request("https://axum-wasm.example/sized?length=five"), tx)
+            .await
+            .unwrap();
+        assert!(res.headers().get(hyper::header::CONTENT_LENGTH).is_none());
+        assert_eq!(
+            hyper::body::to_bytes(res.into_body()).await.unwrap(),
+            "hello"
+        );
+    }
+
+    #[test]
+    fn connection_close() {
+        let headers = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(hyper::header::CONNECTION, HeaderValue::from_static(value));
+            headers
+        };
+
+        assert!(requests_close(&headers("close")));
+        assert!(requests_close(&headers("Upgrade, Close")));
+        assert!(!requests_close(&headers("keep-alive")));
+        assert!(!requests_close(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn strip_headers_removes_every_value() {
+        let mut headers = HeaderMap::new();
+        headers.append("server", HeaderValue::from_static("guest"));
+        headers.append("x-debug", HeaderValue::from_static("one"));
+        headers.append("x-debug", HeaderValue::from_static("two"));
+        headers.insert("content-type", HeaderValue::from_static("text/plain"));
+
+        strip_headers(
+            &mut headers,
+            &[
+                HeaderName::from_static("server"),
+                HeaderName::from_static("x-debug"),
+            ],
+        );
+
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers["content-type"], "text/plain");
+    }
+
+    #[test]
+    fn ambiguous_framing_is_rejected() {
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.append(*name, HeaderValue::from_static(*value));
             }
+            headers
+        };
+
+        assert!(ambiguous_framing(&headers(&[])).is_none());
+        assert!(ambiguous_framing(&headers(&[("content-length", "5")])).is_none());
+        assert!(ambiguous_framing(&headers(&[("transfer-encoding", "chunked")])).is_none());
+        assert!(ambiguous_framing(&headers(&[
+            ("content-length", "5"),
+            ("content-length", "5")
+        ]))
+        .is_none());
+
+        assert!(ambiguous_framing(&headers(&[
+            ("transfer-encoding", "chunked"),
+            ("content-length", "5")
+        ]))
+        .is_some());
+        assert!(ambiguous_framing(&headers(&[("transfer-encoding", "gzip, chunked")])).is_some());
+        assert!(ambiguous_framing(&headers(&[
+            ("transfer-encoding", "chunked"),
+            ("transfer-encoding", "chunked")
+        ]))
+        .is_some());
+        assert!(ambiguous_framing(&headers(&[
+            ("content-length", "5"),
+            ("content-length", "6")
+        ]))
+        .is_some());
+    }
+
+    #[test]
+    fn malformed_uri_is_rejected() {
+        let malformed = |uri: &str| malformed_uri(&uri.parse().unwrap());
+
+        assert!(malformed("/").is_none());
+        assert!(malformed("/hello/world?name=a%20b").is_none());
+        assert!(malformed("https://axum-wasm.example/hello").is_none());
+
+        assert!(malformed("ftp://axum-wasm.example/hello").is_some());
+        assert!(malformed("*").is_some());
+        assert!(malformed(&"/a".repeat(MAX_PATH_SEGMENTS + 1)).is_some());
+        assert!(malformed("/hello%2").is_some());
+        assert!(malformed("/hello%zz/world").is_some());
+    }
+
+    #[test]
+    fn deployment_slots() {
+        let slots = Arc::new(DeploymentSlots::new(1));
+
+        let slot = slots.try_acquire().expect("first slot to be free");
+        assert!(slots.try_acquire().is_none());
+
+        drop(slot);
+        assert!(slots.try_acquire().is_some());
+    }
+
+    #[test]
+    fn allowed_hosts() {
+        let allowed = vec!["myapp.shuttle.app".to_string(), "*.example.com".to_string()];
+
+        assert!(is_host_allowed("myapp.shuttle.app", &allowed));
+        assert!(is_host_allowed("MyApp.Shuttle.App", &allowed));
+        assert!(is_host_allowed("api.example.com", &allowed));
+        assert!(is_host_allowed("v1.api.example.com", &allowed));
+
+        assert!(!is_host_allowed("example.com", &allowed));
+        assert!(!is_host_allowed("badexample.com", &allowed));
+        assert!(!is_host_allowed("other.shuttle.app", &allowed));
+        assert!(!is_host_allowed("", &allowed));
+
+        let request = Request::builder()
+            .header("host", "api.example.com:8000")
+            .body(())
+            .unwrap();
+        assert_eq!(request_host(&request), Some("api.example.com"));
+
+        let request = Request::builder()
+            .uri("https://[::1]:8000/hello")
+            .body(())
+            .unwrap();
+        assert_eq!(request_host(&request), Some("[::1]"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn less_common_methods() {
+        compile_module();
+
+        let router = RouterBuilder::new()
+            .unwrap()
+            .src("tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm")
+            .build()
+            .unwrap();
+
+        let (tx, _rx) = mpsc::channel(64);
+
+        for (method, body) in [
+            (Method::PATCH, "patched"),
+            (Method::PUT, "replaced"),
+            (Method::DELETE, ""),
+            (Method::OPTIONS, ""),
+            (Method::from_bytes(b"PURGE").unwrap(), ""),
+        ] {
+            let request = Request::builder()
+                .method(method.clone())
+                .uri("https://axum-wasm.example/method")
+                .body(Body::from(body))
+                .unwrap();
+
+            let res = router
+                .clone()
+                .handle_request(request, tx.clone())
+                .await
+                .unwrap();
+
+            assert_eq!(res.status(), StatusCode::OK, "{method}");
+            assert_eq!(
+                hyper::body::to_bytes(res.into_body()).await.unwrap(),
+                format!("{method} {body}"),
+            );
         }
-    };
-}
+    }
 
-#[cfg(test)]
-pub mod tests {
-    use std::process::Command;
+    #[tokio::test]
+    async fn status_remap() {
+        compile_module();
 
-    use super::*;
-    use hyper::{http::HeaderValue, Method, Request, StatusCode, Version};
+        let router = RouterBuilder::new()
+            .unwrap()
+            .src("tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm")
+            .status_remap(HashMap::from([(
+                StatusCode::OK,
+                StatusCode::NON_AUTHORITATIVE_INFORMATION,
+            )]))
+            .build()
+            .unwrap();
 
-    // Compile axum wasm module
-    fn compile_module() {
-        Command::new("cargo")
-            .arg("build")
-            .arg("--target")
-            .arg("wasm32-wasi")
-            .current_dir("tests/resources/axum-wasm-expanded")
-            .spawn()
+        let (tx, _rx) = mpsc::channel(64);
+        let get = |path: &str| {
+            Request::get(format!("https://axum-wasm.example{path}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let res = router
+            .handle_request(get("/hello"), tx.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NON_AUTHORITATIVE_INFORMATION);
+        assert_eq!(
+            hyper::body::to_bytes(res.into_body()).await.unwrap(),
+            "Hello, World!"
+        );
+
+        // Statuses which are not remapped are sent as they are
+        let res = router.handle_request(get("/not-found"), tx).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn error_pages() {
+        let builder = RouterBuilder::new()
             .unwrap()
-            .wait()
+            .module_bytes(
+                br#"(module (func (export "__SHUTTLE_Axum_call") (param i32 i32 i32)))"#.to_vec(),
+            )
+            .max_body_size(4)
+            .payload_too_large_message("at most {limit} bytes")
+            .error_pages(HashMap::from([
+                (
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    ErrorPage::html("<h1>Too large</h1>"),
+                ),
+                (StatusCode::NOT_FOUND, ErrorPage::html("<h1>Not here</h1>")),
+            ]));
+        let router = builder.clone().build().unwrap();
+
+        let (tx, _rx) = mpsc::channel(64);
+
+        // Errors of the host get the page for their status, even with a custom detail
+        let res = router
+            .handle_request(
+                Request::post("https://axum-wasm.example/orders")
+                    .body(Body::from("too large"))
+                    .unwrap(),
+                tx.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            res.headers()[hyper::header::CONTENT_TYPE],
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(
+            hyper::body::to_bytes(res.into_body()).await.unwrap(),
+            "<h1>Too large</h1>"
+        );
+
+        // The guest's own errors are never replaced
+        let router =
+            builder
+                .build()
+                .unwrap()
+                .with_guest(Arc::new(handler::mock::MockGuest::respond(
+                    StatusCode::NOT_FOUND,
+                    "no such order",
+                )));
+        let res = router
+            .handle_request(
+                Request::get("https://axum-wasm.example/orders/1")
+                    .body(Body::empty())
+                    .unwrap(),
+                tx,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            hyper::body::to_bytes(res.into_body()).await.unwrap(),
+            "no such order"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn guest_defers_to_host() {
+        compile_module();
+
+        let router = RouterBuilder::new()
+            .unwrap()
+            .src("tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm")
+            .redirects(vec![
+                ("/defer".to_string(), "/hello".to_string(), StatusCode::FOUND),
+                ("/hello".to_string(), "/goodbye".to_string(), StatusCode::FOUND),
+            ])
+            .unwrap()
+            .host_routes_after_guest(true)
+            .build()
+            .unwrap();
+
+        let (tx, _rx) = mpsc::channel(64);
+
+        // The guest handles its own routes first
+        let res = router
+            .clone()
+            .handle_request(
+                Request::get("https://axum-wasm.example/hello")
+                    .body(Body::empty())
+                    .unwrap(),
+                tx.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // Then the host routes are tried when the guest defers
+        let res = router
+            .clone()
+            .handle_request(
+                Request::get("https://axum-wasm.example/defer")
+                    .body(Body::empty())
+                    .unwrap(),
+                tx.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FOUND);
+        assert_eq!(res.headers()[hyper::header::LOCATION], "/hello");
+
+        // And it is the host's own not found without a matching host route
+        let router = RouterBuilder::new()
+            .unwrap()
+            .src("tests/resources/axum-wasm-expanded/target/wasm32-wasi/debug/shuttle_axum_expanded.wasm")
+            .host_routes_after_guest(true)
+            .problem_json(true)
+            .build()
+            .unwrap();
+
+        let res = router
+            .handle_request(
+                Request::get("https://axum-wasm.example/defer")
+                    .body(Body::empty())
+                    .unwrap(),
+                tx,
+            )
+            .await
             .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            res.headers()[hyper::header::CONTENT_TYPE],
+            "application/problem+json"
+        );
     }
 
     #[tokio::test(flavor = "multi_thread")]
@@ -556,4 +5447,124 @@ pub mod tests {
             b"THIS SHOULD BE UPPERCASED"
         );
     }
+
+    #[tokio::test]
+    async fn guest_not_reading_its_parts() {
+        let router = RouterBuilder::new()
+            .unwrap()
+            .module_bytes(
+                br#"(module (func (export "__SHUTTLE_Axum_call") (param i32 i32 i32)))"#.to_vec(),
+            )
+            .body_write_timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+
+        let (tx, _rx) = mpsc::channel(64);
+
+        // Parts much larger than the socket buffer, which only fit if the guest reads them
+        let res = router
+            .handle_request(
+                Request::get("https://axum-wasm.example/")
+                    .header("x-padding", "a".repeat(8 * 1024 * 1024))
+                    .body(Body::empty())
+                    .unwrap(),
+                tx,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn guest_produced_no_response() {
+        let router = RouterBuilder::new()
+            .unwrap()
+            .module_bytes(
+                br#"(module (func (export "__SHUTTLE_Axum_call") (param i32 i32 i32)))"#.to_vec(),
+            )
+            .problem_json(true)
+            .build()
+            .unwrap();
+
+        let (tx, mut rx) = mpsc::channel(64);
+
+        // The guest returns without writing anything to its parts fd
+        let res = router
+            .handle_request(
+                Request::get("https://axum-wasm.example/")
+                    .body(Body::empty())
+                    .unwrap(),
+                tx,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["type"], "urn:shuttle:next:no-response");
+
+        let mut logged = false;
+        while let Ok(log) = rx.try_recv() {
+            let fields: serde_json::Value = serde_json::from_slice(&log.unwrap().fields).unwrap();
+            logged |= fields["message"] == "guest produced no response";
+        }
+        assert!(logged, "the missing response should be logged");
+    }
+
+    #[tokio::test]
+    async fn auto_options() {
+        let builder = RouterBuilder::new().unwrap().module_bytes(
+            br#"(module (func (export "__SHUTTLE_Axum_call") (param i32 i32 i32)))"#.to_vec(),
+        );
+        let options = || {
+            Request::options("https://axum-wasm.example/orders")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // The host answers, even though the guest never produces a response
+        let router = builder.clone().auto_options(true).build().unwrap();
+        let (tx, _rx) = mpsc::channel(64);
+        let res = router.handle_request(options(), tx).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            res.headers()[hyper::header::ALLOW],
+            "OPTIONS, GET, HEAD, POST, PUT, PATCH, DELETE"
+        );
+
+        // Off by default, so the request goes to the guest
+        let router = builder.build().unwrap();
+        let (tx, _rx) = mpsc::channel(64);
+        let res = router.handle_request(options(), tx).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn routes_path() {
+        let router = RouterBuilder::new()
+            .unwrap()
+            .module_bytes(
+                br#"(module (func (export "__SHUTTLE_Axum_call") (param i32 i32 i32)))"#.to_vec(),
+            )
+            .routes_path("/__routes")
+            .build()
+            .unwrap();
+
+        let (tx, _rx) = mpsc::channel(64);
+
+        // Answered by the host, with no routes since the module advertises none
+        let res = router
+            .handle_request(
+                Request::get("https://axum-wasm.example/__routes")
+                    .body(Body::empty())
+                    .unwrap(),
+                tx,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(hyper::body::to_bytes(res.into_body()).await.unwrap(), "[]");
+    }
 }
@@ -0,0 +1,126 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Context;
+use wasmtime::Engine;
+
+use super::watch::{LoadedModule, SwappableModule};
+
+/// A module serving every request whose path is under `prefix`
+struct Mount {
+    prefix: String,
+    module: Arc<SwappableModule>,
+}
+
+/// Modules mounted on path prefixes, where the longest matching prefix wins
+#[derive(Default)]
+pub(crate) struct Mounts(Vec<Mount>);
+
+impl Mounts {
+    /// Compile the module of every `(prefix, path)` mount
+    pub(crate) fn load(engine: &Engine, mounts: Vec<(String, PathBuf)>) -> anyhow::Result<Self> {
+        let mut mounts = mounts
+            .into_iter()
+            .map(|(prefix, path)| {
+                let loaded = LoadedModule::from_file(engine, &path).with_context(|| {
+                    format!("failed to load module mounted on '{prefix}' from {path:?}")
+                })?;
+
+                Ok(Mount {
+                    prefix: prefix.trim_end_matches('/').to_string(),
+                    module: Arc::new(SwappableModule::new(loaded)),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        // Check the longest prefixes first so that they win over their parents
+        mounts.sort_by(|a, b| b.prefix.len().cmp(&a.prefix.len()));
+
+        Ok(Self(mounts))
+    }
+
+    /// Check if no modules are mounted
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Get the module mounted on the longest prefix of `path`. A prefix only matches whole
+    /// path segments, so `/api` matches `/api` and `/api/users` but not `/apis`.
+    pub(crate) fn module_for(&self, path: &str) -> Option<&Arc<SwappableModule>> {
+        self.0
+            .iter()
+            .find(|mount| {
+                path.strip_prefix(&mount.prefix)
+                    .map(|rest| rest.is_empty() || rest.starts_with('/'))
+                    .unwrap_or(false)
+            })
+            .map(|mount| &mount.module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmtime::Module;
+
+    use super::*;
+
+    fn mounts(prefixes: &[&str]) -> Mounts {
+        let engine = Engine::default();
+
+        let mut mounts: Vec<_> = prefixes
+            .iter()
+            .map(|prefix| Mount {
+                prefix: prefix.trim_end_matches('/').to_string(),
+                module: Arc::new(SwappableModule::new(LoadedModule {
+                    module: Module::new(&engine, b"\0asm\x01\0\0\0").unwrap(),
+                    metadata: Default::default(),
+                    body_limits: Default::default(),
+                    route_methods: Default::default(),
+                    sha256: Arc::from(""),
+                })),
+            })
+            .collect();
+        mounts.sort_by(|a, b| b.prefix.len().cmp(&a.prefix.len()));
+
+        Mounts(mounts)
+    }
+
+    fn mounted_prefix<'a>(mounts: &'a Mounts, path: &str) -> Option<&'a str> {
+        let module = mounts.module_for(path)?;
+
+        mounts
+            .0
+            .iter()
+            .find(|mount| Arc::ptr_eq(&mount.module, module))
+            .map(|mount| mount.prefix.as_str())
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        let mounts = mounts(&["/api", "/api/admin/", "/static"]);
+
+        assert_eq!(mounted_prefix(&mounts, "/api"), Some("/api"));
+        assert_eq!(mounted_prefix(&mounts, "/api/users"), Some("/api"));
+        assert_eq!(mounted_prefix(&mounts, "/api/admin"), Some("/api/admin"));
+        assert_eq!(mounted_prefix(&mounts, "/api/admin/1"), Some("/api/admin"));
+        assert_eq!(mounted_prefix(&mounts, "/static/a.css"), Some("/static"));
+    }
+
+    #[test]
+    fn prefixes_match_whole_segments() {
+        let mounts = mounts(&["/api"]);
+
+        assert_eq!(mounted_prefix(&mounts, "/apis"), None);
+        assert_eq!(mounted_prefix(&mounts, "/"), None);
+        assert_eq!(mounted_prefix(&mounts, "/other/api"), None);
+    }
+
+    #[test]
+    fn root_mount_matches_everything() {
+        let mounts = mounts(&["/", "/api"]);
+
+        assert_eq!(mounted_prefix(&mounts, "/"), Some(""));
+        assert_eq!(mounted_prefix(&mounts, "/anything"), Some(""));
+        assert_eq!(mounted_prefix(&mounts, "/api/users"), Some("/api"));
+    }
+}
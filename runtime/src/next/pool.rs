@@ -0,0 +1,173 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use tokio::sync::oneshot;
+use tracing::debug;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A set of OS threads which run guest calls, so that long or blocking guests cannot starve
+/// tokio's own blocking pool. Threads are started as needed up to a maximum, and are stopped
+/// again once they have been idle for the idle timeout.
+pub(crate) struct GuestPool {
+    jobs: Sender<Job>,
+    receiver: Receiver<Job>,
+    max_threads: usize,
+    idle_timeout: Option<Duration>,
+    counts: Arc<Counts>,
+}
+
+#[derive(Default)]
+struct Counts {
+    threads: AtomicUsize,
+    idle: AtomicUsize,
+}
+
+impl GuestPool {
+    /// Create a pool of at most `max_threads` threads. Threads exit once they have been idle for
+    /// `idle_timeout`, or once the pool is dropped.
+    pub(crate) fn new(max_threads: usize, idle_timeout: Option<Duration>) -> Self {
+        let (jobs, receiver) = crossbeam_channel::unbounded();
+
+        Self {
+            jobs,
+            receiver,
+            max_threads: max_threads.max(1),
+            idle_timeout,
+            counts: Default::default(),
+        }
+    }
+
+    /// The default pool size: one thread per available CPU
+    pub(crate) fn default_size() -> usize {
+        thread::available_parallelism()
+            .map(usize::from)
+            .unwrap_or(4)
+    }
+
+    /// The number of threads currently in the pool
+    pub(crate) fn size(&self) -> usize {
+        self.counts.threads.load(Ordering::Relaxed)
+    }
+
+    /// Run `f` on one of the pool threads and wait for its result
+    pub(crate) async fn run<F, T>(&self, f: F) -> anyhow::Result<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+
+        self.jobs
+            .send(Box::new(move || {
+                // The caller may have gone away, in which case nobody wants the result
+                let _ = tx.send(f());
+            }))
+            .map_err(|_| anyhow!("guest pool has shut down"))?;
+
+        if self.counts.idle.load(Ordering::Relaxed) == 0 {
+            self.try_spawn();
+        }
+
+        rx.await.map_err(|_| anyhow!("guest call panicked"))
+    }
+
+    fn try_spawn(&self) {
+        let reserved =
+            self.counts
+                .threads
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |threads| {
+                    (threads < self.max_threads).then_some(threads + 1)
+                });
+
+        let Ok(index) = reserved else {
+            return;
+        };
+
+        let receiver = self.receiver.clone();
+        let idle_timeout = self.idle_timeout;
+        let counts = self.counts.clone();
+
+        thread::Builder::new()
+            .name(format!("shuttle-next-guest-{index}"))
+            .spawn(move || worker(receiver, idle_timeout, counts))
+            .expect("failed to spawn guest pool thread");
+    }
+}
+
+fn worker(receiver: Receiver<Job>, idle_timeout: Option<Duration>, counts: Arc<Counts>) {
+    loop {
+        counts.idle.fetch_add(1, Ordering::Relaxed);
+        let job = match idle_timeout {
+            Some(idle_timeout) => receiver.recv_timeout(idle_timeout),
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        counts.idle.fetch_sub(1, Ordering::Relaxed);
+
+        match job {
+            // Keep the thread alive when a guest call panics
+            Ok(job) => {
+                let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(job));
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                let threads = counts.threads.fetch_sub(1, Ordering::Relaxed) - 1;
+
+                // A job may have been queued just as this thread timed out
+                if !receiver.is_empty() {
+                    counts.threads.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+
+                debug!(threads, "stopped idle guest pool thread");
+                return;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    counts.threads.fetch_sub(1, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn runs_on_pool_threads() {
+        let pool = GuestPool::new(2, None);
+
+        let name = pool
+            .run(|| thread::current().name().map(ToString::to_string))
+            .await
+            .unwrap();
+
+        assert!(name.unwrap().starts_with("shuttle-next-guest-"));
+        assert_eq!(pool.size(), 1);
+    }
+
+    #[tokio::test]
+    async fn survives_panics() {
+        let pool = GuestPool::new(1, None);
+
+        assert!(pool.run(|| panic!("guest exploded")).await.is_err());
+        assert_eq!(pool.run(|| 42).await.unwrap(), 42);
+    }
+
+    #[tokio::test]
+    async fn evicts_idle_threads() {
+        let pool = GuestPool::new(2, Some(Duration::from_millis(50)));
+
+        assert_eq!(pool.run(|| 42).await.unwrap(), 42);
+        assert_eq!(pool.size(), 1);
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(pool.size(), 0);
+
+        // The pool grows again when needed
+        assert_eq!(pool.run(|| 42).await.unwrap(), 42);
+    }
+}
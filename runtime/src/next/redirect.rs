@@ -0,0 +1,157 @@
+use anyhow::{bail, Context};
+use hyper::header::{self, HeaderValue};
+use hyper::http::StatusCode;
+use hyper::{Body, Request, Response};
+
+/// A redirect which the host serves without calling the guest
+#[derive(Clone, Debug)]
+struct Redirect {
+    from: String,
+    to: String,
+    status: StatusCode,
+    prefix: bool,
+}
+
+/// Static redirects checked in order, where the first matching rule wins
+#[derive(Clone, Debug, Default)]
+pub struct Redirects(Vec<Redirect>);
+
+impl Redirects {
+    /// Build redirects from `(from, to, status)` rules. A `from` ending in `*` matches every
+    /// path with that prefix and the rest of the path is appended to `to`, otherwise the path
+    /// has to match exactly.
+    pub fn new(rules: Vec<(String, String, StatusCode)>) -> anyhow::Result<Self> {
+        let redirects = rules
+            .into_iter()
+            .map(|(from, to, status)| {
+                if !status.is_redirection() {
+                    bail!("redirect from '{from}' should use a 3xx status, not {status}");
+                }
+
+                HeaderValue::from_str(&to)
+                    .with_context(|| format!("redirect target '{to}' is not a valid header"))?;
+
+                let (from, prefix) = match from.strip_suffix('*') {
+                    Some(from) => (from.to_string(), true),
+                    None => (from, false),
+                };
+
+                Ok(Redirect {
+                    from,
+                    to,
+                    status,
+                    prefix,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self(redirects))
+    }
+
+    /// Check if there are no redirect rules
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Get the redirect response for a request, if a rule matches it
+    pub fn respond<B>(&self, req: &Request<B>) -> Option<Response<Body>> {
+        let path = req.uri().path();
+
+        let location = self.0.iter().find_map(|redirect| {
+            if redirect.prefix {
+                let rest = path.strip_prefix(&redirect.from)?;
+
+                Some((format!("{}{rest}", redirect.to), redirect.status))
+            } else {
+                (path == redirect.from).then(|| (redirect.to.clone(), redirect.status))
+            }
+        });
+
+        let (mut location, status) = location?;
+
+        if let Some(query) = req.uri().query() {
+            location.push(if location.contains('?') { '&' } else { '?' });
+            location.push_str(query);
+        }
+
+        // The rest of the path and the query come from a valid URI, so they are valid here too
+        let response = Response::builder()
+            .status(status)
+            .header(header::LOCATION, location)
+            .body(Body::empty())
+            .expect("building a redirect response should not fail");
+
+        Some(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redirects() -> Redirects {
+        Redirects::new(vec![
+            (
+                "/old".to_string(),
+                "/new".to_string(),
+                StatusCode::MOVED_PERMANENTLY,
+            ),
+            (
+                "/docs/*".to_string(),
+                "https://docs.example/".to_string(),
+                StatusCode::FOUND,
+            ),
+        ])
+        .unwrap()
+    }
+
+    fn location(redirects: &Redirects, uri: &str) -> Option<(StatusCode, String)> {
+        let req = Request::get(uri).body(()).unwrap();
+
+        redirects.respond(&req).map(|response| {
+            (
+                response.status(),
+                response.headers()[header::LOCATION]
+                    .to_str()
+                    .unwrap()
+                    .to_string(),
+            )
+        })
+    }
+
+    #[test]
+    fn exact() {
+        let redirects = redirects();
+
+        assert_eq!(
+            location(&redirects, "/old"),
+            Some((StatusCode::MOVED_PERMANENTLY, "/new".to_string()))
+        );
+        assert_eq!(
+            location(&redirects, "/old?page=2"),
+            Some((StatusCode::MOVED_PERMANENTLY, "/new?page=2".to_string()))
+        );
+        assert_eq!(location(&redirects, "/old/nested"), None);
+    }
+
+    #[test]
+    fn prefix() {
+        let redirects = redirects();
+
+        assert_eq!(
+            location(&redirects, "/docs/getting-started"),
+            Some((
+                StatusCode::FOUND,
+                "https://docs.example/getting-started".to_string()
+            ))
+        );
+        assert_eq!(location(&redirects, "/documents"), None);
+    }
+
+    #[test]
+    fn rejects_non_redirect_status() {
+        assert!(
+            Redirects::new(vec![("/".to_string(), "/home".to_string(), StatusCode::OK)]).is_err()
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use std::io::Write;
+use std::net::Shutdown;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use cap_std::os::unix::net::UnixStream;
+use hyper::body::{Bytes, HttpBody};
+use hyper::Body;
+use thiserror::Error;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+
+#[derive(Debug, Error)]
+pub(crate) enum RequestBodyError {
+    #[error("the request body is larger than {0} bytes")]
+    TooLarge(u64),
+    #[error("failed to read the request body: {0}")]
+    Read(#[from] hyper::Error),
+    #[error("failed to write the request body to wasm: {0}")]
+    Write(#[from] std::io::Error),
+}
+
+/// Stream `body` into `stream` while the guest reads it. At most `high_water_mark` bytes are
+/// kept which were received from the client but not written to the guest yet, and no more is
+/// read from the client until the guest catches up. `cancelled` is set when the body cannot be
+/// streamed whole, so that the guest does not respond to a truncated body.
+pub(crate) fn stream_body(
+    mut body: Body,
+    mut stream: UnixStream,
+    high_water_mark: usize,
+    limit: u64,
+    cancelled: Arc<AtomicBool>,
+) -> JoinHandle<Result<(), RequestBodyError>> {
+    let high_water_mark = high_water_mark.clamp(1, u32::MAX as usize);
+    let buffered = Arc::new(Semaphore::new(high_water_mark));
+    let (tx, mut rx) = mpsc::unbounded_channel::<(Bytes, OwnedSemaphorePermit)>();
+
+    // Writes block until the guest reads, so they happen on their own thread. Each chunk is
+    // released from the buffer once it is written.
+    let writer = tokio::task::spawn_blocking(move || {
+        while let Some((chunk, _permit)) = rx.blocking_recv() {
+            stream.write_all(&chunk)?;
+        }
+
+        // Signal EOF to the guest
+        stream.shutdown(Shutdown::Write)
+    });
+
+    tokio::spawn(async move {
+        let read = forward_chunks(&mut body, &tx, &buffered, high_water_mark, limit).await;
+
+        // Cancel the guest before the writer signals EOF, so it never takes a truncated body
+        // as the whole of it
+        if read.is_err() {
+            cancelled.store(true, Ordering::Relaxed);
+        }
+        drop(tx);
+        read?;
+
+        if let Err(error) = writer.await.expect("request body writer should not panic") {
+            cancelled.store(true, Ordering::Relaxed);
+
+            return Err(error.into());
+        }
+
+        Ok(())
+    })
+}
+
+/// Write the whole of a buffered `body` into `stream` while the guest reads it. Bodies larger
+/// than the socket buffer only fit once the guest reads them, so the write happens on its own
+/// thread alongside the guest call. `cancelled` is set when the body cannot be written whole,
+/// so that the guest does not respond to a truncated body.
+pub(crate) fn write_body(
+    body: Bytes,
+    mut stream: UnixStream,
+    cancelled: Arc<AtomicBool>,
+) -> JoinHandle<Result<(), RequestBodyError>> {
+    tokio::task::spawn_blocking(move || {
+        let written = stream
+            .write_all(&body)
+            .and_then(|()| stream.shutdown(Shutdown::Write));
+
+        match written {
+            Ok(()) => Ok(()),
+            // The guest returned without reading the whole body, which it is free to do
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    std::io::ErrorKind::BrokenPipe | std::io::ErrorKind::ConnectionReset
+                ) =>
+            {
+                Ok(())
+            }
+            Err(error) => {
+                // Cancel the guest while the host still holds the other copy of the stream, so
+                // it does not see the end of the body before noticing
+                cancelled.store(true, Ordering::Relaxed);
+
+                Err(error.into())
+            }
+        }
+    })
+}
+
+/// Pass the chunks of `body` to the writer, waiting while `buffered` has no room for them
+async fn forward_chunks(
+    body: &mut Body,
+    tx: &mpsc::UnboundedSender<(Bytes, OwnedSemaphorePermit)>,
+    buffered: &Arc<Semaphore>,
+    high_water_mark: usize,
+    limit: u64,
+) -> Result<(), RequestBodyError> {
+    let mut received = 0;
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+
+        received += chunk.len() as u64;
+        if received > limit {
+            return Err(RequestBodyError::TooLarge(limit));
+        }
+
+        // A chunk larger than the high-water mark waits for the whole buffer to drain
+        let permits = chunk.len().min(high_water_mark) as u32;
+        let permit = buffered
+            .clone()
+            .acquire_many_owned(permits)
+            .await
+            .expect("body buffer semaphore should never be closed");
+
+        // The writer failed, which it reports itself
+        if tx.send((chunk, permit)).is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn chunked_body(chunks: &'static [&'static [u8]]) -> Body {
+        Body::wrap_stream(futures::stream::iter(
+            chunks.iter().map(|chunk| Ok::<_, std::io::Error>(*chunk)),
+        ))
+    }
+
+    #[tokio::test]
+    async fn streams_the_whole_body() {
+        let (host, mut guest) = UnixStream::pair().unwrap();
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let streamer = stream_body(
+            chunked_body(&[b"hello", b" ", b"world"]),
+            host,
+            4,
+            1024,
+            cancelled.clone(),
+        );
+
+        let read = tokio::task::spawn_blocking(move || {
+            let mut body = Vec::new();
+            guest.read_to_end(&mut body).unwrap();
+            body
+        });
+
+        streamer.await.unwrap().unwrap();
+        assert_eq!(read.await.unwrap(), b"hello world");
+        assert!(!cancelled.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn stops_reading_while_the_guest_does_not() {
+        let (host, mut guest) = UnixStream::pair().unwrap();
+        let (mut sender, body) = Body::channel();
+
+        let streamer = stream_body(body, host, 8, u64::MAX, Default::default());
+
+        // Fill the socket buffer of the guest, after which only the high-water mark is buffered
+        let chunk = Bytes::from(vec![0; 64 * 1024]);
+        let mut sent = 0;
+        while tokio::time::timeout(Duration::from_millis(100), sender.send_data(chunk.clone()))
+            .await
+            .is_ok()
+        {
+            sent += chunk.len();
+            assert!(
+                sent < 64 * 1024 * 1024,
+                "the client should get backpressure"
+            );
+        }
+
+        // Everything sent is written once the guest reads
+        drop(sender);
+        let read = tokio::task::spawn_blocking(move || {
+            let mut body = Vec::new();
+            guest.read_to_end(&mut body).unwrap();
+            body.len()
+        });
+
+        streamer.await.unwrap().unwrap();
+        assert_eq!(read.await.unwrap(), sent);
+    }
+
+    #[tokio::test]
+    async fn body_limit() {
+        let (host, _guest) = UnixStream::pair().unwrap();
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let streamer = stream_body(
+            chunked_body(&[b"hello", b" ", b"world"]),
+            host,
+            1024,
+            8,
+            cancelled.clone(),
+        );
+
+        assert!(matches!(
+            streamer.await.unwrap(),
+            Err(RequestBodyError::TooLarge(8))
+        ));
+        assert!(cancelled.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,98 @@
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::http::request::Parts;
+use tracing::warn;
+
+/// Prefix of the headers the guest gets the context of a request in. Clients cannot set these
+/// themselves, since every header with the prefix is removed from requests first.
+pub(crate) const CONTEXT_HEADER_PREFIX: &str = "x-shuttle-context-";
+
+/// Attaches context the host established about a request, like the user an auth check
+/// identified, for the guest to trust without checking it again
+pub trait RequestContext: Send + Sync + 'static {
+    /// Get the context of a request as `(name, value)` pairs, which the guest gets as
+    /// `x-shuttle-context-<name>` headers
+    fn context(&self, parts: &Parts) -> Vec<(String, HeaderValue)>;
+}
+
+/// Replace any context headers the client sent with the context `provider` gives the request
+pub(crate) fn apply(provider: &dyn RequestContext, parts: &mut Parts) {
+    let spoofed: Vec<HeaderName> = parts
+        .headers
+        .keys()
+        .filter(|name| name.as_str().starts_with(CONTEXT_HEADER_PREFIX))
+        .cloned()
+        .collect();
+    for name in spoofed {
+        parts.headers.remove(name);
+    }
+
+    for (name, value) in provider.context(parts) {
+        let Ok(header) =
+            HeaderName::from_bytes(format!("{CONTEXT_HEADER_PREFIX}{name}").as_bytes())
+        else {
+            warn!(name, "dropping request context with an invalid name");
+            continue;
+        };
+
+        parts.headers.append(header, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Request;
+
+    use super::*;
+
+    struct BearerUser;
+
+    impl RequestContext for BearerUser {
+        fn context(&self, parts: &Parts) -> Vec<(String, HeaderValue)> {
+            match parts.headers.get("authorization") {
+                Some(token) if token == "Bearer valid" => vec![
+                    ("user-id".to_string(), HeaderValue::from_static("42")),
+                    (
+                        "not a name".to_string(),
+                        HeaderValue::from_static("dropped"),
+                    ),
+                ],
+                _ => Vec::new(),
+            }
+        }
+    }
+
+    fn parts(authorization: &'static str) -> Parts {
+        Request::get("/orders")
+            .header("authorization", authorization)
+            .header("x-shuttle-context-user-id", "1")
+            .header("x-shuttle-context-role", "admin")
+            .body(())
+            .unwrap()
+            .into_parts()
+            .0
+    }
+
+    #[test]
+    fn replaces_client_context() {
+        let mut valid = parts("Bearer valid");
+        apply(&BearerUser, &mut valid);
+        assert_eq!(valid.headers["x-shuttle-context-user-id"], "42");
+        assert_eq!(
+            valid
+                .headers
+                .get_all("x-shuttle-context-user-id")
+                .iter()
+                .count(),
+            1
+        );
+        assert!(valid.headers.get("x-shuttle-context-role").is_none());
+
+        // Clients without context cannot claim any either
+        let mut invalid = parts("Bearer forged");
+        apply(&BearerUser, &mut invalid);
+        assert!(invalid
+            .headers
+            .keys()
+            .all(|name| !name.as_str().starts_with(CONTEXT_HEADER_PREFIX)));
+    }
+}
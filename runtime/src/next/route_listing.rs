@@ -0,0 +1,81 @@
+use hyper::header::{self, HeaderValue};
+use hyper::http::StatusCode;
+use hyper::{Body, Method, Response};
+use serde::Serialize;
+
+use super::auto_options::RouteMethods;
+
+/// A route the guest advertises, as it is listed
+#[derive(Debug, PartialEq, Eq, Serialize)]
+struct RouteDescriptor {
+    /// The path of the route, ending in `*` when it matches every path with that prefix
+    path: String,
+    methods: Vec<String>,
+}
+
+/// The routes the guest advertises in its `shuttle:routes` custom section, sorted by path so the
+/// listing is the same for every request
+fn listing(routes: &RouteMethods) -> Vec<RouteDescriptor> {
+    let mut listing: Vec<_> = routes
+        .routes()
+        .map(|(path, methods)| RouteDescriptor {
+            path,
+            methods: methods.iter().map(Method::to_string).collect(),
+        })
+        .collect();
+    listing.sort_by(|a, b| a.path.cmp(&b.path));
+
+    listing
+}
+
+/// Respond with the routes as a JSON array, which is empty for guests which advertise none
+pub(crate) fn listing_response(routes: &RouteMethods, head: bool) -> Response<Body> {
+    let listing = serde_json::to_vec(&listing(routes)).expect("route listings should serialize");
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        )
+        .header(header::CONTENT_LENGTH, listing.len())
+        .header(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))
+        .body(if head { Body::empty() } else { listing.into() })
+        .expect("building a route listing response should not fail")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn lists_routes_by_path() {
+        let response = listing_response(&RouteMethods::default(), false);
+        assert_eq!(
+            hyper::body::to_bytes(response.into_body()).await.unwrap(),
+            "[]"
+        );
+
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        let section = br#"{"/orders": ["GET", "POST"], "/files/*": ["GET"]}"#;
+        let name = b"shuttle:routes";
+        module.push(0);
+        module.push((1 + name.len() + section.len()) as u8);
+        module.push(name.len() as u8);
+        module.extend_from_slice(name);
+        module.extend_from_slice(section);
+
+        let response = listing_response(&RouteMethods::from_module(&module), false);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let listing: serde_json::Value =
+            serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+                .unwrap();
+        assert_eq!(
+            listing,
+            serde_json::json!([
+                {"path": "/files/*", "methods": ["GET"]},
+                {"path": "/orders", "methods": ["GET", "POST"]},
+            ])
+        );
+    }
+}
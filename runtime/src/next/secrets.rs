@@ -0,0 +1,95 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SecretsError {
+    #[error("secret name '{0}' should be non-empty without '=' or NUL characters")]
+    InvalidName(String),
+    #[error("value of secret '{0}' should not contain NUL characters")]
+    InvalidValue(String),
+}
+
+/// Secrets, like API keys or database URLs, which the guest gets as environment variables
+/// instead of having them baked into the module. Their values are never shown when debug
+/// printed, so they are safe to log.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secrets(BTreeMap<String, String>);
+
+impl Secrets {
+    pub fn new(secrets: HashMap<String, String>) -> Result<Self, SecretsError> {
+        for (name, value) in &secrets {
+            if name.is_empty() || name.contains(['=', '\0']) {
+                return Err(SecretsError::InvalidName(name.clone()));
+            }
+
+            if value.contains('\0') {
+                return Err(SecretsError::InvalidValue(name.clone()));
+            }
+        }
+
+        Ok(Self(secrets.into_iter().collect()))
+    }
+
+    /// The secrets as environment variables, sorted by name so every context gets the same
+    /// environment
+    pub(crate) fn envs(&self) -> Vec<(String, String)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+}
+
+impl fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.0.keys().map(|name| (name, "[redacted]")))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_names_and_values() {
+        for name in ["", "API=KEY", "API\0KEY"] {
+            assert_eq!(
+                Secrets::new(HashMap::from([(name.to_string(), "hunter2".to_string())])),
+                Err(SecretsError::InvalidName(name.to_string()))
+            );
+        }
+
+        assert_eq!(
+            Secrets::new(HashMap::from([(
+                "API_KEY".to_string(),
+                "hunter\02".to_string()
+            )])),
+            Err(SecretsError::InvalidValue("API_KEY".to_string()))
+        );
+    }
+
+    #[test]
+    fn redacted_when_printed() {
+        let secrets = Secrets::new(HashMap::from([
+            ("DATABASE_URL".to_string(), "postgres://db".to_string()),
+            ("API_KEY".to_string(), "hunter2".to_string()),
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            format!("{secrets:?}"),
+            r#"{"API_KEY": "[redacted]", "DATABASE_URL": "[redacted]"}"#
+        );
+        assert_eq!(
+            secrets.envs(),
+            [
+                ("API_KEY".to_string(), "hunter2".to_string()),
+                ("DATABASE_URL".to_string(), "postgres://db".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use shuttle_proto::runtime::LogItem;
+
+/// Numbers the logs of a single request in the order they were emitted, so subscribers can
+/// restore that order after logs were buffered
+pub(crate) struct LogSequence {
+    request_id: u64,
+    next: AtomicU64,
+}
+
+impl LogSequence {
+    pub(crate) fn new(request_id: u64) -> Self {
+        Self {
+            request_id,
+            next: AtomicU64::new(0),
+        }
+    }
+
+    /// Add the request id and the next sequence number to the JSON fields of `log`
+    pub(crate) fn apply(&self, log: &mut LogItem) {
+        let seq = self.next.fetch_add(1, Ordering::Relaxed);
+
+        let Ok(mut fields) =
+            serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(&log.fields)
+        else {
+            return;
+        };
+
+        fields.insert("request_id".to_string(), self.request_id.into());
+        fields.insert("seq".to_string(), seq.into());
+
+        log.fields = serde_json::to_vec(&fields).expect("json values should serialize");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_logs_in_order() {
+        let sequence = LogSequence::new(7);
+
+        let fields: Vec<serde_json::Value> = (0..3)
+            .map(|_| {
+                let mut log = LogItem {
+                    fields: br#"{"message":"hello"}"#.to_vec(),
+                    ..Default::default()
+                };
+                sequence.apply(&mut log);
+
+                serde_json::from_slice(&log.fields).unwrap()
+            })
+            .collect();
+
+        for (seq, fields) in fields.iter().enumerate() {
+            assert_eq!(fields["message"], "hello");
+            assert_eq!(fields["request_id"], 7);
+            assert_eq!(fields["seq"], seq);
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use hyper::Request;
+use tracing::{field, info_span, Span};
+
+/// Create the span for a request served by the guest. Besides the total duration, it records
+/// how long the guest took to instantiate separately from how long its handler ran, to show how
+/// much of the latency is overhead, and how many host calls the guest made. With the `otel`
+/// feature the span continues the trace from the request's `traceparent`/`tracestate` headers,
+/// and those headers are updated so that calls made by the guest continue the trace from this
+/// span.
+pub(crate) fn request_span<B>(req: &mut Request<B>) -> Span {
+    let span = info_span!(
+        "handle_request",
+        http.method = %req.method(),
+        http.uri = %req.uri(),
+        http.status_code = field::Empty,
+        duration_ms = field::Empty,
+        instantiation_us = field::Empty,
+        handler_us = field::Empty,
+        host_calls = field::Empty,
+    );
+
+    #[cfg(feature = "otel")]
+    {
+        use opentelemetry::global;
+        use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let parent_context = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(req.headers()))
+        });
+        span.set_parent(parent_context);
+
+        let cx = span.context();
+        global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&cx, &mut HeaderInjector(req.headers_mut()))
+        });
+    }
+
+    span
+}
@@ -0,0 +1,569 @@
+use std::io::{ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use hyper::header::{self, HeaderValue};
+use hyper::http::StatusCode;
+use hyper::{Body, HeaderMap, Method, Request, Response};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tracing::warn;
+
+use super::conditional::Validators;
+
+// Files are streamed in chunks of this size, so only the part a request asks for is read
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Files under a directory which the host serves without calling the guest
+#[derive(Clone, Debug)]
+pub struct StaticFiles {
+    prefix: String,
+    root: PathBuf,
+}
+
+/// An open file, with the metadata its validators are derived from
+struct File {
+    handle: tokio::fs::File,
+    path: PathBuf,
+    length: u64,
+    modified: SystemTime,
+}
+
+/// The part of a file a request asks for with its `Range` header
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    /// The whole file, also for malformed ranges and multiple ranges, which are ignored
+    Full,
+    /// The bytes from the first to the last offset, inclusive
+    Partial(u64, u64),
+    /// A range which starts past the end of the file
+    Unsatisfiable,
+}
+
+impl StaticFiles {
+    /// Serve the files in `root` for request paths starting with `prefix`
+    pub fn new(prefix: impl Into<String>, root: impl AsRef<Path>) -> Self {
+        let mut prefix = prefix.into();
+        if !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+
+        Self {
+            prefix,
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Get the response for a request, if it is for a file which exists. A precompressed `.gz`
+    /// sidecar of the file is served instead when the client accepts gzip. A single byte range
+    /// of the file is served when the request asks for one.
+    pub async fn respond<B>(&self, req: &Request<B>) -> Option<Response<Body>> {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return None;
+        }
+
+        let mut path = self.file_path(req.uri().path())?;
+        if tokio::fs::metadata(&path)
+            .await
+            .is_ok_and(|metadata| metadata.is_dir())
+        {
+            path.push("index.html");
+        }
+
+        let content_type = content_type(&path);
+
+        let sidecar = if accepts_gzip(req.headers()) {
+            let mut sidecar = path.clone().into_os_string();
+            sidecar.push(".gz");
+            self.open(PathBuf::from(sidecar)).await
+        } else {
+            None
+        };
+        let gzipped = sidecar.is_some();
+
+        let file = match sidecar {
+            Some(file) => file,
+            None => self.open(path).await?,
+        };
+
+        let validators = Validators::for_file(file.length, file.modified);
+
+        let mut response = if validators.is_not_modified(req.headers()) {
+            Response::builder()
+                .status(StatusCode::NOT_MODIFIED)
+                .body(Body::empty())
+        } else {
+            let length = file.length;
+            let range = if validators.is_range_current(req.headers()) {
+                byte_range(req.headers(), length)
+            } else {
+                ByteRange::Full
+            };
+            let head = req.method() == Method::HEAD;
+
+            match range {
+                ByteRange::Full => Response::builder()
+                    .header(header::CONTENT_TYPE, HeaderValue::from_static(content_type))
+                    .header(header::CONTENT_LENGTH, length)
+                    .body(if head {
+                        Body::empty()
+                    } else {
+                        file.stream(0, length).await?
+                    }),
+                ByteRange::Partial(first, last) => {
+                    let part_length = last - first + 1;
+
+                    Response::builder()
+                        .status(StatusCode::PARTIAL_CONTENT)
+                        .header(header::CONTENT_TYPE, HeaderValue::from_static(content_type))
+                        .header(header::CONTENT_LENGTH, part_length)
+                        .header(
+                            header::CONTENT_RANGE,
+                            format!("bytes {first}-{last}/{length}"),
+                        )
+                        .body(if head {
+                            Body::empty()
+                        } else {
+                            file.stream(first, part_length).await?
+                        })
+                }
+                ByteRange::Unsatisfiable => Response::builder()
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(header::CONTENT_RANGE, format!("bytes */{length}"))
+                    .body(Body::empty()),
+            }
+        }
+        .expect("building a static file response should not fail");
+
+        let headers = response.headers_mut();
+        validators.apply(headers);
+        headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+        if gzipped {
+            headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        }
+
+        Some(response)
+    }
+
+    /// Open the file at `path`, unless it is missing or not a regular file, or resolves to
+    /// somewhere outside the root through a symlink
+    async fn open(&self, path: PathBuf) -> Option<File> {
+        let result = async {
+            let root = tokio::fs::canonicalize(&self.root).await?;
+            let resolved = tokio::fs::canonicalize(&path).await?;
+            if !resolved.starts_with(&root) {
+                warn!(path = %path.display(), "refusing to serve a static file outside the root");
+
+                return Ok(None);
+            }
+
+            let handle = tokio::fs::File::open(&resolved).await?;
+            let metadata = handle.metadata().await?;
+            if !metadata.is_file() {
+                return Ok(None);
+            }
+
+            Ok::<_, std::io::Error>(Some(File {
+                handle,
+                length: metadata.len(),
+                modified: metadata.modified()?,
+                path: resolved,
+            }))
+        }
+        .await;
+
+        match result {
+            Ok(file) => file,
+            Err(error) => {
+                if error.kind() != ErrorKind::NotFound {
+                    warn!(%error, path = %path.display(), "failed to open static file");
+                }
+
+                None
+            }
+        }
+    }
+
+    /// Map a request path to a path under the root, rejecting paths which could escape it
+    fn file_path(&self, request_path: &str) -> Option<PathBuf> {
+        let relative = request_path.strip_prefix(&self.prefix)?;
+        let mut path = self.root.clone();
+
+        for segment in relative.split('/').filter(|segment| !segment.is_empty()) {
+            let segment = percent_decode(segment)?;
+
+            if segment == "." || segment == ".." || segment.contains(['/', '\\', '\0']) {
+                return None;
+            }
+
+            path.push(segment);
+        }
+
+        Some(path)
+    }
+}
+
+impl File {
+    /// Stream `length` bytes of the file from `offset` on, without reading the rest of it
+    async fn stream(mut self, offset: u64, length: u64) -> Option<Body> {
+        if let Err(error) = self.handle.seek(SeekFrom::Start(offset)).await {
+            warn!(%error, path = %self.path.display(), "failed to seek in static file");
+
+            return None;
+        }
+
+        let chunks = futures::stream::unfold(Some(self.handle.take(length)), |reader| async move {
+            let mut reader = reader?;
+            let mut chunk = vec![0; STREAM_CHUNK_SIZE];
+
+            match reader.read(&mut chunk).await {
+                Ok(0) => None,
+                Ok(read) => {
+                    chunk.truncate(read);
+                    Some((Ok(chunk), Some(reader)))
+                }
+                // The response has started, so this can only break the transfer
+                Err(error) => Some((Err(error), None)),
+            }
+        });
+
+        Some(Body::wrap_stream(chunks))
+    }
+}
+
+/// Get the byte range a request asks for in a file of `length` bytes. Only a single range is
+/// served, so requests for more than one get the whole file, like servers may do.
+fn byte_range(headers: &HeaderMap, length: u64) -> ByteRange {
+    let Some(range) = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return ByteRange::Full;
+    };
+
+    let Some(spec) = range.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+    let Some((first, last)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+    if last.contains(',') {
+        return ByteRange::Full;
+    }
+
+    let parse = |offset: &str| offset.trim().parse::<u64>().ok();
+    match (first.trim(), last.trim()) {
+        // The last `n` bytes
+        ("", suffix) => match parse(suffix) {
+            Some(0) => ByteRange::Unsatisfiable,
+            Some(_) if length == 0 => ByteRange::Unsatisfiable,
+            Some(suffix) => ByteRange::Partial(length.saturating_sub(suffix), length - 1),
+            None => ByteRange::Full,
+        },
+        (first, last) => {
+            let Some(first) = parse(first) else {
+                return ByteRange::Full;
+            };
+            let last = match last {
+                "" => None,
+                last => match parse(last) {
+                    Some(last) if last >= first => Some(last),
+                    _ => return ByteRange::Full,
+                },
+            };
+
+            if first >= length {
+                return ByteRange::Unsatisfiable;
+            }
+
+            ByteRange::Partial(first, last.map_or(length - 1, |last| last.min(length - 1)))
+        }
+    }
+}
+
+/// Check if `Accept-Encoding` allows gzip, taking a quality of 0 as refusing it
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|encoding| {
+            let mut parts = encoding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let refused = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+        })
+}
+
+/// Decode `%XX` escapes, returning `None` for invalid escapes or non UTF-8 results
+fn percent_decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(index + 1..index + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+/// Derive the content type from the extension of a file
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "wasm" => "application/wasm",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "pdf" => "application/pdf",
+        "mp4" => "video/mp4",
+        "mp3" => "audio/mpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn static_dir(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("static-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("css")).unwrap();
+        std::fs::write(root.join("index.html"), "<h1>hello</h1>").unwrap();
+        std::fs::write(root.join("css/site.css"), "body {}").unwrap();
+        std::fs::write(root.join("css/site.css.gz"), b"\x1f\x8bgzipped").unwrap();
+
+        root
+    }
+
+    fn request(path: &str, accept_encoding: Option<&'static str>) -> Request<()> {
+        let mut request = Request::get(path);
+        if let Some(accept_encoding) = accept_encoding {
+            request = request.header(header::ACCEPT_ENCODING, accept_encoding);
+        }
+
+        request.body(()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves_files() {
+        let root = static_dir("files");
+        let files = StaticFiles::new("/static", &root);
+
+        let response = files.respond(&request("/static/", None)).await.unwrap();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/html; charset=utf-8"
+        );
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "<h1>hello</h1>");
+
+        assert!(files
+            .respond(&request("/static/missing.js", None))
+            .await
+            .is_none());
+        assert!(files
+            .respond(&request("/other/index.html", None))
+            .await
+            .is_none());
+        assert!(files
+            .respond(&request("/static/%2e%2e/secret", None))
+            .await
+            .is_none());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn gzip_sidecar() {
+        let root = static_dir("gzip");
+        let files = StaticFiles::new("/static", &root);
+
+        let response = files
+            .respond(&request("/static/css/site.css", Some("br, gzip")))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/css; charset=utf-8"
+        );
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, &b"\x1f\x8bgzipped"[..]);
+
+        for accept_encoding in [None, Some("br"), Some("gzip;q=0")] {
+            let response = files
+                .respond(&request("/static/css/site.css", accept_encoding))
+                .await
+                .unwrap();
+            assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert_eq!(body, "body {}");
+        }
+
+        // Files without a sidecar are served uncompressed
+        let response = files
+            .respond(&request("/static/index.html", Some("gzip")))
+            .await
+            .unwrap();
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn byte_ranges() {
+        let range = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::RANGE, HeaderValue::from_static(value));
+            byte_range(&headers, 10)
+        };
+
+        assert_eq!(byte_range(&HeaderMap::new(), 10), ByteRange::Full);
+        assert_eq!(range("bytes=0-4"), ByteRange::Partial(0, 4));
+        assert_eq!(range("bytes=5-"), ByteRange::Partial(5, 9));
+        assert_eq!(range("bytes=-3"), ByteRange::Partial(7, 9));
+        assert_eq!(range("bytes=-30"), ByteRange::Partial(0, 9));
+        assert_eq!(range("bytes=8-20"), ByteRange::Partial(8, 9));
+
+        assert_eq!(range("bytes=10-"), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=-0"), ByteRange::Unsatisfiable);
+
+        // Malformed and multiple ranges are ignored
+        for value in ["bytes=5-2", "bytes=a-b", "items=0-4", "bytes=0-1,4-5"] {
+            assert_eq!(range(value), ByteRange::Full, "{value}");
+        }
+    }
+
+    #[tokio::test]
+    async fn partial_content() {
+        let root = static_dir("range");
+        let files = StaticFiles::new("/static", &root);
+
+        let range = |value: &'static str| {
+            Request::get("/static/index.html")
+                .header(header::RANGE, value)
+                .body(())
+                .unwrap()
+        };
+
+        let response = files.respond(&range("bytes=4-8")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 4-8/14");
+        assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "hello");
+
+        let response = files.respond(&range("bytes=20-")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */14");
+
+        // A range for a different version of the file gets the whole current one
+        let response = files
+            .respond(
+                &Request::get("/static/index.html")
+                    .header(header::RANGE, "bytes=4-8")
+                    .header(header::IF_RANGE, "\"outdated\"")
+                    .body(())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "<h1>hello</h1>");
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn ranges_of_large_files() {
+        let root = static_dir("large");
+        let content: Vec<u8> = (0..4 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        std::fs::write(root.join("video.mp4"), &content).unwrap();
+        let files = StaticFiles::new("/static", &root);
+
+        let response = files
+            .respond(
+                &Request::get("/static/video.mp4")
+                    .header(header::RANGE, "bytes=3000000-3000009")
+                    .body(())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "10");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, &content[3000000..3000010]);
+
+        // The whole file is streamed in chunks
+        let response = files
+            .respond(&request("/static/video.mp4", None))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, content);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn symlinks_stay_under_the_root() {
+        let root = static_dir("symlinks");
+        let outside = std::env::temp_dir().join(format!("static-outside-{}", std::process::id()));
+        std::fs::write(&outside, "secret").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("secret.txt")).unwrap();
+        std::os::unix::fs::symlink(root.join("index.html"), root.join("home.html")).unwrap();
+        let files = StaticFiles::new("/static", &root);
+
+        assert!(files
+            .respond(&request("/static/secret.txt", None))
+            .await
+            .is_none());
+
+        // Symlinks to files under the root are still served
+        let response = files
+            .respond(&request("/static/home.html", None))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "<h1>hello</h1>");
+
+        std::fs::remove_dir_all(root).unwrap();
+        std::fs::remove_file(outside).unwrap();
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::{BTreeMap, HashMap};
+
+use shuttle_proto::runtime::LogItem;
+use thiserror::Error;
+
+// Keep the tags bounded so that they are safe to use as labels
+const MAX_TAGS: usize = 16;
+const MAX_KEY_LEN: usize = 32;
+const MAX_VALUE_LEN: usize = 64;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TagsError {
+    #[error("at most {} tags are allowed", MAX_TAGS)]
+    TooMany,
+    #[error(
+        "tag key '{0}' should be 1 to {} lowercase letters, digits or underscores",
+        MAX_KEY_LEN
+    )]
+    InvalidKey(String),
+    #[error("value of tag '{0}' should be at most {} characters", MAX_VALUE_LEN)]
+    ValueTooLong(String),
+}
+
+/// Tags, like the project name or environment, to label everything a deployment logs with
+#[derive(Clone, Debug, Default)]
+pub struct Tags(BTreeMap<String, String>);
+
+impl Tags {
+    pub fn new(tags: HashMap<String, String>) -> Result<Self, TagsError> {
+        if tags.len() > MAX_TAGS {
+            return Err(TagsError::TooMany);
+        }
+
+        for (key, value) in &tags {
+            let is_valid_key = !key.is_empty()
+                && key.len() <= MAX_KEY_LEN
+                && key
+                    .bytes()
+                    .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_');
+
+            if !is_valid_key {
+                return Err(TagsError::InvalidKey(key.clone()));
+            }
+
+            if value.chars().count() > MAX_VALUE_LEN {
+                return Err(TagsError::ValueTooLong(key.clone()));
+            }
+        }
+
+        Ok(Self(tags.into_iter().collect()))
+    }
+
+    /// Add the tags to the fields of a log item
+    pub fn apply(&self, log: &mut LogItem) {
+        if self.0.is_empty() {
+            return;
+        }
+
+        let Ok(mut fields) =
+            serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(&log.fields)
+        else {
+            return;
+        };
+
+        fields.insert(
+            "tags".to_string(),
+            serde_json::to_value(&self.0).expect("tags should serialize"),
+        );
+
+        log.fields = serde_json::to_vec(&fields).expect("json values should serialize");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validation() {
+        let tags = |pairs: &[(&str, &str)]| {
+            Tags::new(
+                pairs
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+            )
+        };
+
+        assert!(tags(&[("project", "hello-world"), ("region", "eu_west_2")]).is_ok());
+        assert_eq!(
+            tags(&[("Project", "hello")]).unwrap_err(),
+            TagsError::InvalidKey("Project".to_string())
+        );
+        assert_eq!(
+            tags(&[("", "hello")]).unwrap_err(),
+            TagsError::InvalidKey(String::new())
+        );
+        assert_eq!(
+            tags(&[("project", &"a".repeat(65))]).unwrap_err(),
+            TagsError::ValueTooLong("project".to_string())
+        );
+
+        let too_many: HashMap<_, _> = (0..17)
+            .map(|i| (format!("tag_{i}"), String::new()))
+            .collect();
+        assert_eq!(Tags::new(too_many).unwrap_err(), TagsError::TooMany);
+    }
+
+    #[test]
+    fn apply() {
+        let tags = Tags::new(HashMap::from([("env".to_string(), "prod".to_string())])).unwrap();
+        let mut log = LogItem {
+            fields: br#"{"message":"hello"}"#.to_vec(),
+            ..Default::default()
+        };
+
+        tags.apply(&mut log);
+
+        let fields: serde_json::Value = serde_json::from_slice(&log.fields).unwrap();
+        assert_eq!(fields["message"], "hello");
+        assert_eq!(fields["tags"]["env"], "prod");
+    }
+}
@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+// Follows the paused clock of tests, like the rest of the router
+use tokio::time::Instant;
+
+// Sockets take no zero timeout, so an exhausted budget still gets this long to write
+const MIN_WRITE_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// The time budgets of the phases of one request, each capped by what is left of the budget of
+/// the whole request
+pub(crate) struct RequestTimeouts {
+    deadline: Option<Instant>,
+    instantiate: Option<Duration>,
+    write: Duration,
+    handler: Option<Duration>,
+}
+
+impl RequestTimeouts {
+    /// Start the budgets of a request which is starting now
+    pub(crate) fn start(
+        instantiate: Option<Duration>,
+        write: Duration,
+        handler: Option<Duration>,
+        total: Option<Duration>,
+    ) -> Self {
+        Self {
+            deadline: total.map(|total| Instant::now() + total),
+            instantiate,
+            write,
+            handler,
+        }
+    }
+
+    /// How long instantiating the module can take from now
+    pub(crate) fn instantiate(&self) -> Option<Duration> {
+        self.capped(self.instantiate)
+    }
+
+    /// How long writing the request to the guest can take from now
+    pub(crate) fn write(&self) -> Duration {
+        self.capped(Some(self.write))
+            .unwrap_or(self.write)
+            .max(MIN_WRITE_TIMEOUT)
+    }
+
+    /// When the guest has to have produced its response by, if it is called now
+    pub(crate) fn handler_deadline(&self) -> Option<Instant> {
+        self.capped(self.handler)
+            .map(|handler| Instant::now() + handler)
+    }
+
+    fn capped(&self, budget: Option<Duration>) -> Option<Duration> {
+        let remaining = self
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+
+        match (budget, remaining) {
+            (Some(budget), Some(remaining)) => Some(budget.min(remaining)),
+            (budget, remaining) => budget.or(remaining),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn phases_are_capped_by_the_total() {
+        let timeouts = RequestTimeouts::start(
+            Some(Duration::from_secs(2)),
+            Duration::from_secs(5),
+            None,
+            Some(Duration::from_secs(10)),
+        );
+
+        assert_eq!(timeouts.instantiate(), Some(Duration::from_secs(2)));
+        assert_eq!(timeouts.write(), Duration::from_secs(5));
+
+        tokio::time::advance(Duration::from_secs(7)).await;
+
+        assert_eq!(timeouts.instantiate(), Some(Duration::from_secs(2)));
+        assert_eq!(timeouts.write(), Duration::from_secs(3));
+        // Without a handler budget of its own, the handler gets what is left
+        assert_eq!(
+            timeouts.handler_deadline(),
+            Some(Instant::now() + Duration::from_secs(3))
+        );
+
+        tokio::time::advance(Duration::from_secs(5)).await;
+
+        assert_eq!(timeouts.instantiate(), Some(Duration::ZERO));
+        assert_eq!(timeouts.write(), MIN_WRITE_TIMEOUT);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn no_total() {
+        let timeouts = RequestTimeouts::start(
+            None,
+            Duration::from_secs(5),
+            Some(Duration::from_secs(1)),
+            None,
+        );
+
+        assert_eq!(timeouts.instantiate(), None);
+        assert_eq!(timeouts.write(), Duration::from_secs(5));
+        assert_eq!(
+            timeouts.handler_deadline(),
+            Some(Instant::now() + Duration::from_secs(1))
+        );
+    }
+}
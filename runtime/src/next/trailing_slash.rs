@@ -0,0 +1,128 @@
+use hyper::header;
+use hyper::http::uri::PathAndQuery;
+use hyper::http::StatusCode;
+use hyper::{Body, Request, Response, Uri};
+
+/// What the host does with a trailing slash on a request path before the guest sees it. The
+/// root path `/` is never changed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// Pass paths to the guest as they are
+    #[default]
+    Keep,
+    /// Redirect paths ending in a slash to the path without it
+    RedirectToNoSlash,
+    /// Redirect paths not ending in a slash to the path with it
+    RedirectToSlash,
+    /// Remove a trailing slash before the guest sees the path, so `/hello/` is served as
+    /// `/hello`
+    Ignore,
+}
+
+impl TrailingSlash {
+    /// Normalize the path of `req`, either in place or by responding with a `308 Permanent
+    /// Redirect` to the normalized path
+    pub(crate) fn apply<B>(self, req: &mut Request<B>) -> Option<Response<Body>> {
+        let path = req.uri().path();
+
+        // A path starting with `//` would redirect to another host
+        if path == "/" || path.starts_with("//") {
+            return None;
+        }
+
+        let normalized = match self {
+            Self::Keep => return None,
+            Self::RedirectToSlash if !path.ends_with('/') => format!("{path}/"),
+            Self::RedirectToSlash => return None,
+            Self::RedirectToNoSlash | Self::Ignore => {
+                let trimmed = path.trim_end_matches('/');
+                if trimmed.len() == path.len() || trimmed.is_empty() {
+                    return None;
+                }
+
+                trimmed.to_string()
+            }
+        };
+
+        let path_and_query = match req.uri().query() {
+            Some(query) => format!("{normalized}?{query}"),
+            None => normalized,
+        };
+
+        if self == Self::Ignore {
+            let mut parts = req.uri().clone().into_parts();
+            parts.path_and_query = Some(path_and_query.parse::<PathAndQuery>().ok()?);
+            *req.uri_mut() = Uri::from_parts(parts).ok()?;
+
+            return None;
+        }
+
+        // The path and query come from a valid URI, so they are valid here too
+        let response = Response::builder()
+            .status(StatusCode::PERMANENT_REDIRECT)
+            .header(header::LOCATION, path_and_query)
+            .body(Body::empty())
+            .expect("building a redirect response should not fail");
+
+        Some(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(policy: TrailingSlash, uri: &str) -> Option<String> {
+        let mut req = Request::get(uri).body(()).unwrap();
+        let res = policy.apply(&mut req)?;
+
+        assert_eq!(res.status(), StatusCode::PERMANENT_REDIRECT);
+        Some(
+            res.headers()[header::LOCATION]
+                .to_str()
+                .unwrap()
+                .to_string(),
+        )
+    }
+
+    #[test]
+    fn redirects() {
+        assert_eq!(
+            location(TrailingSlash::RedirectToNoSlash, "/hello/?name=wasm").as_deref(),
+            Some("/hello?name=wasm")
+        );
+        assert_eq!(location(TrailingSlash::RedirectToNoSlash, "/hello"), None);
+        assert_eq!(location(TrailingSlash::RedirectToNoSlash, "/"), None);
+
+        assert_eq!(
+            location(TrailingSlash::RedirectToSlash, "/hello").as_deref(),
+            Some("/hello/")
+        );
+        assert_eq!(location(TrailingSlash::RedirectToSlash, "/hello/"), None);
+
+        // Never redirect to another host
+        assert_eq!(
+            location(TrailingSlash::RedirectToNoSlash, "//evil.example/"),
+            None
+        );
+        assert_eq!(
+            location(TrailingSlash::RedirectToSlash, "//evil.example"),
+            None
+        );
+
+        assert_eq!(location(TrailingSlash::Keep, "/hello/"), None);
+    }
+
+    #[test]
+    fn ignore() {
+        let mut req = Request::get("https://example.com/hello//?name=wasm")
+            .body(())
+            .unwrap();
+        assert!(TrailingSlash::Ignore.apply(&mut req).is_none());
+        assert_eq!(req.uri(), "https://example.com/hello?name=wasm");
+
+        let mut req = Request::get("/").body(()).unwrap();
+        assert!(TrailingSlash::Ignore.apply(&mut req).is_none());
+        assert_eq!(req.uri(), "/");
+    }
+}
@@ -0,0 +1,168 @@
+use hyper::HeaderMap;
+
+/// Rewrites the body of guest responses while it is streamed to the client, for example to
+/// inject a script tag into HTML pages. Responses are only transformed when the guest set a
+/// `Content-Type` which [ResponseBodyTransform::applies_to] accepts.
+///
+/// Transforms see the body as the guest sent it, so a body with a `Content-Encoding` is
+/// still encoded.
+pub trait ResponseBodyTransform: Send + Sync + 'static {
+    /// Check if responses with this content type, without its parameters, should be transformed
+    fn applies_to(&self, content_type: &str) -> bool;
+
+    /// Start transforming the body of a single response
+    fn start(&self) -> Box<dyn BodyTransformer>;
+}
+
+/// Transforms the body of a single response one chunk at a time. Chunks are split at arbitrary
+/// points, so anything spanning chunks has to be held back until the next chunk arrives.
+pub trait BodyTransformer: Send {
+    /// Transform the next chunk of the body, returning the bytes to send on
+    fn transform(&mut self, chunk: Vec<u8>) -> Vec<u8>;
+
+    /// Get any bytes still held back once the whole body has been transformed
+    fn finish(&mut self) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+/// Get the transformer for a response with these headers, if `transform` applies to it
+pub(crate) fn transformer_for(
+    transform: &dyn ResponseBodyTransform,
+    headers: &HeaderMap,
+) -> Option<Box<dyn BodyTransformer>> {
+    let content_type = headers
+        .get(hyper::header::CONTENT_TYPE)?
+        .to_str()
+        .ok()?
+        .split(';')
+        .next()?
+        .trim();
+
+    transform
+        .applies_to(content_type)
+        .then(|| transform.start())
+}
+
+/// Pass every chunk of a body through `transformer`. The held back bytes are only sent when the
+/// body ends without an error, since the body is incomplete otherwise.
+pub(crate) fn transform_chunks<I>(
+    mut chunks: I,
+    mut transformer: Box<dyn BodyTransformer>,
+) -> impl Iterator<Item = std::io::Result<Vec<u8>>>
+where
+    I: Iterator<Item = std::io::Result<Vec<u8>>>,
+{
+    let mut done = false;
+
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+
+        match chunks.next() {
+            Some(Ok(chunk)) => Some(Ok(transformer.transform(chunk))),
+            Some(Err(error)) => {
+                done = true;
+                Some(Err(error))
+            }
+            None => {
+                done = true;
+                Some(Ok(transformer.finish()))
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::HeaderValue;
+
+    use super::*;
+
+    /// Replaces every `a` with `b`, holding back a trailing `!` until the end
+    struct Replace;
+
+    struct ReplaceTransformer {
+        held: bool,
+    }
+
+    impl ResponseBodyTransform for Replace {
+        fn applies_to(&self, content_type: &str) -> bool {
+            content_type == "text/html"
+        }
+
+        fn start(&self) -> Box<dyn BodyTransformer> {
+            Box::new(ReplaceTransformer { held: false })
+        }
+    }
+
+    impl BodyTransformer for ReplaceTransformer {
+        fn transform(&mut self, mut chunk: Vec<u8>) -> Vec<u8> {
+            if self.held {
+                chunk.insert(0, b'!');
+            }
+
+            self.held = chunk.last() == Some(&b'!');
+            if self.held {
+                chunk.pop();
+            }
+
+            chunk
+                .into_iter()
+                .map(|byte| if byte == b'a' { b'b' } else { byte })
+                .collect()
+        }
+
+        fn finish(&mut self) -> Vec<u8> {
+            if self.held {
+                b"!".to_vec()
+            } else {
+                Vec::new()
+            }
+        }
+    }
+
+    #[test]
+    fn only_matching_content_types() {
+        let mut headers = HeaderMap::new();
+        assert!(transformer_for(&Replace, &headers).is_none());
+
+        headers.insert(
+            hyper::header::CONTENT_TYPE,
+            HeaderValue::from_static("text/html; charset=utf-8"),
+        );
+        assert!(transformer_for(&Replace, &headers).is_some());
+
+        headers.insert(
+            hyper::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        assert!(transformer_for(&Replace, &headers).is_none());
+    }
+
+    #[test]
+    fn held_back_bytes_are_sent_at_the_end() {
+        let chunks = vec![Ok(b"aa!".to_vec()), Ok(b"ca!".to_vec())];
+
+        let body: Vec<u8> = transform_chunks(chunks.into_iter(), Replace.start())
+            .flat_map(Result::unwrap)
+            .collect();
+
+        assert_eq!(body, b"bb!cb!");
+    }
+
+    #[test]
+    fn held_back_bytes_are_dropped_on_error() {
+        let chunks = vec![
+            Ok(b"a!".to_vec()),
+            Err(std::io::Error::from(std::io::ErrorKind::BrokenPipe)),
+        ];
+
+        let results: Vec<_> = transform_chunks(chunks.into_iter(), Replace.start()).collect();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap(), b"b");
+        assert!(results[1].is_err());
+    }
+}
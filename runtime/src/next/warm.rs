@@ -0,0 +1,159 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+use tracing::warn;
+
+/// Instances of the module which were made ahead of the requests which will use them, so the
+/// first requests after a start do not all pay for instantiating the module. Every instance is
+/// used by one request only, and the pool is refilled in the background as they are taken.
+pub(crate) struct WarmPool<T> {
+    size: usize,
+    /// The ready instances, with the generation of the module they were made from
+    instances: Mutex<Vec<(u64, T)>>,
+    taken: Notify,
+}
+
+impl<T: Send + 'static> WarmPool<T> {
+    /// Keep `size` instances ready
+    pub(crate) fn new(size: usize) -> Self {
+        Self {
+            size,
+            instances: Default::default(),
+            taken: Notify::new(),
+        }
+    }
+
+    /// Take a ready instance of the module at `generation`. Instances of other generations were
+    /// made from a module which has been swapped out since, so they are dropped.
+    pub(crate) fn take(&self, generation: u64) -> Option<T> {
+        let mut instances = self
+            .instances
+            .lock()
+            .expect("warm pool lock should not be poisoned");
+
+        instances.retain(|(made_from, _)| *made_from == generation);
+        let instance = instances.pop().map(|(_, instance)| instance);
+
+        if instances.len() < self.size {
+            self.taken.notify_one();
+        }
+
+        instance
+    }
+
+    /// How many instances are ready
+    pub(crate) fn len(&self) -> usize {
+        self.instances
+            .lock()
+            .expect("warm pool lock should not be poisoned")
+            .len()
+    }
+
+    /// Keep the pool filled with instances from `make`, which gives the generation of the module
+    /// it made each one from. A failed instance leaves the pool short until the next one is
+    /// taken, so a module which cannot be instantiated is not retried in a loop.
+    pub(crate) async fn refill<F>(self: Arc<Self>, make: F)
+    where
+        F: Fn() -> anyhow::Result<(u64, T)> + Send + Sync + 'static,
+    {
+        let make = Arc::new(make);
+
+        loop {
+            while self.len() < self.size {
+                let made = tokio::task::spawn_blocking({
+                    let make = make.clone();
+                    move || make()
+                })
+                .await;
+
+                match made {
+                    Ok(Ok(instance)) => self
+                        .instances
+                        .lock()
+                        .expect("warm pool lock should not be poisoned")
+                        .push(instance),
+                    Ok(Err(error)) => {
+                        warn!(%error, "failed to instantiate the module for the warm pool");
+                        break;
+                    }
+                    Err(error) => {
+                        warn!(%error, "instantiating the module for the warm pool panicked");
+                        break;
+                    }
+                }
+            }
+
+            self.taken.notified().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+
+    async fn filled<T: Send + 'static>(pool: &WarmPool<T>, len: usize) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while pool.len() != len {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("pool should be filled");
+    }
+
+    #[tokio::test]
+    async fn refill() {
+        let pool = Arc::new(WarmPool::new(2));
+        let generation = Arc::new(AtomicU64::new(0));
+        let made = Arc::new(AtomicUsize::new(0));
+
+        let refiller = tokio::spawn(pool.clone().refill({
+            let generation = generation.clone();
+            let made = made.clone();
+            move || {
+                let instance = made.fetch_add(1, Ordering::Relaxed);
+                Ok((generation.load(Ordering::Relaxed), instance))
+            }
+        }));
+
+        filled(&pool, 2).await;
+        assert!(pool.take(0).is_some());
+        filled(&pool, 2).await;
+        assert_eq!(made.load(Ordering::Relaxed), 3);
+
+        // Instances of a swapped out module are never handed out
+        generation.store(1, Ordering::Relaxed);
+        assert_eq!(pool.take(1), None);
+        filled(&pool, 2).await;
+        assert!(pool.take(1).unwrap() >= 3);
+
+        refiller.abort();
+    }
+
+    #[tokio::test]
+    async fn failed_instances_wait_for_the_next_take() {
+        let pool = Arc::new(WarmPool::<()>::new(2));
+        let attempts = Arc::new(AtomicUsize::new(0));
+
+        let refiller = tokio::spawn(pool.clone().refill({
+            let attempts = attempts.clone();
+            move || {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                Err(anyhow::anyhow!("module traps on start"))
+            }
+        }));
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(attempts.load(Ordering::Relaxed), 1);
+
+        assert_eq!(pool.take(0), None);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(attempts.load(Ordering::Relaxed), 2);
+
+        refiller.abort();
+    }
+}
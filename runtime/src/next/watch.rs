@@ -0,0 +1,378 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Context};
+use ring::digest;
+use tracing::{info, warn};
+use wasmtime::{Engine, Module};
+
+use super::auto_options::RouteMethods;
+use super::body_limit::RouteBodyLimits;
+use super::metadata::ModuleMetadata;
+
+// How often the source of a watched module is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Long enough for a deploy to finish writing a module it is staging
+const DEFAULT_LOAD_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// How often loading a module is retried when it fails in a way which can pass, like when its
+/// file is still being written
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct LoadRetries {
+    /// Retries after the first attempt, so no retries by default
+    pub attempts: u32,
+    pub delay: Duration,
+}
+
+impl Default for LoadRetries {
+    fn default() -> Self {
+        Self {
+            attempts: 0,
+            delay: DEFAULT_LOAD_RETRY_DELAY,
+        }
+    }
+}
+
+/// The module requests are routed to, which can be swapped out while requests are in flight.
+/// Requests which already started keep using the module they started with.
+pub(crate) struct SwappableModule {
+    current: RwLock<LoadedModule>,
+    /// Counts the swaps, so what was made from an earlier module can be told apart
+    generation: AtomicU64,
+}
+
+/// A compiled module with the build metadata, route body limits and route methods it embeds
+#[derive(Clone)]
+pub(crate) struct LoadedModule {
+    pub module: Module,
+    pub metadata: Arc<ModuleMetadata>,
+    pub body_limits: Arc<RouteBodyLimits>,
+    pub route_methods: Arc<RouteMethods>,
+    /// Hex SHA-256 of the bytes the module was compiled from
+    pub sha256: Arc<str>,
+}
+
+impl LoadedModule {
+    /// Compile the module at `path` and read its metadata
+    pub(crate) fn from_file(engine: &Engine, path: &Path) -> anyhow::Result<Self> {
+        Self::from_file_with_retries(engine, path, &LoadRetries::default())
+    }
+
+    /// Like [LoadedModule::from_file], but retry failures which can pass. Modules which are
+    /// invalid fail right away.
+    pub(crate) fn from_file_with_retries(
+        engine: &Engine,
+        path: &Path,
+        retries: &LoadRetries,
+    ) -> anyhow::Result<Self> {
+        let mut attempt = 0;
+
+        loop {
+            match Self::try_from_file(engine, path) {
+                Ok(loaded) => return Ok(loaded),
+                Err((error, true)) if attempt < retries.attempts => {
+                    attempt += 1;
+                    warn!(
+                        error = %error,
+                        path = %path.display(),
+                        attempt,
+                        "failed to load module, retrying"
+                    );
+                    std::thread::sleep(retries.delay);
+                }
+                Err((error, _)) => return Err(error),
+            }
+        }
+    }
+
+    /// Load the module at `path`, telling whether a failure can pass on its own
+    fn try_from_file(engine: &Engine, path: &Path) -> Result<Self, (anyhow::Error, bool)> {
+        let before = file_state(path);
+        let bytes = std::fs::read(path).map_err(|error| {
+            // The file can be moved into place any moment while a deploy stages it
+            let transient = matches!(
+                error.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::Interrupted
+            );
+
+            (
+                anyhow::Error::new(error).context("failed to read module"),
+                transient,
+            )
+        })?;
+
+        Self::from_bytes(engine, &bytes).map_err(|error| {
+            // A module which is still being written fails to compile, but then its file is
+            // empty or changes while it is loaded
+            let after = file_state(path);
+            let transient = bytes.is_empty()
+                || before.is_none()
+                || before != after
+                || after.is_some_and(|(len, _)| len != bytes.len() as u64);
+
+            (error, transient)
+        })
+    }
+
+    /// Compile the module in `bytes` and read its metadata
+    pub(crate) fn from_bytes(engine: &Engine, bytes: &[u8]) -> anyhow::Result<Self> {
+        let module = Module::new(engine, bytes)?;
+
+        Ok(Self {
+            module,
+            metadata: Arc::new(ModuleMetadata::from_module(bytes)),
+            body_limits: Arc::new(RouteBodyLimits::from_module(bytes)),
+            route_methods: Arc::new(RouteMethods::from_module(bytes)),
+            sha256: Arc::from(sha256_hex(bytes)),
+        })
+    }
+}
+
+/// The size and modification time of the file at `path`, to tell if it changed
+fn file_state(path: &Path) -> Option<(u64, Option<SystemTime>)> {
+    let metadata = std::fs::metadata(path).ok()?;
+
+    Some((metadata.len(), metadata.modified().ok()))
+}
+
+/// Hash `bytes` with SHA-256, as lowercase hex like `sha256sum` prints it
+fn sha256_hex(bytes: &[u8]) -> String {
+    digest::digest(&digest::SHA256, bytes)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+impl SwappableModule {
+    pub(crate) fn new(module: LoadedModule) -> Self {
+        Self {
+            current: RwLock::new(module),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the module new requests should use
+    pub(crate) fn current(&self) -> Module {
+        self.current
+            .read()
+            .expect("module lock should not be poisoned")
+            .module
+            .clone()
+    }
+
+    /// Get the module new requests should use, with how many times it was swapped before
+    pub(crate) fn versioned(&self) -> (Module, u64) {
+        let current = self
+            .current
+            .read()
+            .expect("module lock should not be poisoned");
+
+        (
+            current.module.clone(),
+            self.generation.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Get the route body limits of the module new requests use
+    pub(crate) fn body_limits(&self) -> Arc<RouteBodyLimits> {
+        self.current
+            .read()
+            .expect("module lock should not be poisoned")
+            .body_limits
+            .clone()
+    }
+
+    /// Get the route methods of the module new requests use
+    pub(crate) fn route_methods(&self) -> Arc<RouteMethods> {
+        self.current
+            .read()
+            .expect("module lock should not be poisoned")
+            .route_methods
+            .clone()
+    }
+
+    /// Get the hex SHA-256 of the module new requests use
+    pub(crate) fn sha256(&self) -> Arc<str> {
+        self.current
+            .read()
+            .expect("module lock should not be poisoned")
+            .sha256
+            .clone()
+    }
+
+    /// Get the metadata of the module new requests use
+    pub(crate) fn metadata(&self) -> Arc<ModuleMetadata> {
+        self.current
+            .read()
+            .expect("module lock should not be poisoned")
+            .metadata
+            .clone()
+    }
+
+    fn swap(&self, module: LoadedModule) {
+        let mut current = self
+            .current
+            .write()
+            .expect("module lock should not be poisoned");
+
+        *current = module;
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Compile the module at `path` and make sure it exports the router function
+pub(crate) fn load_module(engine: &Engine, path: &Path) -> anyhow::Result<LoadedModule> {
+    let loaded = LoadedModule::from_file(engine, path)?;
+
+    if loaded.module.get_export("__SHUTTLE_Axum_call").is_none() {
+        bail!("module does not export the router function");
+    }
+
+    Ok(loaded)
+}
+
+/// Reload `module` from `path` whenever the file is modified. Watching stops if the file
+/// cannot be inspected, while a changed file which fails to load keeps the current module.
+pub(crate) async fn watch_source(engine: Engine, path: PathBuf, module: Arc<SwappableModule>) {
+    let mut last_modified = match modified(&path) {
+        Ok(modified) => modified,
+        Err(error) => {
+            warn!(
+                error = %error,
+                path = %path.display(),
+                "failed to watch module source, it will not be reloaded"
+            );
+            return;
+        }
+    };
+
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let modified = match modified(&path) {
+            Ok(modified) => modified,
+            Err(error) => {
+                warn!(
+                    error = %error,
+                    path = %path.display(),
+                    "failed to check module source, it will no longer be reloaded"
+                );
+                return;
+            }
+        };
+
+        if modified == last_modified {
+            continue;
+        }
+
+        last_modified = modified;
+
+        let loaded = tokio::task::spawn_blocking({
+            let engine = engine.clone();
+            let path = path.clone();
+            move || load_module(&engine, &path)
+        })
+        .await;
+
+        match loaded {
+            Ok(Ok(new_module)) => {
+                let sha256 = new_module.sha256.clone();
+                module.swap(new_module);
+                info!(
+                    path = %path.display(),
+                    %sha256,
+                    "reloaded module after its source changed"
+                );
+            }
+            Ok(Err(error)) => warn!(
+                error = %error,
+                path = %path.display(),
+                "changed module source failed to load, keeping the current module"
+            ),
+            Err(error) => warn!(error = %error, "loading changed module source panicked"),
+        }
+    }
+}
+
+fn modified(path: &Path) -> anyhow::Result<SystemTime> {
+    path.metadata()
+        .and_then(|metadata| metadata.modified())
+        .context("failed to read modification time")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_module_requires_router() {
+        let engine = Engine::default();
+        let path = std::env::temp_dir().join(format!("watch-{}.wat", std::process::id()));
+
+        std::fs::write(&path, "(module)").unwrap();
+        assert!(load_module(&engine, &path).is_err());
+
+        std::fs::write(
+            &path,
+            r#"(module (func (export "__SHUTTLE_Axum_call") (param i32 i32 i32)))"#,
+        )
+        .unwrap();
+        assert!(load_module(&engine, &path).is_ok());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn retries_only_transient_failures() {
+        let engine = Engine::default();
+        let path = std::env::temp_dir().join(format!("retry-{}.wat", std::process::id()));
+        let retries = LoadRetries {
+            attempts: 5,
+            delay: Duration::from_millis(50),
+        };
+
+        // The module shows up while the load is being retried
+        let staging = std::thread::spawn({
+            let path = path.clone();
+            move || {
+                std::thread::sleep(Duration::from_millis(100));
+                std::fs::write(path, "(module)").unwrap();
+            }
+        });
+        assert!(LoadedModule::from_file_with_retries(&engine, &path, &retries).is_ok());
+        staging.join().unwrap();
+
+        // An invalid module is not retried
+        std::fs::write(&path, "(module").unwrap();
+        let start = std::time::Instant::now();
+        assert!(LoadedModule::from_file_with_retries(&engine, &path, &retries).is_err());
+        assert!(start.elapsed() < retries.delay);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn sha256_of_the_module_bytes() {
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+
+        let engine = Engine::default();
+        let first = LoadedModule::from_bytes(&engine, b"(module)").unwrap();
+        let second = LoadedModule::from_bytes(&engine, b"(module (memory 1))").unwrap();
+
+        assert_eq!(first.sha256.as_ref(), sha256_hex(b"(module)"));
+        assert_ne!(first.sha256, second.sha256);
+
+        let swappable = SwappableModule::new(first);
+        swappable.swap(second.clone());
+        assert_eq!(swappable.sha256(), second.sha256);
+    }
+}
@@ -0,0 +1,164 @@
+use std::fmt::Write as _;
+use std::io::{self, Read};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tracing::debug;
+
+// At most this many messages are dumped per window, so a busy service does not flood the logs
+const MAX_DUMPS: u32 = 10;
+const DUMP_WINDOW: Duration = Duration::from_secs(60);
+
+// Only the start of larger messages is dumped
+const MAX_DUMP_SIZE: usize = 4096;
+
+/// Dumps the raw messagepack the host and guest exchange as hex at debug level, for debugging
+/// the wire protocol. The dumps contain request headers like cookies, so this is only for
+/// debugging and never for production.
+pub(crate) struct WireTap {
+    window: Mutex<(Instant, u32)>,
+}
+
+impl WireTap {
+    pub(crate) fn new() -> Self {
+        Self {
+            window: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Dump the start of a message of `size` bytes going in `direction`, unless too many were
+    /// dumped recently
+    pub(crate) fn dump(&self, direction: &'static str, bytes: &[u8], size: usize) {
+        if !self.take_turn() {
+            return;
+        }
+
+        debug!(
+            direction,
+            size,
+            hex = %hex_dump(bytes, size),
+            "raw wire message"
+        );
+    }
+
+    fn take_turn(&self) -> bool {
+        let mut window = self
+            .window
+            .lock()
+            .expect("wire tap lock should not be poisoned");
+        let (start, dumped) = &mut *window;
+
+        if start.elapsed() >= DUMP_WINDOW {
+            *start = Instant::now();
+            *dumped = 0;
+        }
+
+        if *dumped >= MAX_DUMPS {
+            return false;
+        }
+
+        *dumped += 1;
+        true
+    }
+}
+
+/// Hex of the start of a message of `size` bytes, cut off after the max dump size
+fn hex_dump(bytes: &[u8], size: usize) -> String {
+    let bytes = &bytes[..bytes.len().min(MAX_DUMP_SIZE)];
+    let mut hex = String::with_capacity(bytes.len() * 3);
+
+    for (index, byte) in bytes.iter().enumerate() {
+        if index > 0 {
+            hex.push(' ');
+        }
+        write!(hex, "{byte:02x}").expect("writing to a string should not fail");
+    }
+
+    if size > bytes.len() {
+        write!(hex, " ... ({} more bytes)", size - bytes.len())
+            .expect("writing to a string should not fail");
+    }
+
+    hex
+}
+
+/// A reader which keeps the start of what is read through it when recording
+pub(crate) struct Recorder<R> {
+    inner: R,
+    recorded: Option<Vec<u8>>,
+    size: usize,
+}
+
+impl<R> Recorder<R> {
+    pub(crate) fn new(inner: R, record: bool) -> Self {
+        Self {
+            inner,
+            recorded: record.then(Vec::new),
+            size: 0,
+        }
+    }
+
+    /// The start of what was read so far, which is empty when not recording
+    pub(crate) fn recorded(&self) -> &[u8] {
+        self.recorded.as_deref().unwrap_or_default()
+    }
+
+    /// How many bytes were read so far
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl<R: Read> Read for Recorder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.size += read;
+
+        if let Some(recorded) = &mut self.recorded {
+            let room = MAX_DUMP_SIZE.saturating_sub(recorded.len());
+            recorded.extend_from_slice(&buf[..read.min(room)]);
+        }
+
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex() {
+        assert_eq!(hex_dump(&[0x82, 0xa6, 0x00], 3), "82 a6 00");
+
+        let dump = hex_dump(&[0; MAX_DUMP_SIZE + 2], MAX_DUMP_SIZE + 2);
+        assert!(dump.ends_with("00 ... (2 more bytes)"), "{dump}");
+    }
+
+    #[test]
+    fn records_what_is_read() {
+        let mut recorder = Recorder::new(&b"\x81\xa6status"[..], true);
+        let mut read = Vec::new();
+        recorder.read_to_end(&mut read).unwrap();
+        assert_eq!(recorder.recorded(), b"\x81\xa6status");
+        assert_eq!(recorder.size(), 8);
+
+        let mut recorder = Recorder::new(&b"\x81\xa6status"[..], false);
+        recorder.read_to_end(&mut read).unwrap();
+        assert!(recorder.recorded().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limited() {
+        let tap = WireTap::new();
+
+        for _ in 0..MAX_DUMPS {
+            assert!(tap.take_turn());
+        }
+        assert!(!tap.take_turn());
+
+        tokio::time::advance(DUMP_WINDOW).await;
+        assert!(tap.take_turn());
+    }
+}
@@ -20,6 +20,9 @@ async fn bind_panic() {
         service_name,
         resources: Default::default(),
         secrets,
+        tags: Default::default(),
+        metadata: Default::default(),
+        features: Default::default(),
     });
 
     runtime_client.load(load_request).await.unwrap();
@@ -69,6 +72,9 @@ async fn bind_panic_owned() {
         service_name,
         resources: Default::default(),
         secrets,
+        tags: Default::default(),
+        metadata: Default::default(),
+        features: Default::default(),
     });
 
     runtime_client.load(load_request).await.unwrap();
@@ -115,6 +121,9 @@ async fn loader_panic() {
         service_name,
         resources: Default::default(),
         secrets,
+        tags: Default::default(),
+        metadata: Default::default(),
+        features: Default::default(),
     });
 
     let load_response = runtime_client.load(load_request).await.unwrap();
@@ -146,6 +155,9 @@ async fn loader_panic_owned() {
         service_name,
         resources: Default::default(),
         secrets,
+        tags: Default::default(),
+        metadata: Default::default(),
+        features: Default::default(),
     });
 
     let load_response = runtime_client.load(load_request).await.unwrap();
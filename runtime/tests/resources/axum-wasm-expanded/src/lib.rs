@@ -6,6 +6,10 @@ use shuttle_next::{
 };
 use tracing::debug;
 
+// Let `/length` take bodies of up to 1 MiB, past the default limit of the host
+#[link_section = "shuttle:body-limits"]
+pub static BODY_LIMITS: [u8; 19] = *br#"{"/length":1048576}"#;
+
 pub fn handle_request(req: shuttle_next::Request<BoxBody>) -> shuttle_next::response::Response {
     shuttle_next::block_on(app(req))
 }
@@ -16,7 +20,14 @@ async fn app(request: shuttle_next::Request<BoxBody>) -> shuttle_next::response:
     let mut router = shuttle_next::Router::new()
         .route("/hello", shuttle_next::routing::get(hello))
         .route("/goodbye", shuttle_next::routing::get(goodbye))
-        .route("/uppercase", shuttle_next::routing::post(uppercase));
+        .route("/uppercase", shuttle_next::routing::post(uppercase))
+        .route("/defer", shuttle_next::routing::get(defer))
+        .route("/method", shuttle_next::routing::any(method))
+        .route("/untyped", shuttle_next::routing::get(untyped))
+        .route("/log", shuttle_next::routing::get(log))
+        .route("/spin", shuttle_next::routing::get(spin))
+        .route("/sized", shuttle_next::routing::get(sized))
+        .route("/length", shuttle_next::routing::post(length));
 
     let response = router.call(request).await.unwrap();
 
@@ -33,6 +44,75 @@ async fn goodbye() -> &'static str {
     "Goodbye, World!"
 }
 
+// Leave the request to the host, which serves its own routes for it
+async fn defer() -> shuttle_next::http::StatusCode {
+    debug!("in defer()");
+    shuttle_next::http::StatusCode::from_u16(599).unwrap()
+}
+
+// Echo the method and body back, for any method
+async fn method(method: shuttle_next::http::Method, body: String) -> String {
+    debug!("in method()");
+    format!("{method} {body}")
+}
+
+// Respond with a body but without a content type
+async fn untyped() -> Response {
+    debug!("in untyped()");
+    Response::new(shuttle_next::body::boxed(shuttle_next::body::Full::from(
+        "untyped",
+    )))
+}
+
+// Log `steps` times, three by default, tagging the logs with `id`, and respond with that id
+async fn log(uri: shuttle_next::http::Uri) -> String {
+    let mut id = String::new();
+    let mut steps = 3;
+
+    for pair in uri.query().unwrap_or_default().split('&') {
+        match pair.split_once('=') {
+            Some(("id", value)) => id = value.to_string(),
+            Some(("steps", value)) => steps = value.parse().unwrap_or(steps),
+            _ => {}
+        }
+    }
+
+    for step in 0..steps {
+        debug!(id = id.as_str(), step, "logging");
+    }
+
+    id
+}
+
+// Never respond, like a stuck request
+async fn spin() -> &'static str {
+    debug!("in spin()");
+    loop {
+        std::hint::spin_loop();
+    }
+}
+
+// Respond with a five byte body, declaring the length in the query as its Content-Length
+async fn sized(uri: shuttle_next::http::Uri) -> impl IntoResponse {
+    debug!("in sized()");
+    let length = uri
+        .query()
+        .and_then(|query| query.strip_prefix("length="))
+        .unwrap_or("5")
+        .to_string();
+
+    ([("content-length", length)], "hello")
+}
+
+// Respond with the length of the body, counted as it is read
+async fn length(body: BodyStream) -> String {
+    debug!("in length()");
+    body.try_fold(0, |length, chunk| async move { Ok(length + chunk.len()) })
+        .await
+        .unwrap_or_default()
+        .to_string()
+}
+
 // Map the bytes of the body stream to uppercase and return the stream directly.
 async fn uppercase(body: BodyStream) -> impl IntoResponse {
     debug!("in uppercase()");